@@ -0,0 +1,74 @@
+use alloy::rpc::types::eth::Log;
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use diesel::{Connection, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use rust_indexer::indexer::decode_transfer;
+use rust_indexer::storage::insert_transfers;
+use rust_indexer::testing::{CHAIN_ID, gen_transfers};
+use rust_indexer::types::TransferEvent;
+use std::hint::black_box;
+
+const ROWS: usize = 1_000;
+const SEED: u64 = 42;
+
+// Synthetic Transfer logs, the same on every run
+fn synthetic_logs(count: usize) -> Vec<Log> {
+    gen_transfers(SEED, count)
+        .into_iter()
+        .map(|(log, _)| log)
+        .collect()
+}
+
+// Synthetic transfers matching `synthetic_logs`
+fn synthetic_transfers(count: usize) -> Vec<TransferEvent> {
+    gen_transfers(SEED, count)
+        .into_iter()
+        .map(|(_, transfer)| transfer)
+        .collect()
+}
+
+// Fresh in-memory database with the schema applied
+fn in_memory_db() -> SqliteConnection {
+    let mut conn = SqliteConnection::establish(":memory:").unwrap();
+    conn.run_pending_migrations(rust_indexer::MIGRATIONS)
+        .unwrap();
+    conn
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let logs = synthetic_logs(ROWS);
+
+    let mut group = c.benchmark_group("decode_transfer");
+    group.throughput(Throughput::Elements(logs.len() as u64));
+    group.bench_function("logs", |b| {
+        b.iter(|| {
+            for log in &logs {
+                black_box(decode_transfer(CHAIN_ID, black_box(log)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let transfers = synthetic_transfers(ROWS);
+
+    let mut group = c.benchmark_group("insert_transfers");
+    group.throughput(Throughput::Elements(transfers.len() as u64));
+    // Same shape as the event loop: all rows of a range in one transaction
+    group.bench_function("rows", |b| {
+        b.iter_batched(
+            in_memory_db,
+            |mut conn| {
+                conn.transaction(|conn| insert_transfers(conn, &transfers))
+                    .unwrap();
+                conn
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_insert);
+criterion_main!(benches);
@@ -0,0 +1,8 @@
+CREATE TABLE failed_ranges (
+    chain_id INTEGER NOT NULL,
+    from_block INTEGER NOT NULL,
+    to_block INTEGER NOT NULL,
+    error TEXT NOT NULL,
+    failed_at INTEGER NOT NULL,
+    PRIMARY KEY (chain_id, from_block, to_block)
+);
@@ -0,0 +1,36 @@
+-- transfers.value was declared NUMERIC, so SQLite converted the decimal strings on insert:
+-- values that fit an i64 became INTEGER, larger ones became a lossy REAL.
+-- Rebuild the table with value as TEXT holding the canonical decimal string.
+-- Rows already stored as REAL have lost precision; they are rewritten as the nearest integer
+-- and should be re-indexed (the `checksum` command can be used to find diverging data).
+CREATE TABLE transfers_new (
+    chain_id INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    tx_hash CHAR(66) NOT NULL,
+    token_address CHAR(42) NOT NULL,
+    from_addr CHAR(42) NOT NULL,
+    to_addr CHAR(42) NOT NULL,
+    value TEXT NOT NULL,
+    log_index INTEGER NOT NULL,
+    PRIMARY KEY (chain_id, tx_hash, log_index)
+);
+
+INSERT INTO transfers_new
+SELECT
+    chain_id,
+    block_number,
+    tx_hash,
+    token_address,
+    from_addr,
+    to_addr,
+    CASE typeof(value) WHEN 'real' THEN printf('%.0f', value) ELSE CAST(value AS TEXT) END,
+    log_index
+FROM transfers;
+
+DROP TABLE transfers;
+ALTER TABLE transfers_new RENAME TO transfers;
+
+CREATE INDEX idx_block  ON transfers(chain_id, block_number);
+CREATE INDEX idx_token  ON transfers(chain_id, token_address);
+CREATE INDEX idx_from   ON transfers(from_addr);
+CREATE INDEX idx_to     ON transfers(to_addr);
@@ -0,0 +1,2 @@
+-- EIP-1559 base fee (wei) of the transfer's block, only filled when ENRICH_BASE_FEE is enabled
+ALTER TABLE transfers ADD COLUMN base_fee INTEGER;
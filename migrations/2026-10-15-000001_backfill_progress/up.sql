@@ -0,0 +1,7 @@
+CREATE TABLE backfill_progress (
+    chain_id INTEGER NOT NULL,
+    from_block INTEGER NOT NULL,
+    to_block INTEGER NOT NULL,
+    last_block INTEGER NOT NULL,
+    PRIMARY KEY (chain_id, from_block, to_block)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE token_metadata (
+    chain_id INTEGER NOT NULL,
+    token_address CHAR(42) NOT NULL,
+    symbol TEXT NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (chain_id, token_address)
+);
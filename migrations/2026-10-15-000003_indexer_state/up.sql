@@ -0,0 +1,8 @@
+CREATE TABLE indexer_state (
+    chain_id INTEGER NOT NULL,
+    last_error TEXT,
+    last_error_at INTEGER,
+    last_error_from_block INTEGER,
+    last_error_to_block INTEGER,
+    PRIMARY KEY (chain_id)
+);
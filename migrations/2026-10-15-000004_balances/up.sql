@@ -0,0 +1,7 @@
+CREATE TABLE balances (
+    chain_id INTEGER NOT NULL,
+    token_address CHAR(42) NOT NULL,
+    address CHAR(42) NOT NULL,
+    balance TEXT NOT NULL,
+    PRIMARY KEY (chain_id, token_address, address)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE events (
+    chain_id INTEGER NOT NULL,
+    block_number INTEGER NOT NULL,
+    tx_hash CHAR(66) NOT NULL,
+    log_index INTEGER NOT NULL,
+    event_name TEXT NOT NULL,
+    json_args TEXT NOT NULL,
+    PRIMARY KEY (chain_id, tx_hash, log_index)
+);
+
+CREATE INDEX idx_events_block ON events(chain_id, block_number);
+CREATE INDEX idx_events_name  ON events(chain_id, event_name);
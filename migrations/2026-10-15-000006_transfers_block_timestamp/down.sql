@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_transfers_timestamp;
+ALTER TABLE transfers DROP COLUMN block_timestamp;
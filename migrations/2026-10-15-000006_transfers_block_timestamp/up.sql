@@ -0,0 +1,4 @@
+-- Unix timestamp of the transfer's block, only filled when ENRICH_TIMESTAMP is enabled
+ALTER TABLE transfers ADD COLUMN block_timestamp INTEGER;
+
+CREATE INDEX idx_transfers_timestamp ON transfers(chain_id, block_timestamp);
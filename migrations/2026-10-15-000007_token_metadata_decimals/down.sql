@@ -0,0 +1 @@
+ALTER TABLE token_metadata DROP COLUMN decimals;
@@ -0,0 +1,2 @@
+-- decimals() of the token, NULL if the getter reverted or returned something unusable
+ALTER TABLE token_metadata ADD COLUMN decimals INTEGER;
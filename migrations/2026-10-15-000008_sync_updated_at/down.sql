@@ -0,0 +1 @@
+ALTER TABLE sync DROP COLUMN updated_at;
@@ -0,0 +1,2 @@
+-- Unix time of the last move of the sync pointer, NULL for rows written before this column
+ALTER TABLE sync ADD COLUMN updated_at INTEGER;
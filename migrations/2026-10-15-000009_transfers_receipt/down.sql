@@ -0,0 +1,2 @@
+ALTER TABLE transfers DROP COLUMN tx_status;
+ALTER TABLE transfers DROP COLUMN gas_used;
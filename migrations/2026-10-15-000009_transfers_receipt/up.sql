@@ -0,0 +1,3 @@
+-- Receipt fields of the transfer's transaction, only filled when ENRICH_RECEIPTS is enabled
+ALTER TABLE transfers ADD COLUMN gas_used INTEGER;
+ALTER TABLE transfers ADD COLUMN tx_status BOOLEAN;
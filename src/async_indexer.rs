@@ -0,0 +1,457 @@
+use crate::indexer::{
+    self, AlloyProvider, CaughtUpLog, IndexerError, LagAlert, LoopOptions, ProviderCapabilities,
+    Result,
+};
+use crate::range::RangeCursor;
+use crate::stats::RangeTiming;
+use crate::storage;
+use crate::types::TransferChange;
+use alloy::primitives::U64;
+use alloy::providers::Provider;
+use alloy::rpc::types::eth::Log;
+use diesel::SqliteConnection;
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+use tracing::{Instrument, error, info, warn};
+
+// Async counterpart of LogsProvider, for embedders already running a tokio runtime
+// The sync provider builds a runtime and blocks on it for every call; these calls run on the
+// caller's runtime instead. Only what the async `event_loop` needs is covered.
+pub trait AsyncLogsProvider {
+    fn latest_block(&self) -> impl Future<Output = Result<u64>> + Send;
+
+    fn chain_id(&self) -> impl Future<Output = Result<u64>> + Send;
+
+    fn logs(
+        &self,
+        start_block: u64,
+        end_block: u64,
+    ) -> impl Future<Output = Result<Vec<Log>>> + Send;
+
+    // Same as LogsProvider::capabilities
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+}
+
+impl AsyncLogsProvider for AlloyProvider {
+    fn capabilities(&self) -> ProviderCapabilities {
+        indexer::LogsProvider::capabilities(self)
+    }
+
+    async fn latest_block(&self) -> Result<u64> {
+        let provider = self.connect()?;
+        let head = provider
+            .client()
+            .request_noparams::<U64>(self.methods.block_number.clone())
+            .await
+            .map_err(|e| indexer::rpc_error("get block number", e))?;
+        Ok(head.to::<u64>())
+    }
+
+    async fn chain_id(&self) -> Result<u64> {
+        let provider = self.connect()?;
+        let chain_id = provider
+            .client()
+            .request_noparams::<U64>(self.methods.chain_id.clone())
+            .await
+            .map_err(|e| indexer::rpc_error("get chain ID", e))?;
+        Ok(chain_id.to::<u64>())
+    }
+
+    async fn logs(&self, start_block: u64, end_block: u64) -> Result<Vec<Log>> {
+        let filter = indexer::transfer_filter(
+            indexer::log_addresses(self.token_address, &self.emitters),
+            start_block,
+            end_block,
+            self.topic_filter,
+        )?;
+        let provider = self.connect()?;
+        self.get_logs(&provider, &filter).await
+    }
+}
+
+// Options of the sync loop that need more than the Transfer logs (or its circuit breaker and
+// commit batching); the async loop refuses them instead of silently ignoring them
+fn unsupported_option(options: &LoopOptions) -> Option<&'static str> {
+    [
+        (options.enrich_base_fee, "ENRICH_BASE_FEE"),
+        (options.enrich_timestamp, "ENRICH_TIMESTAMP"),
+        (options.enrich_receipts, "ENRICH_RECEIPTS"),
+        (options.wrapped_events, "WRAPPED_EVENTS"),
+        (!options.events.is_empty(), "EVENTS_FILE"),
+        (options.audit_receipts_every > 0, "AUDIT_RECEIPTS_EVERY"),
+        (options.block_hash_tip > 0, "BLOCK_HASH_TIP_BLOCKS"),
+        (options.breaker_threshold > 0, "CIRCUIT_BREAKER_THRESHOLD"),
+        (options.commit_batch > 0, "COMMIT_BATCH_BLOCKS"),
+        (options.commit_ranges > 1, "COMMIT_RANGES"),
+    ]
+    .into_iter()
+    .find_map(|(enabled, name)| enabled.then_some(name))
+}
+
+// Run a database operation on the blocking thread pool, so Diesel never stalls the runtime
+async fn blocking<T: Send + 'static>(
+    conn: &Arc<Mutex<SqliteConnection>>,
+    operation: impl FnOnce(&mut SqliteConnection) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let conn = Arc::clone(conn);
+    tokio::task::spawn_blocking(move || {
+        let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+        operation(&mut conn)
+    })
+    .await
+    .map_err(|e| IndexerError::Runtime(std::io::Error::other(e)))?
+}
+
+// Same as the sync retries (exponential backoff, retry budget, early exit on shutdown), awaiting
+// the operation and the backoff instead of blocking on them
+async fn with_retries<T, F>(
+    options: &LoopOptions,
+    what: &str,
+    mut operation: impl FnMut() -> F,
+) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        let result = operation()
+            .await
+            .map_err(|e| match options.retry_budget.on_failure(&e) {
+                Some(exhausted) => exhausted,
+                None => e,
+            });
+        match result {
+            Ok(value) => return Ok(value),
+            Err(e @ IndexerError::RetryBudgetExhausted(_)) => return Err(e),
+            Err(e) if attempt < options.max_retries && !options.shutdown.is_requested() => {
+                let delay = options
+                    .retry_backoff
+                    .saturating_mul(2u32.saturating_pow(attempt));
+                attempt += 1;
+                warn!(
+                    "{} failed (attempt {}/{}): {}, retrying in {:?}",
+                    what,
+                    attempt,
+                    options.max_retries.saturating_add(1),
+                    e,
+                    delay
+                );
+                options.shutdown.sleep_async(delay).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Fetch the transfers of a range, with the filters of the sync loop that need no extra call
+async fn fetch_range(
+    provider: &impl AsyncLogsProvider,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+    options: &LoopOptions,
+) -> Result<Vec<TransferChange>> {
+    let logs = provider.logs(from_block, to_block).await?;
+    let mut transfers = indexer::decode_transfer_logs(chain_id, logs, from_block, to_block)?;
+    indexer::normalize_token_addresses(&mut transfers, &options.token_emitters);
+    if options.skip_zero_value {
+        transfers.retain(|change| !indexer::is_zero_value_transfer(change));
+    }
+    Ok(transfers)
+}
+
+// Fetch, hook and commit one range, moving the cursor past it
+// Returns the number of inserted transfers, None when interrupted by a shutdown
+async fn process_range(
+    conn: &Arc<Mutex<SqliteConnection>>,
+    chain_id: u64,
+    provider: &impl AsyncLogsProvider,
+    options: &LoopOptions,
+    cursor: &mut RangeCursor,
+    (from_block, to_block): (u64, u64),
+) -> Result<Option<usize>> {
+    let what = format!("Processing blocks {}..={}", from_block, to_block);
+    let fetch_started = Instant::now();
+    let result = with_retries(options, &what, || {
+        fetch_range(provider, chain_id, from_block, to_block, options)
+    })
+    .await;
+
+    let transfers = match result {
+        Ok(transfers) => transfers,
+        // Interrupted while retrying: leave the range (and the pointer) for the next run
+        Err(_) if options.shutdown.is_requested() => return Ok(None),
+        Err(e) if options.dead_letter && !matches!(e, IndexerError::RetryBudgetExhausted(_)) => {
+            error!(
+                "Giving up on blocks {}..={} after {} attempts: {}",
+                from_block,
+                to_block,
+                options.max_retries.saturating_add(1),
+                e
+            );
+            let message = e.to_string();
+            blocking(conn, move |conn| {
+                storage::write_transaction(conn, |conn| {
+                    storage::record_failed_range(conn, chain_id, from_block, to_block, &message)?;
+                    storage::record_last_error(conn, chain_id, from_block, to_block, &message)?;
+                    storage::set_last_synced_block(conn, chain_id, to_block)
+                })
+            })
+            .await?;
+            cursor.advance(to_block);
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_synced_block(chain_id, to_block);
+            return Ok(Some(0));
+        }
+        Err(e) => {
+            let e = blocking(conn, move |conn| {
+                indexer::remember_error(conn, chain_id, from_block, to_block, &e);
+                Ok(e)
+            })
+            .await?;
+            return Err(e);
+        }
+    };
+    let fetch = fetch_started.elapsed();
+
+    // Hooks and sinks may block (Kafka, files), so they run on the blocking pool with the commit
+    let insert_started = Instant::now();
+    let hooks = options.transfer_hooks.clone();
+    let sinks = options.transfer_sinks.clone();
+    let write = options.write.clone();
+    let applied = blocking(conn, move |conn| {
+        for hook in &hooks {
+            if let Err(e) = hook.run(&transfers) {
+                indexer::remember_error(conn, chain_id, from_block, to_block, &e);
+                return Err(e);
+            }
+        }
+        let applied = storage::write_transaction(conn, |conn| {
+            let applied = storage::apply_transfer_changes(conn, &transfers, &write)?;
+            storage::set_last_synced_block(conn, chain_id, to_block)?;
+            storage::clear_last_error(conn, chain_id)?;
+            Ok(applied)
+        })?;
+        // Sinks only see committed transfers, see indexer::rewind_after_sink_error
+        for sink in &sinks {
+            if let Err(e) = sink.run(&transfers) {
+                return Err(indexer::rewind_after_sink_error(
+                    conn, chain_id, from_block, to_block, e,
+                ));
+            }
+        }
+        Ok(applied)
+    })
+    .await?;
+    cursor.advance(to_block);
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_synced_block(chain_id, to_block);
+    options.timings.record(RangeTiming {
+        from_block,
+        to_block,
+        fetch,
+        insert: insert_started.elapsed(),
+    });
+
+    info!(
+        "Indexed blocks {}..={} ({} transfers)",
+        from_block, to_block, applied.inserted
+    );
+    if applied.removed > 0 {
+        info!(
+            "Removed {} reorged transfers in blocks {}..={}",
+            applied.removed, from_block, to_block
+        );
+    }
+    Ok(Some(applied.inserted))
+}
+
+// Async variant of indexer::event_loop, for embedders running the indexer on their own tokio
+// runtime: RPC calls are awaited and every Diesel call runs through spawn_blocking, so neither a
+// runtime per call nor a dedicated thread is needed. The connection is shared behind a mutex so
+// the embedder can keep using it between ranges.
+// Supports ranges, confirmations, END_BLOCK, the pinned head, retries and the retry budget,
+// dead-lettering, pause, the lag alert, hooks and the zero-value and emitter filters. Options needing other RPC
+// calls (enrichment, custom and wrapped events, the receipt audit), the circuit breaker and
+// commit batching are rejected: use the sync loop for those.
+pub async fn event_loop(
+    conn: Arc<Mutex<SqliteConnection>>,
+    chain_id: u64,
+    provider: impl AsyncLogsProvider,
+    options: &LoopOptions,
+) -> Result<()> {
+    if let Some(option) = unsupported_option(options) {
+        return Err(IndexerError::Unsupported(format!(
+            "{} is not supported by the async event loop",
+            option
+        )));
+    }
+    let fitted = options.fitted_to(&provider.capabilities());
+    let options = fitted.as_ref().unwrap_or(options);
+
+    let pointer = blocking(&conn, move |conn| {
+        storage::get_last_synced_block(conn, chain_id)
+    })
+    .await?;
+    let mut cursor = RangeCursor::new(pointer, options.range_size, options.confirmations);
+    if let Some(next_block) = cursor.next_block() {
+        info!("Indexing from block {}", next_block);
+    }
+    let started = Instant::now();
+    let mut last_progress = Instant::now();
+    let mut indexed = 0;
+    let mut caught_up = CaughtUpLog::new(options.progress_interval);
+    let mut lag_alert = LagAlert::new(options.lag_alert_blocks);
+    let mut paused = false;
+
+    while !options.shutdown.is_requested() {
+        if let Some(end_block) = options.end_block.filter(|end| cursor.reached(*end)) {
+            info!(
+                "Reached end block {}: {} transfers indexed in {:?}",
+                end_block,
+                indexed,
+                started.elapsed()
+            );
+            return Ok(());
+        }
+
+        if options.pause.is_paused() != paused {
+            paused = !paused;
+            info!("Indexing {}", if paused { "paused" } else { "resumed" });
+        }
+        if paused {
+            options.shutdown.sleep_async(options.poll_interval).await;
+            continue;
+        }
+
+        let head = match with_retries(options, "Fetching latest block", || provider.latest_block())
+            .await
+        {
+            Ok(head) => options.visible_head(head),
+            Err(_) if options.shutdown.is_requested() => break,
+            Err(e) => return Err(e),
+        };
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_head_block(chain_id, head);
+        if let Some(pointer) = cursor.pointer {
+            lag_alert.check(
+                chain_id,
+                pointer,
+                head.saturating_sub(options.confirmations),
+            );
+        }
+
+        let Some((from_block, to_block)) = cursor.next_range(head) else {
+            if let Some(pointer) = cursor.pointer {
+                caught_up.log(pointer, head);
+            }
+            options.shutdown.sleep_async(options.poll_interval).await;
+            continue;
+        };
+        let to_block = options.end_block.map_or(to_block, |end| to_block.min(end));
+
+        let processed = process_range(
+            &conn,
+            chain_id,
+            &provider,
+            options,
+            &mut cursor,
+            (from_block, to_block),
+        )
+        .instrument(indexer::range_span(chain_id, from_block, to_block))
+        .await?;
+        let Some(inserted) = processed else {
+            break;
+        };
+        indexed += inserted;
+
+        if last_progress.elapsed() >= options.progress_interval {
+            last_progress = Instant::now();
+            indexer::log_progress(to_block, head, &options.timings);
+        }
+    }
+
+    info!("Event loop stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{CHAIN_ID, FakeProvider, transfer_log};
+    use alloy::primitives::{Address, U256};
+
+    // The sync fake served through the async trait
+    struct AsyncFake<'a>(&'a FakeProvider);
+
+    impl AsyncLogsProvider for AsyncFake<'_> {
+        async fn latest_block(&self) -> Result<u64> {
+            let mut provider = self.0;
+            indexer::LogsProvider::latest_block(&mut provider)
+        }
+
+        async fn chain_id(&self) -> Result<u64> {
+            let mut provider = self.0;
+            indexer::LogsProvider::chain_id(&mut provider)
+        }
+
+        async fn logs(&self, start_block: u64, end_block: u64) -> Result<Vec<Log>> {
+            Ok(indexer::LogsProvider::logs(self.0, start_block, end_block)?
+                .into_iter()
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn async_loop_indexes_up_to_the_end_block() {
+        let logs = [5, 15, 25]
+            .into_iter()
+            .map(|block| transfer_log(block, 0, Address::ZERO, Address::repeat_byte(1), U256::ONE))
+            .collect();
+        let provider = FakeProvider::new(40, logs);
+        let conn = Arc::new(Mutex::new(crate::testing::in_memory_db()));
+        let options = LoopOptions {
+            range_size: 10,
+            end_block: Some(29),
+            ..LoopOptions::default()
+        };
+        event_loop(Arc::clone(&conn), CHAIN_ID, AsyncFake(&provider), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(provider.requested(), vec![(0, 9), (10, 19), (20, 29)]);
+        let mut conn = conn.lock().unwrap();
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, CHAIN_ID).unwrap(),
+            Some(29)
+        );
+        let blocks: Vec<u64> = storage::transfers_in_range(&mut conn, CHAIN_ID, 0, 40)
+            .unwrap()
+            .iter()
+            .map(|transfer| transfer.block_number)
+            .collect();
+        assert_eq!(blocks, vec![5, 15, 25]);
+    }
+
+    #[tokio::test]
+    async fn async_loop_rejects_options_it_cannot_honor() {
+        let provider = FakeProvider::new(40, Vec::new());
+        let conn = Arc::new(Mutex::new(crate::testing::in_memory_db()));
+        let options = LoopOptions {
+            enrich_receipts: true,
+            ..LoopOptions::default()
+        };
+        let error = event_loop(conn, CHAIN_ID, AsyncFake(&provider), &options)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, IndexerError::Unsupported(message) if message.starts_with("ENRICH_RECEIPTS")),
+            "{}",
+            error
+        );
+        assert!(provider.requested().is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::indexer::{IndexerError, Shutdown};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    // Normal operation
+    Closed,
+    // Too many consecutive failures, the loop is paused for the cooldown
+    Open,
+    // Cooldown over, the next range is a trial: success closes the breaker, failure reopens it
+    HalfOpen,
+}
+
+// Circuit breaker for the event loop: after `threshold` consecutive failed ranges (retries
+// exhausted) it opens and the loop pauses for `cooldown` instead of hammering a down RPC,
+// then lets a single trial range through. A threshold of 0 disables it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    state: BreakerState,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            consecutive_failures: 0,
+            state: BreakerState::Closed,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold > 0
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    // Record a successful range, closing the breaker
+    pub fn on_success(&mut self) {
+        if self.state != BreakerState::Closed {
+            info!(
+                "Circuit breaker closed after {} consecutive failures",
+                self.consecutive_failures
+            );
+        }
+        self.consecutive_failures = 0;
+        self.state = BreakerState::Closed;
+    }
+
+    // Record a failed range; returns true if the breaker opened, in which case the caller must
+    // `pause` before trying again. A failed trial (half-open) reopens it right away.
+    pub fn on_failure(&mut self, error: &IndexerError) -> bool {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        if !self.is_enabled()
+            || (self.state == BreakerState::Closed && self.consecutive_failures < self.threshold)
+        {
+            return false;
+        }
+
+        self.state = BreakerState::Open;
+        error!(
+            "Circuit breaker open after {} consecutive failures, pausing for {:?} (last error: {})",
+            self.consecutive_failures, self.cooldown, error
+        );
+        true
+    }
+
+    // Wait out the cooldown (returning early on shutdown), then let one trial through
+    pub fn pause(&mut self, shutdown: &Shutdown) {
+        shutdown.sleep(self.cooldown);
+        self.state = BreakerState::HalfOpen;
+        info!("Circuit breaker half-open, sending a trial request");
+    }
+}
+
+// Global budget of failed RPC calls: every failed attempt (retried or not, any range, any chain)
+// is counted, and more than `max_failures` within `window` exhausts it. The event loop then stops
+// with an error instead of retrying, dead-lettering or pausing forever, so the process exits
+// non-zero and an orchestrator can restart it or alert. A budget of 0 disables it.
+// Shared between clones, like the adaptive throttle.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    max_failures: u32,
+    window: Duration,
+    failures: Arc<Mutex<VecDeque<Instant>>>, // Times of the failures still within the window
+}
+
+impl RetryBudget {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        RetryBudget {
+            max_failures,
+            window,
+            failures: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Record a failed call; returns the error to stop with once the budget is exhausted
+    pub fn on_failure(&self, error: &IndexerError) -> Option<IndexerError> {
+        if self.max_failures == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        while failures
+            .front()
+            .is_some_and(|failed_at| now.duration_since(*failed_at) > self.window)
+        {
+            failures.pop_front();
+        }
+        failures.push_back(now);
+
+        (failures.len() > self.max_failures as usize).then(|| {
+            IndexerError::RetryBudgetExhausted(format!(
+                "{} RPC failures in the last {:?}, last: {}",
+                failures.len(),
+                self.window,
+                error
+            ))
+        })
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        RetryBudget::new(0, Duration::from_secs(600))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaker_opens_at_the_threshold_and_a_trial_decides() {
+        let error = IndexerError::Rpc("down".to_string());
+        let mut breaker = CircuitBreaker::new(3, Duration::ZERO);
+        assert!(!breaker.on_failure(&error));
+        assert!(!breaker.on_failure(&error));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.on_failure(&error));
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        breaker.pause(&Shutdown::default());
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        // A failed trial reopens it right away
+        assert!(breaker.on_failure(&error));
+        assert_eq!(breaker.consecutive_failures(), 4);
+
+        breaker.pause(&Shutdown::default());
+        breaker.on_success();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.consecutive_failures(), 0);
+        assert!(!breaker.on_failure(&error));
+    }
+
+    #[test]
+    fn disabled_breaker_never_opens() {
+        let error = IndexerError::Rpc("down".to_string());
+        let mut breaker = CircuitBreaker::new(0, Duration::ZERO);
+        assert!(!breaker.is_enabled());
+        assert!((0..10).all(|_| !breaker.on_failure(&error)));
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn retry_budget_is_exhausted_within_its_window_only() {
+        let error = IndexerError::Rpc("down".to_string());
+        let budget = RetryBudget::new(2, Duration::from_millis(50));
+        assert!(budget.on_failure(&error).is_none());
+        // Clones share the count, like the chains of a multi-chain run
+        assert!(budget.clone().on_failure(&error).is_none());
+        let Some(IndexerError::RetryBudgetExhausted(message)) = budget.on_failure(&error) else {
+            panic!("a third failure exhausts the budget");
+        };
+        assert!(message.contains("3 RPC failures"), "{}", message);
+
+        // Older failures leave the window
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(budget.on_failure(&error).is_none());
+        assert!((0..100).all(|_| RetryBudget::default().on_failure(&error).is_none()));
+    }
+}
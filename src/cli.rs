@@ -0,0 +1,441 @@
+use crate::export::{Compression, ExportFormat};
+use alloy_primitives::Address;
+use clap::{Args, Parser, Subcommand};
+use std::io::IsTerminal;
+use tracing::Level;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Index ERC20 Transfer events into SQLite",
+    after_help = "Settings precedence: command-line flag > environment variable > .env file > default"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// More log output: -v for debug, -vv for trace (RUST_LOG takes precedence)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only log warnings and errors (RUST_LOG takes precedence)
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
+    /// Never color the log output (also with a non-empty NO_COLOR)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+impl Cli {
+    // Default log level selected by -v/-vv/-q
+    pub fn log_level(&self) -> Level {
+        match (self.quiet, self.verbose) {
+            (true, _) => Level::WARN,
+            (false, 0) => Level::INFO,
+            (false, 1) => Level::DEBUG,
+            (false, _) => Level::TRACE,
+        }
+    }
+
+    // Whether the log lines get ANSI colors: only on a terminal, unless --no-color or NO_COLOR
+    pub fn log_color(&self) -> bool {
+        crate::log_color(
+            self.no_color,
+            std::env::var("NO_COLOR").ok().as_deref(),
+            std::io::stdout().is_terminal(),
+        )
+    }
+}
+
+// Command-line overrides of the Config fields, None falls back to the environment variable
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+pub struct ConfigArgs {
+    /// RPC endpoint: HTTP URL or IPC socket path [env: RPC_URL]
+    #[arg(long, global = true)]
+    pub rpc_url: Option<String>,
+    /// First block to index on a new database [env: START_BLOCK]
+    #[arg(long, global = true)]
+    pub start_block: Option<u64>,
+    /// Last block to index (inclusive), the indexer exits once it is reached [env: END_BLOCK]
+    #[arg(long, global = true)]
+    pub end_block: Option<u64>,
+    /// Treat this block as the chain head, for runs that must not depend on when they happen [env: PINNED_HEAD]
+    #[arg(long, global = true)]
+    pub pinned_head: Option<u64>,
+    /// SQLite database file [env: DB_PATH]
+    #[arg(long, global = true)]
+    pub db_path: Option<String>,
+    /// Octal permissions of a database file created by the indexer, e.g. 600 [env: DB_FILE_MODE]
+    #[arg(long, global = true)]
+    pub db_file_mode: Option<String>,
+    /// Expected chain id, checked against the RPC [env: CHAIN_ID]
+    #[arg(long, global = true)]
+    pub chain_id: Option<u64>,
+    /// ERC20 token contract to index [env: TOKEN_ADDRESS]
+    #[arg(long, global = true)]
+    pub token_address: Option<Address>,
+    /// Other contracts whose Transfer logs belong to the token (e.g. a proxy's implementation), comma-separated [env: TOKEN_EMITTERS]
+    #[arg(long, global = true)]
+    pub token_emitters: Option<String>,
+    /// Decimals of the token, instead of calling its decimals() getter [env: TOKEN_DECIMALS]
+    #[arg(long, global = true)]
+    pub token_decimals: Option<u8>,
+    /// Fractional digits of human-readable amounts [env: VALUE_PRECISION]
+    #[arg(long, global = true)]
+    pub value_precision: Option<usize>,
+    /// Drop trailing zeros from human-readable amounts [env: VALUE_TRIM_ZEROS]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub value_trim_zeros: Option<bool>,
+    /// Write EIP-55 checksummed addresses (true) or lowercase ones (false) in every output [env: CHECKSUM_ADDRESSES]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub checksum_addresses: Option<bool>,
+    /// User-Agent sent to the RPC [env: RPC_USER_AGENT]
+    #[arg(long, global = true)]
+    pub rpc_user_agent: Option<String>,
+    /// JSON-RPC method returning the head block number [env: RPC_METHOD_BLOCK_NUMBER]
+    #[arg(long, global = true)]
+    pub rpc_method_block_number: Option<String>,
+    /// JSON-RPC method returning the logs of a filter [env: RPC_METHOD_LOGS]
+    #[arg(long, global = true)]
+    pub rpc_method_logs: Option<String>,
+    /// JSON-RPC method returning the chain ID [env: RPC_METHOD_CHAIN_ID]
+    #[arg(long, global = true)]
+    pub rpc_method_chain_id: Option<String>,
+    /// Widest eth_getLogs block range the provider accepts, caps RANGE_SIZE [env: RPC_MAX_BLOCK_RANGE]
+    #[arg(long, global = true)]
+    pub rpc_max_block_range: Option<u64>,
+    /// Accept gzip/deflate compressed RPC responses over HTTP [env: RPC_COMPRESSION]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub rpc_compression: Option<bool>,
+    /// Sent as `Authorization: Bearer <key>`, prefer the env var to keep it out of `ps` [env: RPC_API_KEY]
+    #[arg(long, global = true)]
+    pub rpc_api_key: Option<String>,
+    /// Extra RPC headers, comma-separated `Name: value` pairs [env: RPC_HEADERS]
+    #[arg(long, global = true)]
+    pub rpc_headers: Option<String>,
+    /// Blocks fetched per eth_getLogs call [env: RANGE_SIZE]
+    #[arg(long, global = true)]
+    pub range_size: Option<u64>,
+    /// Wait between polls once caught up, in milliseconds [env: POLL_INTERVAL_MS]
+    #[arg(long, global = true)]
+    pub poll_interval_ms: Option<u64>,
+    /// Reuse the chain head for this long while catching up, in milliseconds [env: HEAD_CACHE_TTL_MS]
+    #[arg(long, global = true)]
+    pub head_cache_ttl_ms: Option<u64>,
+    /// Blocks committed per transaction within a range, 0 commits the whole range [env: COMMIT_BATCH_BLOCKS]
+    #[arg(long, global = true)]
+    pub commit_batch_blocks: Option<u64>,
+    /// Ranges fetched before they are committed in one transaction [env: COMMIT_RANGES]
+    #[arg(long, global = true)]
+    pub commit_ranges: Option<u64>,
+    /// Approximate size of fetched changes that commits a group of ranges early, 0 for no cap [env: MAX_BUFFERED_BYTES]
+    #[arg(long, global = true)]
+    pub max_buffered_bytes: Option<usize>,
+    /// Cross-check every Nth committed range against the block receipts, 0 to disable [env: AUDIT_RECEIPTS_EVERY]
+    #[arg(long, global = true)]
+    pub audit_receipts_every: Option<u64>,
+    /// Fetch ranges ending within N blocks of the head block by block, by hash, 0 to disable [env: BLOCK_HASH_TIP_BLOCKS]
+    #[arg(long, global = true)]
+    pub block_hash_tip_blocks: Option<u64>,
+    /// Retries per range before giving up [env: MAX_RETRIES]
+    #[arg(long, global = true)]
+    pub max_retries: Option<u32>,
+    /// Initial retry delay in milliseconds, doubled after every attempt [env: RETRY_BACKOFF_MS]
+    #[arg(long, global = true)]
+    pub retry_backoff_ms: Option<u64>,
+    /// Record ranges that exhausted their retries and keep going [env: DEAD_LETTER]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub dead_letter: Option<bool>,
+    /// Consecutive failed ranges that pause indexing, 0 to disable [env: CIRCUIT_BREAKER_THRESHOLD]
+    #[arg(long, global = true)]
+    pub circuit_breaker_threshold: Option<u32>,
+    /// How long the circuit breaker pauses indexing, in milliseconds [env: CIRCUIT_BREAKER_COOLDOWN_MS]
+    #[arg(long, global = true)]
+    pub circuit_breaker_cooldown_ms: Option<u64>,
+    /// Failed RPC calls tolerated within the window before the indexer exits, 0 to disable [env: RETRY_BUDGET]
+    #[arg(long, global = true)]
+    pub retry_budget: Option<u32>,
+    /// Window of the retry budget, in seconds [env: RETRY_BUDGET_WINDOW_SECS]
+    #[arg(long, global = true)]
+    pub retry_budget_window_secs: Option<u64>,
+    /// Blocks behind the head left unindexed [env: CONFIRMATIONS]
+    #[arg(long, global = true)]
+    pub confirmations: Option<u64>,
+    /// Per-chain confirmations, e.g. `1:12, 137:128` [env: CHAIN_CONFIRMATIONS]
+    #[arg(long, global = true)]
+    pub chain_confirmations: Option<String>,
+    /// Grace period for the in-flight range on shutdown, in milliseconds [env: SHUTDOWN_TIMEOUT_MS]
+    #[arg(long, global = true)]
+    pub shutdown_timeout_ms: Option<u64>,
+    /// Control file pausing the event loop while it exists [env: PAUSE_FILE]
+    #[arg(long, global = true)]
+    pub pause_file: Option<String>,
+    /// How often progress is logged, in seconds [env: PROGRESS_INTERVAL_SECS]
+    #[arg(long, global = true)]
+    pub progress_interval_secs: Option<u64>,
+    /// Log an error once indexing falls more than N blocks behind the confirmed head, 0 to disable [env: LAG_ALERT_BLOCKS]
+    #[arg(long, global = true)]
+    pub lag_alert_blocks: Option<u64>,
+    /// Log database queries taking at least this long, in milliseconds, 0 to disable [env: SLOW_QUERY_MS]
+    #[arg(long, global = true)]
+    pub slow_query_ms: Option<u64>,
+    /// Pace RPC requests and back off on rate limits [env: ADAPTIVE_THROTTLE]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub adaptive_throttle: Option<bool>,
+    /// Lowest request rate of the adaptive throttle [env: THROTTLE_MIN_RPS]
+    #[arg(long, global = true)]
+    pub throttle_min_rps: Option<f64>,
+    /// Highest request rate of the adaptive throttle [env: THROTTLE_MAX_RPS]
+    #[arg(long, global = true)]
+    pub throttle_max_rps: Option<f64>,
+    /// Store each block's base fee with its transfers (one extra RPC call per block) [env: ENRICH_BASE_FEE]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub enrich_base_fee: Option<bool>,
+    /// Store each block's timestamp with its transfers (one extra RPC call per block) [env: ENRICH_TIMESTAMP]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub enrich_timestamp: Option<bool>,
+    /// Store the gas used and status of each transfer's transaction (receipt fetches) [env: ENRICH_RECEIPTS]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub enrich_receipts: Option<bool>,
+    /// Drop zero-value transfers (common spam) instead of storing them, ERC20 tokens only [env: SKIP_ZERO_VALUE]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub skip_zero_value: Option<bool>,
+    /// Also index the Deposit/Withdrawal events of wrapped tokens (WETH) as mints and burns [env: WRAPPED_EVENTS]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub wrapped_events: Option<bool>,
+    /// Make `backfill` check parent hashes across range boundaries (2 block fetches per range) [env: VERIFY_CONTINUITY]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub verify_continuity: Option<bool>,
+    /// Send the Transfer topic in eth_getLogs filters, false for providers that reject it [env: LOGS_TOPIC_FILTER]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub logs_topic_filter: Option<bool>,
+    /// Don't check on startup that TOKEN_ADDRESS has contract code [env: SKIP_CODE_CHECK]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub skip_code_check: Option<bool>,
+    /// Warn instead of failing when the RPC chain ID differs from CHAIN_ID (forks, anvil) [env: ALLOW_CHAIN_ID_MISMATCH]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub allow_chain_id_mismatch: Option<bool>,
+    /// Store each token's transfers in its own `transfers_<address>` table [env: TABLE_PER_TOKEN]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub table_per_token: Option<bool>,
+    /// Store transfers in one `transfers_blocks_<first block>` table per N blocks, 0 to disable [env: PARTITION_BLOCKS]
+    #[arg(long, global = true)]
+    pub partition_blocks: Option<u64>,
+    /// Prefix of the `sync` and `transfers` table names, e.g. `indexer_` [env: TABLE_PREFIX]
+    #[arg(long, global = true)]
+    pub table_prefix: Option<String>,
+    /// Blocks re-scanned on startup to recover from an unclean shutdown [env: REWIND_BLOCKS]
+    #[arg(long, global = true)]
+    pub rewind_blocks: Option<u64>,
+    /// Most recent blocks whose logs the provider still serves, 0 if unlimited [env: LOG_RETENTION_BLOCKS]
+    #[arg(long, global = true)]
+    pub log_retention_blocks: Option<u64>,
+    /// Jump past blocks older than LOG_RETENTION_BLOCKS, dead-lettering them [env: SKIP_RETENTION_GAP]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub skip_retention_gap: Option<bool>,
+    /// Keep per-address balances in the `balances` table up to date [env: MATERIALIZE_BALANCES]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub materialize_balances: Option<bool>,
+    /// START_BLOCK is at or before the token's deployment (for MATERIALIZE_BALANCES) [env: START_AT_DEPLOYMENT]
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub start_at_deployment: Option<bool>,
+    /// File of custom event signatures to decode, one per line [env: EVENTS_FILE]
+    #[arg(long, global = true)]
+    pub events_file: Option<String>,
+    /// Kafka bootstrap servers, enables publishing (requires the `kafka` feature) [env: KAFKA_BROKERS]
+    #[arg(long, global = true)]
+    pub kafka_brokers: Option<String>,
+    /// Kafka topic transfers are published to [env: KAFKA_TOPIC]
+    #[arg(long, global = true)]
+    pub kafka_topic: Option<String>,
+    /// How long the producer retries a delivery, in milliseconds [env: KAFKA_DELIVERY_TIMEOUT_MS]
+    #[arg(long, global = true)]
+    pub kafka_delivery_timeout_ms: Option<u64>,
+    /// Also append every indexed transfer to this JSONL file [env: JSONL_PATH]
+    #[arg(long, global = true)]
+    pub jsonl_path: Option<String>,
+    /// Size at which the JSONL file is rotated, in bytes, 0 disables rotation [env: JSONL_MAX_BYTES]
+    #[arg(long, global = true)]
+    pub jsonl_max_bytes: Option<u64>,
+    /// Serve Prometheus metrics on this address (requires the `metrics` feature) [env: METRICS_ADDR]
+    #[arg(long, global = true)]
+    pub metrics_addr: Option<std::net::SocketAddr>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Run the indexer (default when no command is given)
+    Run,
+    /// Re-attempt the block ranges recorded in `failed_ranges`
+    RetryFailed,
+    /// Index a fixed block range without moving the live sync pointer (resumable)
+    Backfill {
+        /// First block of the range (inclusive)
+        #[arg(long, alias = "from")]
+        from_block: u64,
+        /// Last block of the range (inclusive)
+        #[arg(long, alias = "to")]
+        to_block: u64,
+        /// Only index every Nth range, for a quick preview (the others are recorded as skipped)
+        #[arg(long, value_name = "N")]
+        sample_every: Option<u64>,
+        /// Print the blocks, ranges and requests left (and the time span they cover) and exit
+        #[arg(long)]
+        estimate: bool,
+    },
+    /// Index only the given blocks (e.g. snapshot heights) without moving the sync pointer
+    IndexBlocks {
+        /// Block numbers, comma-separated
+        #[arg(value_delimiter = ',')]
+        blocks: Vec<u64>,
+        /// File listing block numbers (separated by commas or whitespace, `#` comments)
+        #[arg(long)]
+        file: Option<std::path::PathBuf>,
+    },
+    /// Print new transfers to stdout as they are mined, without writing to the database
+    Tail {
+        /// Also print transfers within the confirmation window (may be reorged)
+        #[arg(long)]
+        unconfirmed: bool,
+    },
+    /// Check that the RPC doesn't drop logs from range queries, by comparing them with per-block queries
+    ValidateRpc {
+        /// First block compared (default: 100 blocks before --to-block)
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Last block compared (default: the last confirmed block)
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// Recompute the `balances` table from the stored transfers
+    RebuildBalances,
+    /// Print the sync pointer, dead-lettered ranges and last error of the chain
+    Status,
+    /// Print the lowest and highest block with stored transfers of every chain (read-only)
+    Range,
+    /// Write the indexed transfers as CSV or JSON lines (read-only)
+    Export {
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// Output file (stdout if omitted), `.gz` is appended with `--compress gzip`
+        #[arg(long, short)]
+        output: Option<std::path::PathBuf>,
+        /// Compress the output
+        #[arg(long, value_enum, default_value_t)]
+        compress: Compression,
+        /// Only include transfers up to this block (inclusive)
+        #[arg(long)]
+        to_block: Option<u64>,
+        /// Add the human-readable amount (`value_display`) next to the raw value
+        #[arg(long)]
+        display_values: bool,
+    },
+    /// Insert the transfers of an `export` file, skipping the rows already stored
+    Import {
+        /// CSV or JSONL file, optionally gzipped (`.gz`)
+        file: std::path::PathBuf,
+        /// File format (default: `jsonl` for `.jsonl` files, `csv` otherwise)
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Move the sync pointer to the highest imported block if it is behind
+        #[arg(long)]
+        set_sync_pointer: bool,
+    },
+    /// Print a deterministic checksum of the indexed transfers, to compare two instances
+    Checksum {
+        /// Only include transfers up to this block (inclusive)
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// VACUUM the database so the space of deleted rows is given back to the filesystem
+    Compact {
+        /// Run even if an indexer holds the writer lock of the database
+        #[arg(long)]
+        force: bool,
+    },
+    /// List the transfers found in only one of this database and another (database or JSONL export)
+    Diff {
+        /// Database or `.jsonl` export to compare with
+        other: String,
+        /// First block compared (inclusive)
+        #[arg(long, default_value_t = 0)]
+        from_block: u64,
+        /// Last block compared (inclusive), every block if omitted
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+    /// Print the resolved configuration in .env format (secrets redacted) and exit
+    PrintConfig,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(std::iter::once("rust-indexer").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn config_flags_parse_before_and_after_the_command() {
+        let cli = parse(&[
+            "--rpc-url",
+            "http://localhost:8545",
+            "checksum",
+            "--to-block",
+            "20",
+            "--start-block",
+            "5",
+            "--chain-id",
+            "137",
+            "--db-path",
+            "/tmp/transfers.db",
+            "--token-address",
+            "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        ]);
+
+        assert_eq!(
+            cli.config,
+            ConfigArgs {
+                rpc_url: Some("http://localhost:8545".to_string()),
+                start_block: Some(5),
+                chain_id: Some(137),
+                db_path: Some("/tmp/transfers.db".to_string()),
+                token_address: Some(Address::repeat_byte(0xaa)),
+                ..ConfigArgs::default()
+            }
+        );
+        assert!(matches!(
+            cli.command,
+            Some(Command::Checksum { to_block: Some(20) })
+        ));
+    }
+
+    #[test]
+    fn no_flags_leave_every_setting_to_the_environment() {
+        let cli = parse(&[]);
+        assert_eq!(cli.config, ConfigArgs::default());
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn invalid_flag_values_are_rejected() {
+        let error = Cli::try_parse_from(["rust-indexer", "--start-block", "soon"]).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        assert!(Cli::try_parse_from(["rust-indexer", "--token-address", "0x12"]).is_err());
+    }
+
+    #[test]
+    fn verbosity_flags_select_the_log_level() {
+        assert_eq!(parse(&[]).log_level(), Level::INFO);
+        assert_eq!(parse(&["-v"]).log_level(), Level::DEBUG);
+        assert_eq!(parse(&["run", "-vv"]).log_level(), Level::TRACE);
+        assert_eq!(parse(&["-q"]).log_level(), Level::WARN);
+        assert!(Cli::try_parse_from(["rust-indexer", "-q", "-v"]).is_err());
+    }
+}
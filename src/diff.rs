@@ -0,0 +1,200 @@
+use crate::export::parse_transfer_json;
+use crate::storage::{Checksum, ReadOnlyStore};
+use crate::types::TransferEvent;
+use std::collections::HashSet;
+
+// Ranges at most this many blocks wide are compared row by row instead of being split further
+const LEAF_BLOCKS: u64 = 1_000;
+
+// One side of a `diff`: an indexer database (opened read-only) or a JSONL export of one
+pub enum DiffSource {
+    Database(ReadOnlyStore),
+    // Rows of the export in canonical (block_number, log_index) order
+    Export(Vec<TransferEvent>),
+}
+
+impl DiffSource {
+    // A `.jsonl` file is read as an export, anything else is opened as a database whose `sync`
+    // and `transfers` tables have `table_prefix` (TABLE_PREFIX)
+    pub fn open(path: &str, table_prefix: &str) -> anyhow::Result<Self> {
+        if !path.ends_with(".jsonl") {
+            return Ok(DiffSource::Database(ReadOnlyStore::open_with_prefix(
+                path,
+                table_prefix,
+            )?));
+        }
+
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+        let mut rows = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                parse_transfer_json(line)
+                    .map_err(|e| anyhow::anyhow!("{} line {}: {}", path, number + 1, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        rows.sort_by_key(|event| (event.block_number, event.log_index));
+        Ok(DiffSource::Export(rows))
+    }
+
+    fn checksum(
+        &mut self,
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+    ) -> anyhow::Result<Checksum> {
+        match self {
+            DiffSource::Database(store) => {
+                Ok(store.range_checksum(chain_id, from_block, to_block)?)
+            }
+            DiffSource::Export(rows) => {
+                let mut checksum = Checksum::default();
+                for event in export_range(rows, chain_id, from_block, to_block) {
+                    checksum.add(event);
+                }
+                Ok(checksum)
+            }
+        }
+    }
+
+    fn transfers(
+        &mut self,
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+    ) -> anyhow::Result<Vec<TransferEvent>> {
+        match self {
+            DiffSource::Database(store) => {
+                Ok(store.transfers_in_range(chain_id, from_block, to_block)?)
+            }
+            DiffSource::Export(rows) => Ok(export_range(rows, chain_id, from_block, to_block)
+                .cloned()
+                .collect()),
+        }
+    }
+}
+
+// Rows of a chain within [from_block, to_block] of a sorted export
+fn export_range(
+    rows: &[TransferEvent],
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> impl Iterator<Item = &TransferEvent> {
+    let start = rows.partition_point(|event| event.block_number < from_block);
+    let end = rows.partition_point(|event| event.block_number <= to_block);
+    rows[start..end.max(start)]
+        .iter()
+        .filter(move |event| event.chain_id == chain_id)
+}
+
+// Rows found on only one side, in canonical order
+// A row stored differently on both sides (e.g. another value) shows up in both lists
+#[derive(Debug, Default)]
+pub struct TransferDiff {
+    pub only_left: Vec<TransferEvent>,
+    pub only_right: Vec<TransferEvent>,
+}
+
+impl TransferDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty()
+    }
+}
+
+// Compare the transfers of a chain in [from_block, to_block] on both sides
+// Ranges whose checksums (see Checksum::add) match are skipped; the others are split in half
+// until they are LEAF_BLOCKS wide and their rows are compared, so identical databases cost one
+// checksum per side and a divergence is narrowed down without loading every row.
+pub fn diff_transfers(
+    left: &mut DiffSource,
+    right: &mut DiffSource,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> anyhow::Result<TransferDiff> {
+    let mut diff = TransferDiff::default();
+    // Depth-first, lower half first, so the rows come out in block order
+    let mut pending = vec![(from_block, to_block)];
+    while let Some((start, end)) = pending.pop() {
+        if start > end
+            || left.checksum(chain_id, start, end)? == right.checksum(chain_id, start, end)?
+        {
+            continue;
+        }
+        if end - start < LEAF_BLOCKS {
+            let left_rows = left.transfers(chain_id, start, end)?;
+            let right_rows = right.transfers(chain_id, start, end)?;
+            diff.only_left.extend(missing_from(&left_rows, &right_rows));
+            diff.only_right
+                .extend(missing_from(&right_rows, &left_rows));
+            continue;
+        }
+        let middle = start + (end - start) / 2;
+        pending.push((middle + 1, end));
+        pending.push((start, middle));
+    }
+
+    Ok(diff)
+}
+
+// Rows of `rows` with no identical row (same canonical bytes) in `other`
+fn missing_from(rows: &[TransferEvent], other: &[TransferEvent]) -> Vec<TransferEvent> {
+    let other: HashSet<Vec<u8>> = other.iter().map(TransferEvent::canonical_bytes).collect();
+    rows.iter()
+        .filter(|event| !other.contains(&event.canonical_bytes()))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::insert_transfers;
+    use crate::testing::{CHAIN_ID, gen_transfers, open_db};
+    use alloy_primitives::B256;
+
+    fn keys(rows: &[TransferEvent]) -> Vec<(B256, u64)> {
+        rows.iter()
+            .map(|transfer| (transfer.tx_hash, transfer.log_index))
+            .collect()
+    }
+
+    #[test]
+    fn databases_differing_by_one_row_report_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        let transfers: Vec<TransferEvent> = gen_transfers(7, 500)
+            .into_iter()
+            .map(|(_, transfer)| transfer)
+            .collect();
+        // A row far enough in to need a few splits of the block range to be found
+        let missing = transfers[300].clone();
+        let rest: Vec<TransferEvent> = transfers
+            .iter()
+            .filter(|transfer| transfer.canonical_bytes() != missing.canonical_bytes())
+            .cloned()
+            .collect();
+        insert_transfers(&mut open_db(&path("left.db")), &transfers).unwrap();
+        insert_transfers(&mut open_db(&path("right.db")), &rest).unwrap();
+
+        let mut left = DiffSource::open(&path("left.db"), "").unwrap();
+        let mut right = DiffSource::open(&path("right.db"), "").unwrap();
+        let to_block = transfers.last().unwrap().block_number + 5_000;
+        let diff = diff_transfers(&mut left, &mut right, CHAIN_ID, 0, to_block).unwrap();
+        assert_eq!(keys(&diff.only_left), keys(std::slice::from_ref(&missing)));
+        assert!(diff.only_right.is_empty());
+
+        // Reversed, and against an export of the complete data
+        let diff = diff_transfers(&mut right, &mut left, CHAIN_ID, 0, to_block).unwrap();
+        assert_eq!(keys(&diff.only_right), keys(&[missing]));
+        let mut export = DiffSource::Export(transfers);
+        assert!(
+            diff_transfers(&mut left, &mut export, CHAIN_ID, 0, to_block)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
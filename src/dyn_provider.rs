@@ -0,0 +1,201 @@
+use crate::indexer::{LogQuery, LogsProvider, ProviderCapabilities, Result};
+use crate::types::{BlockInfo, ReceiptInfo};
+use alloy::primitives::{Address, B256, Bytes};
+use alloy::rpc::types::eth::Log;
+
+// Object-safe twin of LogsProvider, so the provider can be picked at runtime (HTTP, IPC, an
+// embedder's own) and passed around as a BoxedProvider, which is itself a LogsProvider
+// Implemented for every LogsProvider. A trait object can't return `impl IntoIterator`, so
+// `logs` collects the provider's logs into a Vec.
+pub trait DynLogsProvider: Send {
+    fn latest_block(&mut self) -> Result<u64>;
+
+    fn chain_id(&mut self) -> Result<u64>;
+
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<Vec<Log>>;
+
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>>;
+
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo>;
+
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo>;
+
+    fn transaction_receipts(&self, tx_hashes: &[B256]) -> Result<Vec<ReceiptInfo>>;
+
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes>;
+
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>>;
+
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>>;
+
+    fn block_hash_logs(&self, block_hash: B256) -> Result<Vec<Log>>;
+
+    fn capabilities(&self) -> ProviderCapabilities;
+
+    fn code(&self, address: Address) -> Result<Bytes>;
+}
+
+// A provider chosen at runtime, usable wherever a LogsProvider is expected
+pub type BoxedProvider = Box<dyn DynLogsProvider>;
+
+impl<P: LogsProvider + Send> DynLogsProvider for P {
+    fn latest_block(&mut self) -> Result<u64> {
+        LogsProvider::latest_block(self)
+    }
+
+    fn chain_id(&mut self) -> Result<u64> {
+        LogsProvider::chain_id(self)
+    }
+
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<Vec<Log>> {
+        Ok(LogsProvider::logs(self, start_block, end_block)?
+            .into_iter()
+            .collect())
+    }
+
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>> {
+        LogsProvider::batch_logs(self, queries)
+    }
+
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo> {
+        LogsProvider::block_info(self, block_number)
+    }
+
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo> {
+        LogsProvider::transaction_receipt(self, tx_hash)
+    }
+
+    fn transaction_receipts(&self, tx_hashes: &[B256]) -> Result<Vec<ReceiptInfo>> {
+        LogsProvider::transaction_receipts(self, tx_hashes)
+    }
+
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        LogsProvider::eth_call(self, to, data)
+    }
+
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>> {
+        LogsProvider::event_logs(self, start_block, end_block, selectors)
+    }
+
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>> {
+        LogsProvider::block_receipt_logs(self, block_number)
+    }
+
+    fn block_hash_logs(&self, block_hash: B256) -> Result<Vec<Log>> {
+        LogsProvider::block_hash_logs(self, block_hash)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        LogsProvider::capabilities(self)
+    }
+
+    fn code(&self, address: Address) -> Result<Bytes> {
+        LogsProvider::code(self, address)
+    }
+}
+
+impl LogsProvider for BoxedProvider {
+    fn latest_block(&mut self) -> Result<u64> {
+        DynLogsProvider::latest_block(&mut **self)
+    }
+
+    fn chain_id(&mut self) -> Result<u64> {
+        DynLogsProvider::chain_id(&mut **self)
+    }
+
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<impl IntoIterator<Item = Log>> {
+        DynLogsProvider::logs(&**self, start_block, end_block)
+    }
+
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>> {
+        DynLogsProvider::batch_logs(&**self, queries)
+    }
+
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo> {
+        DynLogsProvider::block_info(&**self, block_number)
+    }
+
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo> {
+        DynLogsProvider::transaction_receipt(&**self, tx_hash)
+    }
+
+    fn transaction_receipts(&self, tx_hashes: &[B256]) -> Result<Vec<ReceiptInfo>> {
+        DynLogsProvider::transaction_receipts(&**self, tx_hashes)
+    }
+
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        DynLogsProvider::eth_call(&**self, to, data)
+    }
+
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>> {
+        DynLogsProvider::event_logs(&**self, start_block, end_block, selectors)
+    }
+
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>> {
+        DynLogsProvider::block_receipt_logs(&**self, block_number)
+    }
+
+    fn block_hash_logs(&self, block_hash: B256) -> Result<Vec<Log>> {
+        DynLogsProvider::block_hash_logs(&**self, block_hash)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        DynLogsProvider::capabilities(&**self)
+    }
+
+    fn code(&self, address: Address) -> Result<Bytes> {
+        DynLogsProvider::code(&**self, address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::{LoopOptions, event_loop};
+    use crate::storage;
+    use crate::testing::{CHAIN_ID, FakeProvider, transfer_log};
+    use alloy::primitives::U256;
+
+    // Stand-in for the config-driven choice in `run`
+    fn select(archive: bool) -> BoxedProvider {
+        let block = if archive { 5 } else { 15 };
+        let logs = vec![transfer_log(
+            block,
+            0,
+            Address::ZERO,
+            Address::repeat_byte(1),
+            U256::ONE,
+        )];
+        let mut provider = FakeProvider::new(30, logs);
+        provider.capabilities.max_block_range = archive.then_some(5);
+        Box::new(provider)
+    }
+
+    #[test]
+    fn boxed_provider_drives_the_event_loop() {
+        let mut provider = select(true);
+        assert_eq!(LogsProvider::chain_id(&mut provider).unwrap(), CHAIN_ID);
+        assert_eq!(LogsProvider::latest_block(&mut provider).unwrap(), 30);
+        assert_eq!(
+            LogsProvider::capabilities(&provider).max_block_range,
+            Some(5)
+        );
+
+        for (archive, block) in [(true, 5), (false, 15)] {
+            let mut conn = crate::testing::in_memory_db();
+            let options = LoopOptions {
+                end_block: Some(19),
+                ..LoopOptions::default()
+            };
+            event_loop(&mut conn, CHAIN_ID, select(archive), &options).unwrap();
+
+            let stored = storage::transfers_in_range(&mut conn, CHAIN_ID, 0, 19).unwrap();
+            assert_eq!(stored.len(), 1);
+            assert_eq!(stored[0].block_number, block);
+            assert_eq!(
+                storage::get_last_synced_block(&mut conn, CHAIN_ID).unwrap(),
+                Some(19)
+            );
+        }
+    }
+}
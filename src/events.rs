@@ -0,0 +1,260 @@
+use crate::indexer::{IndexerError, Result};
+use alloy::dyn_abi::{DynSolEvent, DynSolValue, Specifier};
+use alloy::json_abi::Event;
+use alloy::primitives::{B256, LogData};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+// A custom event to index, declared by its human-readable ABI fragment, e.g.
+// `event Approval(address indexed owner, address indexed spender, uint256 value)`
+// The `indexed` keywords give the field layout: which parameters are topics and which are
+// ABI-encoded in the log data.
+#[derive(Debug, Clone)]
+pub struct EventSpec {
+    pub abi: Event,
+    decoder: DynSolEvent,
+}
+
+// Parameters of a decoded log, in declaration order
+// Unnamed parameters are called `arg<position>` (e.g. `arg2`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedEvent {
+    pub name: String,
+    pub params: Vec<(String, DynSolValue)>,
+}
+
+impl EventSpec {
+    pub fn parse(signature: &str) -> anyhow::Result<Self> {
+        let abi = Event::parse(signature.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid event signature '{}': {}", signature, e))?;
+        // Logs are matched on topic0, which anonymous events don't emit
+        if abi.anonymous {
+            return Err(anyhow::anyhow!(
+                "Anonymous event '{}' can't be matched by its topic",
+                abi.signature()
+            ));
+        }
+        let decoder = abi
+            .resolve()
+            .map_err(|e| anyhow::anyhow!("Unsupported event '{}': {}", abi.signature(), e))?;
+        Ok(EventSpec { abi, decoder })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.abi.name
+    }
+
+    // topic0 of the event: keccak256 of its canonical signature
+    pub fn selector(&self) -> B256 {
+        self.abi.selector()
+    }
+
+    pub fn decode(&self, log: &LogData) -> Result<DecodedEvent> {
+        let decoded = self.decoder.decode_log_data(log).map_err(|e| {
+            IndexerError::Parse(format!("Failed to decode {} log: {}", self.name(), e))
+        })?;
+
+        // The decoder splits topics and data, put them back in declaration order
+        let mut indexed = decoded.indexed.into_iter();
+        let mut body = decoded.body.into_iter();
+        let params = self
+            .abi
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(position, input)| {
+                let value = if input.indexed {
+                    indexed.next()
+                } else {
+                    body.next()
+                };
+                let name = if input.name.is_empty() {
+                    format!("arg{}", position)
+                } else {
+                    input.name.clone()
+                };
+                value.map(|value| (name, value)).ok_or_else(|| {
+                    IndexerError::Parse(format!("{} log is missing parameters", self.name()))
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(DecodedEvent {
+            name: self.name().to_string(),
+            params,
+        })
+    }
+}
+
+impl DecodedEvent {
+    // JSON object of the parameters, keyed by name in declaration order (the `json_args` column)
+    pub fn json_args(&self) -> String {
+        let args: Map<String, Value> = self
+            .params
+            .iter()
+            .map(|(name, value)| (name.clone(), json_value(value)))
+            .collect();
+        Value::Object(args).to_string()
+    }
+}
+
+// JSON encoding of a decoded value: integers as decimal strings (they don't fit a JSON number),
+// addresses and bytes as 0x-prefixed lowercase hex, arrays and tuples as arrays
+fn json_value(value: &DynSolValue) -> Value {
+    match value {
+        DynSolValue::Bool(b) => Value::Bool(*b),
+        DynSolValue::Int(i, _) => Value::String(i.to_string()),
+        DynSolValue::Uint(u, _) => Value::String(u.to_string()),
+        DynSolValue::FixedBytes(word, size) => {
+            Value::String(format!("0x{}", hex::encode(&word[..*size])))
+        }
+        DynSolValue::Address(address) => Value::String(format!("{:#x}", address)),
+        DynSolValue::Function(function) => Value::String(format!("{:#x}", function)),
+        DynSolValue::Bytes(bytes) => Value::String(format!("0x{}", hex::encode(bytes))),
+        DynSolValue::String(s) => Value::String(s.clone()),
+        // Arrays, fixed arrays and tuples
+        sequence => Value::Array(
+            sequence
+                .as_array()
+                .or_else(|| sequence.as_fixed_seq())
+                .or_else(|| sequence.as_tuple())
+                .unwrap_or_default()
+                .iter()
+                .map(json_value)
+                .collect(),
+        ),
+    }
+}
+
+// Read the event signatures of EVENTS_FILE: one per line, blank lines and `#` comments skipped
+pub fn load_event_specs(path: &str) -> anyhow::Result<Vec<EventSpec>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read EVENTS_FILE {}: {}", path, e))?;
+    parse_event_specs(&contents).map_err(|e| anyhow::anyhow!("{}: {}", path, e))
+}
+
+pub fn parse_event_specs(contents: &str) -> anyhow::Result<Vec<EventSpec>> {
+    let mut selectors = HashSet::new();
+    let mut specs = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let spec =
+            EventSpec::parse(line).map_err(|e| anyhow::anyhow!("line {}: {}", number + 1, e))?;
+        // Two declarations with the same canonical signature would both claim every log
+        if !selectors.insert(spec.selector()) {
+            return Err(anyhow::anyhow!(
+                "line {}: event {} is declared twice",
+                number + 1,
+                spec.abi.signature()
+            ));
+        }
+        specs.push(spec);
+    }
+    Ok(specs)
+}
+
+// The spec whose selector matches the log's topic0, if any
+pub fn find_spec<'a>(specs: &'a [EventSpec], log: &LogData) -> Option<&'a EventSpec> {
+    let topic0 = log.topics().first()?;
+    specs.iter().find(|spec| spec.selector() == *topic0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::{Address, Bytes, U256};
+
+    const APPROVAL: &str =
+        "event Approval(address indexed owner, address indexed spender, uint256 value)";
+
+    fn approval_log(owner: Address, spender: Address, value: U256) -> LogData {
+        let spec = EventSpec::parse(APPROVAL).unwrap();
+        LogData::new_unchecked(
+            vec![spec.selector(), owner.into_word(), spender.into_word()],
+            Bytes::from(value.to_be_bytes::<32>().to_vec()),
+        )
+    }
+
+    #[test]
+    fn custom_event_is_decoded_from_its_abi() {
+        let spec = EventSpec::parse(APPROVAL).unwrap();
+        assert_eq!(spec.name(), "Approval");
+        assert_eq!(
+            spec.selector(),
+            alloy::primitives::keccak256("Approval(address,address,uint256)")
+        );
+
+        let (owner, spender) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let decoded = spec
+            .decode(&approval_log(owner, spender, U256::from(500)))
+            .unwrap();
+        assert_eq!(
+            decoded,
+            DecodedEvent {
+                name: "Approval".to_string(),
+                params: vec![
+                    ("owner".to_string(), DynSolValue::Address(owner)),
+                    ("spender".to_string(), DynSolValue::Address(spender)),
+                    ("value".to_string(), DynSolValue::Uint(U256::from(500), 256)),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn unnamed_parameters_keep_their_position_and_indexed_layout() {
+        // The indexed parameter comes second, between two data parameters
+        let spec = EventSpec::parse("event Ping(uint64, address indexed, bool)").unwrap();
+        let mut data = U256::from(7).to_be_bytes::<32>().to_vec();
+        data.extend(U256::ONE.to_be_bytes::<32>());
+        let log = LogData::new_unchecked(
+            vec![spec.selector(), Address::repeat_byte(3).into_word()],
+            Bytes::from(data),
+        );
+
+        let decoded = spec.decode(&log).unwrap();
+        let names: Vec<&str> = decoded
+            .params
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, vec!["arg0", "arg1", "arg2"]);
+        assert_eq!(
+            decoded.params[1].1,
+            DynSolValue::Address(Address::repeat_byte(3))
+        );
+        assert_eq!(decoded.params[2].1, DynSolValue::Bool(true));
+
+        // Missing topics are a parse error, not a panic
+        let truncated = LogData::new_unchecked(vec![spec.selector()], log.data.clone());
+        assert!(matches!(
+            spec.decode(&truncated),
+            Err(IndexerError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn events_file_skips_comments_and_rejects_bad_declarations() {
+        let specs = parse_event_specs(&format!(
+            "# approvals\n{}\n\n  event Sync(uint112 reserve0, uint112 reserve1)  \n",
+            APPROVAL
+        ))
+        .unwrap();
+        let names: Vec<&str> = specs.iter().map(EventSpec::name).collect();
+        assert_eq!(names, vec!["Approval", "Sync"]);
+
+        let log = approval_log(Address::ZERO, Address::ZERO, U256::ZERO);
+        assert_eq!(
+            find_spec(&specs, &log).map(EventSpec::name),
+            Some("Approval")
+        );
+
+        let error = parse_event_specs(&format!("{}\n{}", APPROVAL, APPROVAL)).unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+        assert!(parse_event_specs("event Anon(uint256 a) anonymous").is_err());
+        assert!(parse_event_specs("event Broken(uint257 a)").is_err());
+    }
+}
@@ -0,0 +1,387 @@
+use crate::storage::{ReadOnlyStore, value_from_storage};
+use crate::types::{AddressFormat, TransferEvent};
+use crate::units::ValueFormat;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Transfers read from the database per query, so an export never holds the whole table
+const PAGE_SIZE: usize = 10_000;
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// Header line, then one comma-separated row per transfer
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Plain output
+    #[default]
+    None,
+    /// gzip-compressed output (`.gz`)
+    Gzip,
+}
+
+impl Compression {
+    // Output file name for this compression: `.gz` is appended for gzip unless already present
+    pub fn file_name(self, path: PathBuf) -> PathBuf {
+        match self {
+            Compression::Gzip if path.extension().is_none_or(|ext| ext != "gz") => {
+                let mut name = path.into_os_string();
+                name.push(".gz");
+                name.into()
+            }
+            _ => path,
+        }
+    }
+}
+
+// JSON encoding of a transfer: hex strings for hashes and addresses, the value as a decimal
+// string (it doesn't fit a JSON number), null for enrichment that wasn't fetched. No field needs
+// escaping.
+pub fn transfer_json(event: &TransferEvent, addresses: AddressFormat) -> String {
+    let or_null = |field: Option<String>| field.unwrap_or_else(|| "null".to_string());
+    format!(
+        concat!(
+            r#"{{"chain_id":{},"block_number":{},"tx_hash":"{:#x}","log_index":{},"#,
+            r#""token_address":"{}","from":"{}","to":"{}","value":"{}","base_fee":{},"#,
+            r#""block_timestamp":{},"gas_used":{},"tx_status":{}}}"#
+        ),
+        event.chain_id,
+        event.block_number,
+        event.tx_hash,
+        event.log_index,
+        addresses.render(event.token_address),
+        addresses.render(event.from_addr),
+        addresses.render(event.to_addr),
+        event.value,
+        or_null(event.base_fee.map(|fee| fee.to_string())),
+        or_null(event.block_timestamp.map(|ts| ts.to_string())),
+        or_null(event.gas_used.map(|gas| gas.to_string())),
+        or_null(event.tx_status.map(|status| status.to_string()))
+    )
+}
+
+// Parse a line written by `transfer_json` (e.g. a JSONL export) back into a transfer
+// Extra fields such as `value_display` are ignored
+pub fn parse_transfer_json(line: &str) -> anyhow::Result<TransferEvent> {
+    let json: serde_json::Value = serde_json::from_str(line)?;
+    let number = |field: &str| {
+        json[field]
+            .as_u64()
+            .ok_or_else(|| anyhow::anyhow!("missing or invalid `{}`", field))
+    };
+    let text = |field: &str| {
+        json[field]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("missing or invalid `{}`", field))
+    };
+    let parsed =
+        |field: &str, e: &dyn std::fmt::Display| anyhow::anyhow!("invalid `{}`: {}", field, e);
+
+    Ok(TransferEvent {
+        chain_id: number("chain_id")?,
+        block_number: number("block_number")?,
+        tx_hash: text("tx_hash")?
+            .parse()
+            .map_err(|e| parsed("tx_hash", &e))?,
+        token_address: text("token_address")?
+            .parse()
+            .map_err(|e| parsed("token_address", &e))?,
+        from_addr: text("from")?.parse().map_err(|e| parsed("from", &e))?,
+        to_addr: text("to")?.parse().map_err(|e| parsed("to", &e))?,
+        value: value_from_storage(text("value")?)?,
+        log_index: number("log_index")?,
+        base_fee: json["base_fee"].as_u64(),
+        block_timestamp: json["block_timestamp"].as_u64(),
+        gas_used: json["gas_used"].as_u64(),
+        tx_status: json["tx_status"].as_bool(),
+    })
+}
+
+const CSV_HEADER: &str = "chain_id,block_number,tx_hash,log_index,token_address,from,to,value,\
+                          base_fee,block_timestamp,gas_used,tx_status";
+
+// Parse a row written by `transfer_csv` (e.g. a CSV export) back into a transfer
+// A trailing `value_display` column is ignored
+pub fn parse_transfer_csv(line: &str) -> anyhow::Result<TransferEvent> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() < 12 {
+        return Err(anyhow::anyhow!(
+            "expected {} columns, found {}",
+            CSV_HEADER.split(',').count(),
+            fields.len()
+        ));
+    }
+    let parsed =
+        |field: &str, e: &dyn std::fmt::Display| anyhow::anyhow!("invalid `{}`: {}", field, e);
+    let number =
+        |index: usize, field: &str| fields[index].parse::<u64>().map_err(|e| parsed(field, &e));
+    // Enrichment columns are empty when it wasn't fetched
+    let optional = |index: usize, field: &str| match fields[index] {
+        "" => Ok(None),
+        _ => number(index, field).map(Some),
+    };
+
+    Ok(TransferEvent {
+        chain_id: number(0, "chain_id")?,
+        block_number: number(1, "block_number")?,
+        tx_hash: fields[2].parse().map_err(|e| parsed("tx_hash", &e))?,
+        log_index: number(3, "log_index")?,
+        token_address: fields[4].parse().map_err(|e| parsed("token_address", &e))?,
+        from_addr: fields[5].parse().map_err(|e| parsed("from", &e))?,
+        to_addr: fields[6].parse().map_err(|e| parsed("to", &e))?,
+        value: value_from_storage(fields[7])?,
+        base_fee: optional(8, "base_fee")?,
+        block_timestamp: optional(9, "block_timestamp")?,
+        gas_used: optional(10, "gas_used")?,
+        tx_status: match fields[11] {
+            "" => None,
+            status => Some(status.parse().map_err(|e| parsed("tx_status", &e))?),
+        },
+    })
+}
+
+// Format and compression of an export file, from its name: `.gz` is gzip, then `.jsonl` is
+// JSON lines and anything else CSV (e.g. `transfers.jsonl.gz`)
+pub fn detect_format(path: &Path) -> (ExportFormat, Compression) {
+    let name = path.to_string_lossy();
+    let (name, compression) = match name.strip_suffix(".gz") {
+        Some(name) => (name, Compression::Gzip),
+        None => (name.as_ref(), Compression::None),
+    };
+    let format = if name.ends_with(".jsonl") {
+        ExportFormat::Jsonl
+    } else {
+        ExportFormat::Csv
+    };
+    (format, compression)
+}
+
+// Read back the transfers of a file written by `write_transfers`, in file order, without
+// loading it whole. The CSV header and blank lines are skipped; a row that doesn't parse is
+// an error naming its line.
+pub fn read_transfers(
+    input: impl Read + 'static,
+    format: ExportFormat,
+    compression: Compression,
+) -> impl Iterator<Item = anyhow::Result<TransferEvent>> {
+    let reader: Box<dyn BufRead> = match compression {
+        Compression::None => Box::new(BufReader::new(input)),
+        Compression::Gzip => Box::new(BufReader::new(GzDecoder::new(input))),
+    };
+    reader
+        .lines()
+        .enumerate()
+        .filter_map(move |(number, line)| {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(anyhow::anyhow!("line {}: {}", number + 1, e))),
+            };
+            let is_header = format == ExportFormat::Csv && line.starts_with(CSV_HEADER);
+            if line.trim().is_empty() || is_header {
+                return None;
+            }
+            let event = match format {
+                ExportFormat::Csv => parse_transfer_csv(&line),
+                ExportFormat::Jsonl => parse_transfer_json(&line),
+            };
+            Some(event.map_err(|e| anyhow::anyhow!("line {}: {}", number + 1, e)))
+        })
+}
+
+// CSV row of a transfer, in CSV_HEADER order (no field needs quoting, enrichment columns are
+// empty if unset)
+fn transfer_csv(event: &TransferEvent, addresses: AddressFormat) -> String {
+    let or_empty = |field: Option<String>| field.unwrap_or_default();
+    format!(
+        "{},{},{:#x},{},{},{},{},{},{},{},{},{}",
+        event.chain_id,
+        event.block_number,
+        event.tx_hash,
+        event.log_index,
+        addresses.render(event.token_address),
+        addresses.render(event.from_addr),
+        addresses.render(event.to_addr),
+        event.value,
+        or_empty(event.base_fee.map(|fee| fee.to_string())),
+        or_empty(event.block_timestamp.map(|ts| ts.to_string())),
+        or_empty(event.gas_used.map(|gas| gas.to_string())),
+        or_empty(event.tx_status.map(|status| status.to_string()))
+    )
+}
+
+// Write every transfer of a chain (up to `to_block`) to `out` in canonical
+// (block_number, log_index) order, compressed if requested. With a value format, the
+// human-readable amount is added as a last `value_display` column / field.
+// Returns the number of transfers.
+#[allow(clippy::too_many_arguments)]
+pub fn write_transfers(
+    store: &mut ReadOnlyStore,
+    chain_id: u64,
+    to_block: Option<u64>,
+    format: ExportFormat,
+    compression: Compression,
+    values: Option<ValueFormat>,
+    addresses: AddressFormat,
+    out: impl Write,
+) -> anyhow::Result<usize> {
+    match compression {
+        Compression::None => {
+            let mut out = BufWriter::new(out);
+            let rows = write_rows(
+                store, chain_id, to_block, format, values, addresses, &mut out,
+            )?;
+            out.flush()?;
+            Ok(rows)
+        }
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(BufWriter::new(out), flate2::Compression::default());
+            let rows = write_rows(
+                store,
+                chain_id,
+                to_block,
+                format,
+                values,
+                addresses,
+                &mut encoder,
+            )?;
+            // Writes the gzip trailer; dropping the encoder would swallow its errors
+            encoder.finish()?.flush()?;
+            Ok(rows)
+        }
+    }
+}
+
+fn write_rows(
+    store: &mut ReadOnlyStore,
+    chain_id: u64,
+    to_block: Option<u64>,
+    format: ExportFormat,
+    values: Option<ValueFormat>,
+    addresses: AddressFormat,
+    out: &mut impl Write,
+) -> anyhow::Result<usize> {
+    if format == ExportFormat::Csv {
+        match values {
+            Some(_) => writeln!(out, "{},value_display", CSV_HEADER)?,
+            None => writeln!(out, "{}", CSV_HEADER)?,
+        }
+    }
+
+    // Block numbers are stored as i64, so that is the highest block a row can have
+    let head = to_block.unwrap_or(i64::MAX as u64);
+    let mut after = None;
+    let mut rows = 0;
+    loop {
+        let page = store.finalized_transfers(chain_id, head, 0, after, PAGE_SIZE)?;
+        for event in &page {
+            let display = values.map(|values| values.format(event.value));
+            match (format, display) {
+                (ExportFormat::Csv, None) => writeln!(out, "{}", transfer_csv(event, addresses))?,
+                (ExportFormat::Csv, Some(display)) => {
+                    writeln!(out, "{},{}", transfer_csv(event, addresses), display)?
+                }
+                (ExportFormat::Jsonl, None) => {
+                    writeln!(out, "{}", transfer_json(event, addresses))?
+                }
+                (ExportFormat::Jsonl, Some(display)) => {
+                    // Same object with one more field, before the closing brace
+                    let json = transfer_json(event, addresses);
+                    let fields = json.strip_suffix('}').unwrap_or(&json);
+                    writeln!(out, r#"{},"value_display":"{}"}}"#, fields, display)?
+                }
+            }
+        }
+        rows += page.len();
+
+        match page.last() {
+            Some(last) if page.len() == PAGE_SIZE => {
+                after = Some((last.block_number, last.log_index));
+            }
+            _ => return Ok(rows),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Read-only store over a database holding `count` generated transfers
+    fn store_with_transfers(dir: &tempfile::TempDir, count: usize) -> ReadOnlyStore {
+        let path = dir.path().join("export.db").display().to_string();
+        let mut conn = crate::testing::open_db(&path);
+        let transfers: Vec<TransferEvent> = crate::testing::gen_transfers(7, count)
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect();
+        crate::storage::insert_transfers(&mut conn, &transfers).unwrap();
+        ReadOnlyStore::open(&path).unwrap()
+    }
+
+    fn export(
+        store: &mut ReadOnlyStore,
+        format: ExportFormat,
+        compression: Compression,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_transfers(
+            store,
+            crate::testing::CHAIN_ID,
+            None,
+            format,
+            compression,
+            None,
+            AddressFormat::Lowercase,
+            &mut out,
+        )
+        .unwrap();
+        out
+    }
+
+    #[test]
+    fn compressed_export_decompresses_to_the_plain_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = store_with_transfers(&dir, 200);
+        for format in [ExportFormat::Csv, ExportFormat::Jsonl] {
+            let plain = export(&mut store, format, Compression::None);
+            let gzip = export(&mut store, format, Compression::Gzip);
+
+            assert_eq!(&gzip[..2], &[0x1f, 0x8b], "gzip magic");
+            assert!(gzip.len() < plain.len());
+            let mut decompressed = Vec::new();
+            GzDecoder::new(&gzip[..])
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, plain);
+        }
+    }
+
+    #[test]
+    fn gzip_file_names_end_in_gz_once() {
+        let name = |compression: Compression, path: &str| compression.file_name(path.into());
+        assert_eq!(
+            name(Compression::Gzip, "out.csv"),
+            PathBuf::from("out.csv.gz")
+        );
+        assert_eq!(
+            name(Compression::Gzip, "out.csv.gz"),
+            PathBuf::from("out.csv.gz")
+        );
+        assert_eq!(name(Compression::None, "out.csv"), PathBuf::from("out.csv"));
+        assert_eq!(
+            detect_format(Path::new("transfers.jsonl.gz")),
+            (ExportFormat::Jsonl, Compression::Gzip)
+        );
+        assert_eq!(
+            detect_format(Path::new("transfers.csv")),
+            (ExportFormat::Csv, Compression::None)
+        );
+    }
+}
@@ -0,0 +1,296 @@
+use crate::indexer::{
+    IndexerError, LogQuery, LogsProvider, ProviderCapabilities, Result, batch_get_logs,
+    batch_get_receipts, block_hash_filter, call_contract, event_filter, get_block_info,
+    get_block_receipt_logs, get_code, get_receipt_info, log_addresses, rpc_error, transfer_filter,
+};
+use crate::types::{BlockInfo, ReceiptInfo};
+use alloy::primitives::{Address, B256, Bytes};
+use alloy::providers::{IpcConnect, Provider, ProviderBuilder};
+use alloy::rpc::types::eth::Log;
+use std::path::PathBuf;
+
+// LogsProvider talking to a local node over its IPC socket (e.g. geth.ipc / reth.ipc)
+#[derive(Clone)]
+pub struct IpcProvider {
+    pub path: PathBuf,
+    pub token_address: Address,
+    pub emitters: Vec<Address>, // Other contracts emitting the token's logs (see TOKEN_EMITTERS)
+    pub topic_filter: bool,     // Send topic0 in eth_getLogs filters (see LOGS_TOPIC_FILTER)
+}
+
+impl IpcProvider {
+    // Create Alloy IPC provider connected to the node socket
+    async fn connect(&self) -> Result<impl Provider + use<>> {
+        ProviderBuilder::new()
+            .connect_ipc(IpcConnect::new(self.path.clone()))
+            .await
+            .map_err(|e| IndexerError::Rpc(format!("Failed to connect to IPC socket: {:?}", e)))
+    }
+}
+
+impl LogsProvider for IpcProvider {
+    // A local node takes batches and subscriptions, and serves any block range
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            batch_requests: true,
+            subscriptions: true,
+            ..ProviderCapabilities::default()
+        }
+    }
+
+    // Fetch the latest block number over IPC
+    fn latest_block(&mut self) -> Result<u64> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async {
+            self.connect()
+                .await?
+                .get_block_number()
+                .await
+                .map_err(|e| rpc_error("get block number", e))
+        })
+    }
+
+    // Fetch chain_id over IPC
+    fn chain_id(&mut self) -> Result<u64> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async {
+            self.connect()
+                .await?
+                .get_chain_id()
+                .await
+                .map_err(|e| rpc_error("get chain ID", e))
+        })
+    }
+
+    // Fetch ERC20 Transfer event logs within a block range over IPC
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<impl IntoIterator<Item = Log>> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        let filter = transfer_filter(
+            log_addresses(self.token_address, &self.emitters),
+            start_block,
+            end_block,
+            self.topic_filter,
+        )?;
+        rt.block_on(async {
+            self.connect()
+                .await?
+                .get_logs(&filter)
+                .await
+                .map_err(|e| rpc_error("get logs", e))
+        })
+    }
+
+    // Fetch the logs of several queries in a single batched JSON-RPC request
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        let filters = queries
+            .iter()
+            .map(|query| {
+                transfer_filter(
+                    vec![query.address],
+                    query.from_block,
+                    query.to_block,
+                    self.topic_filter,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        rt.block_on(async {
+            let provider = self.connect().await?;
+            batch_get_logs(&provider, "eth_getLogs", &filters)
+                .await
+                .map_err(|e| rpc_error("get logs", e))
+        })
+    }
+
+    // Fetch the header of a block over IPC
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async { get_block_info(&self.connect().await?, block_number).await })
+    }
+
+    // Fetch the receipt of a transaction over IPC
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async { get_receipt_info(&self.connect().await?, tx_hash).await })
+    }
+
+    // Fetch several receipts in a single batched request over IPC
+    fn transaction_receipts(&self, tx_hashes: &[B256]) -> Result<Vec<ReceiptInfo>> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async { batch_get_receipts(&self.connect().await?, tx_hashes).await })
+    }
+
+    // Execute a read-only contract call over IPC
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async { call_contract(&self.connect().await?, to, data).await })
+    }
+
+    // Fetch the bytecode of an address over IPC
+    fn code(&self, address: Address) -> Result<Bytes> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        rt.block_on(async { get_code(&self.connect().await?, address).await })
+    }
+
+    // Fetch the token's logs from the receipts of a block over IPC
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        let addresses = log_addresses(self.token_address, &self.emitters);
+        rt.block_on(async {
+            get_block_receipt_logs(&self.connect().await?, block_number, &addresses).await
+        })
+    }
+
+    // Fetch the Transfer logs of the token in the block with this hash over IPC
+    fn block_hash_logs(&self, block_hash: B256) -> Result<Vec<Log>> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        let filter = block_hash_filter(
+            log_addresses(self.token_address, &self.emitters),
+            block_hash,
+            self.topic_filter,
+        )?;
+        rt.block_on(async {
+            self.connect()
+                .await?
+                .get_logs(&filter)
+                .await
+                .map_err(|e| rpc_error("get logs", e))
+        })
+    }
+
+    // Fetch the custom event logs of the token within a block range over IPC
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>> {
+        // Create tokio runtime for async operations (Diesel is synchronous)
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(IndexerError::Runtime)?;
+
+        let filter = event_filter(
+            log_addresses(self.token_address, &self.emitters),
+            start_block,
+            end_block,
+            selectors,
+        );
+        rt.block_on(async {
+            self.connect()
+                .await?
+                .get_logs(&filter)
+                .await
+                .map_err(|e| rpc_error("get event logs", e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(path: PathBuf) -> IpcProvider {
+        IpcProvider {
+            path,
+            token_address: crate::testing::TOKEN,
+            emitters: Vec::new(),
+            topic_filter: true,
+        }
+    }
+
+    #[test]
+    fn socket_paths_select_ipc() {
+        assert_eq!(
+            crate::indexer::ipc_path("ipc:///tmp/geth.ipc"),
+            Some(PathBuf::from("/tmp/geth.ipc"))
+        );
+        assert_eq!(
+            crate::indexer::ipc_path("/data/reth.ipc"),
+            Some(PathBuf::from("/data/reth.ipc"))
+        );
+        assert_eq!(crate::indexer::ipc_path("http://localhost:8545"), None);
+    }
+
+    #[test]
+    fn missing_socket_is_an_rpc_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = provider(dir.path().join("missing.ipc"))
+            .latest_block()
+            .unwrap_err();
+        assert!(matches!(error, IndexerError::Rpc(_)), "{:?}", error);
+    }
+
+    // Needs a running node: IPC_SOCKET=/path/to/node.ipc cargo test --features ipc -- --ignored
+    #[test]
+    #[ignore = "needs a node socket in IPC_SOCKET"]
+    fn node_answers_over_ipc() {
+        let path = std::env::var("IPC_SOCKET").expect("IPC_SOCKET is set");
+        let mut provider = provider(path.into());
+
+        let head = provider.latest_block().unwrap();
+        assert!(provider.chain_id().unwrap() > 0);
+        let from_block = head.saturating_sub(10);
+        provider.logs(from_block, head).unwrap();
+        let batched = provider
+            .batch_logs(&[LogQuery {
+                address: crate::testing::TOKEN,
+                from_block,
+                to_block: head,
+            }])
+            .unwrap();
+        assert_eq!(batched.len(), 1);
+    }
+}
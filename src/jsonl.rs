@@ -0,0 +1,207 @@
+use crate::export::transfer_json;
+use crate::indexer::{HookErrorPolicy, TransferHook};
+use crate::types::{AddressFormat, TransferEvent};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Secondary sink appending every indexed transfer to a JSONL file, alongside SQLite
+// When the file would grow past `max_bytes` it is renamed to `<path>.<n>` (the first unused n)
+// and a new one is started, so old segments can be shipped or deleted. 0 disables rotation.
+pub struct JsonlSink {
+    path: PathBuf,
+    max_bytes: u64,
+    addresses: AddressFormat,
+    file: Mutex<Segment>,
+}
+
+struct Segment {
+    file: File,
+    size: u64,
+}
+
+impl JsonlSink {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        max_bytes: u64,
+        addresses: AddressFormat,
+    ) -> anyhow::Result<Self> {
+        let path = path.into();
+        let segment = open_segment(&path)?;
+        Ok(JsonlSink {
+            path,
+            max_bytes,
+            addresses,
+            file: Mutex::new(segment),
+        })
+    }
+
+    // Append a transfer as one line, rotating the file first if the line doesn't fit
+    pub fn append(&self, event: &TransferEvent) -> anyhow::Result<()> {
+        let line = format!("{}\n", transfer_json(event, self.addresses));
+        let mut segment = self.file.lock().unwrap_or_else(|e| e.into_inner());
+
+        let line_bytes = line.len() as u64;
+        if self.max_bytes > 0 && segment.size > 0 && segment.size + line_bytes > self.max_bytes {
+            let rotated = rotated_path(&self.path);
+            std::fs::rename(&self.path, &rotated)
+                .map_err(|e| anyhow::anyhow!("Failed to rotate {}: {}", self.path.display(), e))?;
+            *segment = open_segment(&self.path)?;
+        }
+
+        segment
+            .file
+            .write_all(line.as_bytes())
+            .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", self.path.display(), e))?;
+        segment.size += line_bytes;
+        Ok(())
+    }
+
+    // Transfer sink appending every transfer once its range is committed, so the file never
+    // holds a transfer the database doesn't. A failed write stops the loop and sends the range
+    // back to be processed again, so the file never misses a stored transfer either. A range
+    // processed again (after a crash or `retry-failed`) is appended again, and reorged
+    // transfers are not removed from the file: readers should dedupe on
+    // (chain_id, tx_hash, log_index).
+    pub fn into_hook(self) -> TransferHook {
+        let sink = Arc::new(self);
+        TransferHook::new(move |event| sink.append(event), HookErrorPolicy::Abort)
+    }
+}
+
+// Open (or create) the file in append mode, continuing from its current size
+fn open_segment(path: &Path) -> anyhow::Result<Segment> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let size = file.metadata()?.len();
+    Ok(Segment { file, size })
+}
+
+// First `<path>.<n>` (n = 1, 2, ...) that doesn't exist yet
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut n = 1u64;
+    loop {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        let candidate = PathBuf::from(name);
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::{LoopOptions, event_loop};
+    use crate::storage;
+    use crate::testing::{CHAIN_ID, FakeProvider, in_memory_db, transfer_log};
+    use alloy::primitives::{Address, U256};
+    use diesel::RunQueryDsl;
+
+    fn provider() -> FakeProvider {
+        let account = Address::repeat_byte;
+        FakeProvider::new(
+            30,
+            vec![
+                transfer_log(3, 0, account(1), account(2), U256::from(7)),
+                transfer_log(3, 1, account(2), account(3), U256::from(5)),
+                transfer_log(12, 0, account(3), account(1), U256::from(1)),
+                transfer_log(25, 4, account(1), account(4), U256::from(9)),
+            ],
+        )
+    }
+
+    fn options(sink: TransferHook) -> LoopOptions {
+        LoopOptions {
+            range_size: 10,
+            end_block: Some(30),
+            transfer_sinks: vec![sink],
+            ..LoopOptions::default()
+        }
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn stored_lines(conn: &mut diesel::SqliteConnection) -> Vec<String> {
+        storage::transfers_in_range(conn, CHAIN_ID, 0, u64::MAX)
+            .unwrap()
+            .iter()
+            .map(|event| transfer_json(event, AddressFormat::Lowercase))
+            .collect()
+    }
+
+    #[test]
+    fn file_matches_the_database_after_a_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfers.jsonl");
+        let sink = JsonlSink::new(&path, 0, AddressFormat::Lowercase).unwrap();
+        let mut conn = in_memory_db();
+
+        event_loop(&mut conn, CHAIN_ID, provider(), &options(sink.into_hook())).unwrap();
+
+        let stored = stored_lines(&mut conn);
+        assert_eq!(stored.len(), 4);
+        assert_eq!(lines(&path), stored);
+    }
+
+    #[test]
+    fn nothing_is_written_for_a_range_the_database_rejects() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfers.jsonl");
+        let sink = JsonlSink::new(&path, 0, AddressFormat::Lowercase).unwrap();
+        let mut conn = in_memory_db();
+        // Blocks past 10 can't be stored
+        diesel::sql_query(
+            "CREATE TRIGGER reject BEFORE INSERT ON transfers WHEN NEW.block_number > 10 \
+             BEGIN SELECT RAISE(ABORT, 'rejected'); END",
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        let result = event_loop(&mut conn, CHAIN_ID, provider(), &options(sink.into_hook()));
+
+        assert!(result.is_err());
+        assert_eq!(lines(&path), stored_lines(&mut conn));
+        assert_eq!(lines(&path).len(), 2);
+    }
+
+    #[test]
+    fn a_failed_write_sends_the_range_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfers.jsonl");
+        let mut conn = in_memory_db();
+        let failing = TransferHook::new(
+            |event| match event.block_number {
+                12 => Err(anyhow::anyhow!("disk full")),
+                _ => Ok(()),
+            },
+            HookErrorPolicy::Abort,
+        );
+
+        let result = event_loop(&mut conn, CHAIN_ID, provider(), &options(failing));
+
+        assert!(result.is_err());
+        // The range of block 12 is committed but will be processed again
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, CHAIN_ID).unwrap(),
+            Some(9)
+        );
+        let sink = JsonlSink::new(&path, 0, AddressFormat::Lowercase).unwrap();
+        event_loop(&mut conn, CHAIN_ID, provider(), &options(sink.into_hook())).unwrap();
+        let written = lines(&path);
+        assert_eq!(written.len(), 2);
+        assert_eq!(written, stored_lines(&mut conn)[2..]);
+    }
+}
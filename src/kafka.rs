@@ -0,0 +1,173 @@
+use crate::export::transfer_json;
+use crate::indexer::{HookErrorPolicy, TransferHook};
+use crate::types::{AddressFormat, TransferEvent};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use std::sync::Arc;
+use std::time::Duration;
+
+// How long a send may wait for room in the producer queue
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Publishes transfers to a Kafka topic as JSON, keyed by token address
+// Delivery is acknowledged by all in-sync replicas (acks=all) and retried by the idempotent
+// producer until `message.timeout.ms`, so retries never reorder or duplicate within a session.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+    addresses: AddressFormat,
+    rt: tokio::runtime::Runtime,
+}
+
+impl KafkaSink {
+    pub fn new(
+        brokers: &str,
+        topic: &str,
+        delivery_timeout: Duration,
+        addresses: AddressFormat,
+    ) -> anyhow::Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .set("acks", "all")
+            .set(
+                "message.timeout.ms",
+                delivery_timeout.as_millis().to_string(),
+            )
+            .create()?;
+
+        // Delivery futures are awaited from the synchronous event loop
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(KafkaSink {
+            producer,
+            topic: topic.to_string(),
+            addresses,
+            rt,
+        })
+    }
+
+    // Send a transfer and wait until the broker acknowledged it
+    pub fn publish(&self, event: &TransferEvent) -> anyhow::Result<()> {
+        let key = format!("{:#x}", event.token_address);
+        let payload = transfer_json(event, self.addresses);
+        let record = FutureRecord::to(&self.topic).key(&key).payload(&payload);
+
+        self.rt
+            .block_on(self.producer.send(record, QUEUE_TIMEOUT))
+            .map(|_| ())
+            .map_err(|(e, _)| anyhow::anyhow!("Failed to publish to {}: {}", self.topic, e))
+    }
+
+    // Transfer sink publishing every transfer once its range is committed, so nothing is
+    // published for a range the database rejected. A failed delivery stops the loop and moves
+    // the sync pointer back before the range: at-least-once, the range is processed (and
+    // published) again on the next run.
+    pub fn into_hook(self) -> TransferHook {
+        let sink = Arc::new(self);
+        TransferHook::new(move |event| sink.publish(event), HookErrorPolicy::Abort)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::{LoopOptions, event_loop};
+    use crate::storage;
+    use crate::testing::{CHAIN_ID, FakeProvider, TOKEN, in_memory_db, transfer_log};
+    use alloy::primitives::{Address, U256};
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+    use rdkafka::message::Message;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::{Offset, TopicPartitionList};
+    use std::time::Instant;
+
+    const TOPIC: &str = "transfers";
+
+    // Messages of the topic's single partition as (key, payload): the `expected` ones, plus any
+    // extra one arriving shortly after
+    fn consume(bootstrap_servers: &str, expected: usize) -> Vec<(String, String)> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", bootstrap_servers)
+            .set("group.id", "indexer-test")
+            .create()
+            .unwrap();
+        let mut partitions = TopicPartitionList::new();
+        partitions
+            .add_partition_offset(TOPIC, 0, Offset::Beginning)
+            .unwrap();
+        consumer.assign(&partitions).unwrap();
+
+        let mut messages = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let timeout = if messages.len() < expected {
+                Duration::from_millis(100)
+            } else {
+                Duration::from_millis(500)
+            };
+            match consumer.poll(timeout) {
+                Some(message) => {
+                    let message = message.unwrap();
+                    messages.push((
+                        String::from_utf8(message.key().unwrap().to_vec()).unwrap(),
+                        message.payload_view::<str>().unwrap().unwrap().to_string(),
+                    ));
+                }
+                None if messages.len() >= expected || Instant::now() >= deadline => break,
+                None => {}
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn every_committed_transfer_is_published_once() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic(TOPIC, 1, 1).unwrap();
+        let sink = KafkaSink::new(
+            &cluster.bootstrap_servers(),
+            TOPIC,
+            Duration::from_secs(10),
+            AddressFormat::Lowercase,
+        )
+        .unwrap();
+        let account = Address::repeat_byte;
+        let provider = FakeProvider::new(
+            30,
+            vec![
+                transfer_log(3, 0, account(1), account(2), U256::from(7)),
+                transfer_log(12, 0, account(2), account(3), U256::from(5)),
+                transfer_log(25, 1, account(3), account(1), U256::from(1)),
+            ],
+        );
+        let options = LoopOptions {
+            range_size: 10,
+            end_block: Some(30),
+            transfer_sinks: vec![sink.into_hook()],
+            ..LoopOptions::default()
+        };
+        let mut conn = in_memory_db();
+
+        event_loop(&mut conn, CHAIN_ID, provider, &options).unwrap();
+
+        let expected: Vec<(String, String)> =
+            storage::transfers_in_range(&mut conn, CHAIN_ID, 0, u64::MAX)
+                .unwrap()
+                .iter()
+                .map(|event| {
+                    (
+                        format!("{:#x}", TOKEN),
+                        transfer_json(event, AddressFormat::Lowercase),
+                    )
+                })
+                .collect();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(
+            consume(&cluster.bootstrap_servers(), expected.len()),
+            expected
+        );
+    }
+}
@@ -1,89 +1,2193 @@
+use crate::indexer::LogsProvider;
 use anyhow::Result;
 use diesel::Connection;
+use diesel::RunQueryDsl;
 use diesel::SqliteConnection;
 use diesel_migrations::MigrationHarness;
-use tracing::{Level, info};
+use tracing::{Level, error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, fmt};
 
+pub mod async_indexer;
+pub mod breaker;
+pub mod cli;
 pub mod config;
+pub mod diff;
+pub mod dyn_provider;
+pub mod events;
+pub mod export;
 pub mod indexer;
+#[cfg(feature = "ipc")]
+pub mod ipc;
+pub mod jsonl;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod lock;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod range;
 pub mod schema;
+pub mod stats;
+pub mod storage;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
+pub mod throttle;
 pub mod types;
-// pub mod storage;
+pub mod units;
 
 pub use config::Config;
 
 pub const MIGRATIONS: diesel_migrations::EmbeddedMigrations =
     diesel_migrations::embed_migrations!("./migrations");
 
-pub fn init_logging() -> Result<()> {
-    // Create environment filter with default "info" level
-    let mut filter = EnvFilter::new("info");
+pub fn init_logging(default_level: Level, color: bool) -> Result<()> {
+    let filter = log_filter(default_level, std::env::var("RUST_LOG").ok().as_deref())?;
 
-    // Override filter if RUST_LOG environment variable is set
-    if let Ok(var) = std::env::var("RUST_LOG") {
-        // Parse and add custom log level directive (e.g., "debug", "rust_indexer=debug")
-        filter = filter.add_directive(var.parse()?);
+    // Configure and initialize tracing subscriber
+    let subscriber = tracing_subscriber::registry()
+        .with(filter) // Apply environment-based filtering
+        .with(fmt::layer().with_ansi(color));
+    // Also export the spans over OTLP when an endpoint is configured
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otel::layer()?);
+    subscriber.init(); // Initialize the global logger
+
+    Ok(())
+}
+
+// Flush what the logging still buffers (the OpenTelemetry spans) before exiting
+pub fn shutdown_logging() {
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+}
+
+// Whether to color the log lines: never with --no-color or a non-empty NO_COLOR (no-color.org),
+// otherwise only when they go to a terminal, so redirected logs carry no escape codes
+pub fn log_color(no_color_flag: bool, no_color_env: Option<&str>, is_terminal: bool) -> bool {
+    let no_color_env = no_color_env.is_some_and(|value| !value.is_empty());
+    is_terminal && !no_color_flag && !no_color_env
+}
+
+// Effective log filter: `default_level` (from -v/-q, "info" otherwise) with the RUST_LOG
+// directives on top, so an explicit RUST_LOG always wins over the command-line shortcut
+pub fn log_filter(default_level: Level, rust_log: Option<&str>) -> Result<EnvFilter> {
+    // Custom log level directives (e.g., "debug", "rust_indexer=debug,warn")
+    let directives: Vec<&str> = rust_log
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .collect();
+
+    // A bare level in RUST_LOG replaces the default, target directives refine it
+    let sets_global_level = directives
+        .iter()
+        .any(|directive| directive.parse::<LevelFilter>().is_ok());
+    let default_directive = default_level.as_str().to_lowercase();
+    let directives = if sets_global_level {
+        directives.join(",")
+    } else {
+        std::iter::once(default_directive.as_str())
+            .chain(directives)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    Ok(EnvFilter::try_new(directives)?)
+}
+
+// Create the missing parent directories of a database and check that it can be written, so a
+// first run in a fresh location works and a bad path fails with a clear error instead of
+// SQLite's. A database created here gets DB_FILE_MODE; SQLite gives its -wal and -shm files the
+// same permissions.
+fn prepare_db_path(db_path: &str, file_mode: Option<u32>) -> Result<()> {
+    if db_path == ":memory:" {
+        return Ok(());
+    }
+    let path = std::path::Path::new(db_path);
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to create the directory of DB_PATH {}: {}",
+                db_path,
+                e
+            )
+        })?;
     }
 
-    // Configure and initialize tracing subscriber
-    fmt()
-        .with_max_level(Level::INFO) // Set maximum log level
-        .with_env_filter(filter) // Apply environment-based filtering
-        .init(); // Initialize the global logger
+    let created = !path.exists();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!("DB_PATH {} is not writable: {}", db_path, e))?;
+    if let Some(mode) = file_mode.filter(|_| created) {
+        set_file_mode(path, mode)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &std::path::Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| anyhow::anyhow!("Failed to set the mode of {}: {}", path.display(), e))
+}
 
+#[cfg(not(unix))]
+fn set_file_mode(path: &std::path::Path, _mode: u32) -> Result<()> {
+    warn!(
+        "DB_FILE_MODE is ignored on this platform ({})",
+        path.display()
+    );
     Ok(())
 }
 
-pub async fn run(config: Config) -> Result<()> {
+// Open the SQLite database and apply pending migrations
+fn establish_connection(config: &Config) -> Result<SqliteConnection> {
+    prepare_db_path(&config.db_path, config.db_file_mode)?;
+
     // Format SQLite connection URL (Diesel requires "sqlite://" prefix)
     let database_url = format!("sqlite://{}", config.db_path);
 
-    // Establish database connection, panic if connection fails
     let mut conn = SqliteConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+        .map_err(|e| anyhow::anyhow!("Error connecting to {}: {}", database_url, e))?;
+
+    // Wait for locks held by other connections (e.g. an API reading the file) instead of failing
+    diesel::sql_query(format!(
+        "PRAGMA busy_timeout = {}",
+        storage::BUSY_TIMEOUT_MS
+    ))
+    .execute(&mut conn)
+    .map_err(|e| anyhow::anyhow!("Failed to set busy_timeout on {}: {}", config.db_path, e))?;
+
+    // Log the slow queries (SLOW_QUERY_MS), a debugging aid for database bottlenecks
+    if config.slow_query_ms > 0 {
+        conn.set_instrumentation(storage::SlowQueryLog::new(
+            std::time::Duration::from_millis(config.slow_query_ms),
+        ));
+    }
+
+    // Refuse to migrate a shared database whose own tables use the indexer's names, instead of
+    // failing halfway through the first migration
+    let conflicting = storage::conflicting_tables(&mut conn, &config.table_prefix)?;
+    if !conflicting.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} already has tables named like the indexer's ({}); use a separate DB_PATH, or a TABLE_PREFIX if only sync, transfers or indexes clash",
+            config.db_path,
+            conflicting.join(", ")
+        ));
+    }
 
     // Apply pending migrations
     info!("Applying pending migrations");
-    conn.run_pending_migrations(MIGRATIONS)
+    if config.table_prefix.is_empty() {
+        conn.run_pending_migrations(MIGRATIONS)
+            .expect("failed to apply migrations");
+    } else {
+        conn.run_pending_migrations(storage::PrefixedMigrations {
+            prefix: &config.table_prefix,
+        })
         .expect("failed to apply migrations");
+    }
     info!("Applied pending migrations");
+    storage::use_table_prefix(&mut conn, &config.table_prefix)?;
 
-    info!("Starting indexer...");
-    info!("  RPC URL: {}", config.rpc_url);
-    info!("  Chain ID: {}", config.chain_id);
-    info!("  Start Block: {}", config.start_block);
-    info!("  DB Path: {}", config.db_path);
-    info!("  Token Address: {:#x}", config.token_address);
+    Ok(conn)
+}
 
-    // Create Alloy provider for RPC access
-    let mut provider = indexer::AlloyProvider {
+// Create Alloy provider for RPC access
+fn build_provider(config: &Config) -> Result<indexer::AlloyProvider> {
+    Ok(indexer::AlloyProvider {
         url: config.rpc_url.parse()?,
         token_address: config.token_address,
+        emitters: config.token_emitters.clone(),
+        topic_filter: config.logs_topic_filter,
+        methods: config.rpc_methods(),
+        max_block_range: config.rpc_max_block_range,
+        compression: config.rpc_compression,
+        headers: indexer::build_headers(
+            &config.rpc_user_agent,
+            config.rpc_api_key.as_deref(),
+            &config.rpc_headers,
+        )?,
+    })
+}
+
+// Create Alloy IPC provider when RPC_URL points to a node socket
+#[cfg(feature = "ipc")]
+fn build_ipc_provider(config: &Config, path: std::path::PathBuf) -> Result<ipc::IpcProvider> {
+    Ok(ipc::IpcProvider {
+        path,
+        token_address: config.token_address,
+        emitters: config.token_emitters.clone(),
+        topic_filter: config.logs_topic_filter,
+    })
+}
+
+#[cfg(not(feature = "ipc"))]
+fn build_ipc_provider(
+    config: &Config,
+    _path: std::path::PathBuf,
+) -> Result<indexer::AlloyProvider> {
+    Err(anyhow::anyhow!(
+        "RPC_URL {} is an IPC endpoint, rebuild with `--features ipc` to use it",
+        config.rpc_url
+    ))
+}
+
+// Provider RPC_URL selects, chosen at runtime: IPC for a socket path, HTTP otherwise
+fn configured_provider(config: &Config) -> Result<dyn_provider::BoxedProvider> {
+    Ok(match indexer::ipc_path(&config.rpc_url) {
+        Some(path) => Box::new(build_ipc_provider(config, path)?),
+        None => Box::new(build_provider(config)?),
+    })
+}
+
+// Wrap a provider in the adaptive RPC throttle when ADAPTIVE_THROTTLE is set
+fn throttled<P: indexer::LogsProvider>(config: &Config, provider: P) -> throttle::Throttled<P> {
+    throttle::Throttled {
+        inner: provider,
+        throttle: config.adaptive_throttle.then(|| {
+            throttle::AdaptiveThrottle::new(config.throttle_min_rps, config.throttle_max_rps)
+        }),
+    }
+}
+
+// Publish every transfer to Kafka when KAFKA_BROKERS is set
+#[cfg(feature = "kafka")]
+fn kafka_hook(config: &Config) -> Result<Option<indexer::TransferHook>> {
+    let Some(brokers) = &config.kafka_brokers else {
+        return Ok(None);
+    };
+    let sink = kafka::KafkaSink::new(
+        brokers,
+        &config.kafka_topic,
+        std::time::Duration::from_millis(config.kafka_delivery_timeout_ms),
+        config.address_format(false),
+    )?;
+    Ok(Some(sink.into_hook()))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka_hook(config: &Config) -> Result<Option<indexer::TransferHook>> {
+    match &config.kafka_brokers {
+        Some(_) => Err(anyhow::anyhow!(
+            "KAFKA_BROKERS is set, rebuild with `--features kafka` to publish to Kafka"
+        )),
+        None => Ok(None),
+    }
+}
+
+// Serve the Prometheus metrics when METRICS_ADDR is set
+#[cfg(feature = "metrics")]
+fn serve_metrics(config: &Config) -> Result<()> {
+    match config.metrics_addr {
+        Some(addr) => metrics::serve(addr),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+fn serve_metrics(config: &Config) -> Result<()> {
+    match config.metrics_addr {
+        Some(_) => Err(anyhow::anyhow!(
+            "METRICS_ADDR is set, rebuild with `--features metrics` to serve metrics"
+        )),
+        None => Ok(()),
+    }
+}
+
+// Append every transfer to a JSONL file when JSONL_PATH is set
+fn jsonl_hook(config: &Config) -> Result<Option<indexer::TransferHook>> {
+    let Some(path) = &config.jsonl_path else {
+        return Ok(None);
     };
+    let sink = jsonl::JsonlSink::new(path, config.jsonl_max_bytes, config.address_format(false))?;
+    Ok(Some(sink.into_hook()))
+}
+
+// Target tables and balance materialization of the inserts
+fn write_options(config: &Config) -> storage::WriteOptions {
+    storage::WriteOptions {
+        tables: if config.table_per_token {
+            storage::TransferTables::PerToken
+        } else if config.partition_blocks > 0 {
+            storage::TransferTables::Partitioned {
+                blocks: config.partition_blocks,
+            }
+        } else {
+            storage::TransferTables::Shared
+        },
+        balances: config.materialize_balances,
+        table_prefix: config.table_prefix.clone(),
+    }
+}
+
+// Event loop settings
+fn loop_options(config: &Config) -> Result<indexer::LoopOptions> {
+    Ok(indexer::LoopOptions {
+        range_size: config.range_size,
+        poll_interval: std::time::Duration::from_millis(config.poll_interval_ms),
+        max_retries: config.max_retries,
+        retry_backoff: std::time::Duration::from_millis(config.retry_backoff_ms),
+        dead_letter: config.dead_letter,
+        confirmations: config.confirmations_for(config.chain_id),
+        shutdown: indexer::Shutdown::default(),
+        pause: indexer::Pause::new(config.pause_file.as_ref().map(std::path::PathBuf::from)),
+        timings: stats::RangeTimings::default(),
+        progress_interval: std::time::Duration::from_secs(config.progress_interval_secs),
+        lag_alert_blocks: config.lag_alert_blocks,
+        enrich_base_fee: config.enrich_base_fee,
+        enrich_timestamp: config.enrich_timestamp,
+        enrich_receipts: config.enrich_receipts,
+        skip_zero_value: config.skip_zero_value,
+        wrapped_events: config.wrapped_events,
+        verify_continuity: config.verify_continuity,
+        end_block: config.end_block,
+        pinned_head: config.pinned_head,
+        breaker_threshold: config.circuit_breaker_threshold,
+        breaker_cooldown: std::time::Duration::from_millis(config.circuit_breaker_cooldown_ms),
+        retry_budget: breaker::RetryBudget::new(
+            config.retry_budget,
+            std::time::Duration::from_secs(config.retry_budget_window_secs),
+        ),
+        head_cache_ttl: std::time::Duration::from_millis(config.head_cache_ttl_ms),
+        commit_batch: config.commit_batch_blocks,
+        commit_ranges: config.commit_ranges,
+        max_buffered_bytes: config.max_buffered_bytes,
+        audit_receipts_every: config.audit_receipts_every,
+        block_hash_tip: config.block_hash_tip_blocks,
+        sample_every: 0,
+        write: write_options(config),
+        token_emitters: config
+            .token_emitters
+            .iter()
+            .map(|emitter| (*emitter, config.token_address))
+            .collect(),
+        transfer_hooks: Vec::new(),
+        transfer_sinks: [kafka_hook(config)?, jsonl_hook(config)?]
+            .into_iter()
+            .flatten()
+            .collect(),
+        // Parsed up front, so a bad signature fails before connecting rather than mid-loop
+        events: match &config.events_file {
+            Some(path) => events::load_event_specs(path)?,
+            None => Vec::new(),
+        },
+    })
+}
 
-    // Fetch chain_id from RPC and validate against config
-    use indexer::LogsProvider;
+pub async fn run(config: Config) -> Result<()> {
+    // Held until `run` returns, so a second `run` of the same chain and database fails right away
+    let _locks = writer_locks(&config)?;
+    let mut conn = establish_connection(&config)?;
+
+    info!("Starting indexer...");
+    if config.chains.is_empty() {
+        info!("  RPC URL: {}", config::redact_url(&config.rpc_url));
+        info!("  Chain ID: {}", config.chain_id);
+        info!("  Start Block: {}", config.start_block);
+        if let Some(end_block) = config.end_block {
+            info!("  End Block: {}", end_block);
+        }
+        if let Some(pinned_head) = config.pinned_head {
+            info!("  Pinned Head: {}", pinned_head);
+        }
+        info!("  Token Address: {:#x}", config.token_address);
+        for emitter in &config.token_emitters {
+            info!("  Token Emitter: {:#x}", emitter);
+        }
+    }
+    for chain in &config.chains {
+        info!(
+            "  Chain {}: token {:#x} from block {} via {}",
+            chain.chain_id,
+            chain.token_address,
+            chain.start_block,
+            config::redact_url(&chain.rpc_url)
+        );
+    }
+    info!("  DB Path: {}", config.db_path);
+    info!("  Range Size: {}", config.range_size);
+    if config.commit_batch_blocks > 0 {
+        info!(
+            "  Committing every {} blocks within a range",
+            config.commit_batch_blocks
+        );
+    }
+    if config.commit_ranges > 1 {
+        info!("  Committing every {} ranges", config.commit_ranges);
+    }
+    if config.max_buffered_bytes > 0 {
+        info!(
+            "  Committing early past ~{} buffered bytes",
+            config.max_buffered_bytes
+        );
+    }
+    if config.audit_receipts_every > 0 {
+        info!(
+            "  Auditing every {} ranges against the block receipts",
+            config.audit_receipts_every
+        );
+    }
+    if config.lag_alert_blocks > 0 {
+        info!("  Alerting past {} blocks of lag", config.lag_alert_blocks);
+    }
+    if config.block_hash_tip_blocks > 0 {
+        info!(
+            "  Fetching by block hash within {} blocks of the head",
+            config.block_hash_tip_blocks
+        );
+    }
+    if config.chains.is_empty() {
+        info!(
+            "  Confirmations: {}",
+            config.confirmations_for(config.chain_id)
+        );
+    }
+    if config.dead_letter {
+        info!("  Dead-lettering failed ranges");
+    }
+    if config.circuit_breaker_threshold > 0 {
+        info!(
+            "  Circuit breaker: pause {} ms after {} consecutive failed ranges",
+            config.circuit_breaker_cooldown_ms, config.circuit_breaker_threshold
+        );
+    }
+    if config.retry_budget > 0 {
+        info!(
+            "  Retry budget: exit after more than {} RPC failures in {} s",
+            config.retry_budget, config.retry_budget_window_secs
+        );
+    }
+    if config.enrich_base_fee {
+        info!("  Enriching transfers with the block base fee");
+    }
+    if config.enrich_timestamp {
+        info!("  Enriching transfers with the block timestamp");
+    }
+    if config.enrich_receipts {
+        info!("  Enriching transfers with the transaction receipt");
+    }
+    if config.table_per_token {
+        info!(
+            "  Storing transfers in {}",
+            storage::token_table_name(config.token_address)
+        );
+    }
+    if config.partition_blocks > 0 {
+        info!(
+            "  Storing transfers in partitions of {} blocks (view {})",
+            config.partition_blocks,
+            storage::PARTITIONS_VIEW
+        );
+    }
+    if config.materialize_balances {
+        info!("  Maintaining the balances table");
+    }
+    if config.skip_zero_value {
+        info!("  Skipping zero-value transfers");
+    }
+    if config.wrapped_events {
+        info!("  Indexing Deposit/Withdrawal events as mints and burns");
+    }
+    if let Some(brokers) = &config.kafka_brokers {
+        info!(
+            "  Publishing to Kafka topic {} on {}",
+            config.kafka_topic, brokers
+        );
+    }
+    if let Some(path) = &config.jsonl_path {
+        info!("  Appending transfers to {}", path);
+    }
+    if let Some(addr) = config.metrics_addr {
+        info!("  Serving metrics on http://{}/metrics", addr);
+    }
+    if config.adaptive_throttle {
+        info!(
+            "  Adaptive RPC throttle: {} to {} requests/sec",
+            config.throttle_min_rps, config.throttle_max_rps
+        );
+    }
+    if config.log_retention_blocks > 0 {
+        info!(
+            "  Provider log retention: {} blocks{}",
+            config.log_retention_blocks,
+            if config.skip_retention_gap {
+                " (older blocks are skipped)"
+            } else {
+                ""
+            }
+        );
+    }
+    if !config.logs_topic_filter {
+        info!("  Filtering logs by address only (topic0 matched locally)");
+    }
+    info!("  User-Agent: {}", config.rpc_user_agent);
+    let methods = config.rpc_methods();
+    if methods != indexer::RpcMethods::default() {
+        info!(
+            "  RPC methods: {}, {}, {}",
+            methods.block_number, methods.logs, methods.chain_id
+        );
+    }
+    // Only header names are logged, values may contain credentials
+    if config.rpc_api_key.is_some() {
+        info!("  RPC API Key: <redacted>");
+    }
+    for (name, _) in &config.rpc_headers {
+        info!("  RPC Header: {}: <redacted>", name);
+    }
+
+    let options = loop_options(&config)?;
+    serve_metrics(&config)?;
+    pause_on_signals(options.pause.clone())?;
+    if let Some(path) = &config.pause_file {
+        info!("  Pause file: {}", path);
+    }
+    for spec in &options.events {
+        info!("  Indexing custom event: {}", spec.abi.signature());
+    }
+    let shutdown = options.shutdown.clone();
+    let shutdown_timeout = std::time::Duration::from_millis(config.shutdown_timeout_ms);
+
+    if !config.chains.is_empty() {
+        drop(conn);
+        return run_until_shutdown(shutdown, shutdown_timeout, move || {
+            index_chains(&config, &options)
+        })
+        .await;
+    }
+    run_until_shutdown(shutdown, shutdown_timeout, move || {
+        index_configured_chain(&mut conn, &config, &options)
+    })
+    .await
+}
+
+// One writer lock per indexed chain (see lock::WriterLock)
+fn writer_locks(config: &Config) -> Result<Vec<lock::WriterLock>> {
+    if config.chains.is_empty() {
+        prepare_db_path(&config.db_path, config.db_file_mode)?;
+        return Ok(vec![lock::WriterLock::acquire(
+            &config.db_path,
+            config.chain_id,
+        )?]);
+    }
+    config
+        .chains
+        .iter()
+        .map(|chain| {
+            let chain_config = config.for_chain(chain);
+            prepare_db_path(&chain_config.db_path, chain_config.db_file_mode)?;
+            lock::WriterLock::acquire(&chain_config.db_path, chain_config.chain_id)
+        })
+        .collect()
+}
+
+// Index every CHAIN_<n> chain at once, each on its own thread with its own connection and
+// provider; the other settings, the transfer hooks and the shutdown flag are shared.
+// A chain that fails stops the others, so the process exits (and can be restarted) just like
+// with a single chain; the first error is returned.
+fn index_chains(config: &Config, options: &indexer::LoopOptions) -> Result<()> {
+    // Opened one after the other, so the migrations are never run concurrently
+    let chains: Vec<(Config, SqliteConnection)> = config
+        .chains
+        .iter()
+        .map(|chain| {
+            let chain_config = config.for_chain(chain);
+            let conn = establish_connection(&chain_config)?;
+            Ok((chain_config, conn))
+        })
+        .collect::<Result<_>>()?;
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = chains
+            .into_iter()
+            .map(|(chain_config, mut conn)| {
+                let chain_options = indexer::LoopOptions {
+                    confirmations: chain_config.confirmations_for(chain_config.chain_id),
+                    end_block: chain_config.end_block,
+                    pinned_head: chain_config.pinned_head,
+                    timings: stats::RangeTimings::default(),
+                    token_emitters: std::collections::HashMap::new(),
+                    ..options.clone()
+                };
+                scope.spawn(move || {
+                    let result = index_configured_chain(&mut conn, &chain_config, &chain_options);
+                    if let Err(e) = &result {
+                        error!(
+                            chain_id = chain_config.chain_id,
+                            ?e,
+                            "chain stopped, stopping the others"
+                        );
+                        chain_options.shutdown.request();
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        // Every worker is joined before the first error is returned
+        let results: Vec<Result<()>> = workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("An indexing thread panicked")))
+            })
+            .collect();
+        results.into_iter().collect()
+    })
+}
+
+// Run index_chain over the provider RPC_URL selects (see configured_provider)
+fn index_configured_chain(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    index_chain(conn, config, configured_provider(config)?, options)
+}
+
+// Pause the event loop on SIGUSR1 and resume it on SIGUSR2, for as long as the process runs
+#[cfg(unix)]
+fn pause_on_signals(pause: indexer::Pause) -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut pause_signal = signal(SignalKind::user_defined1())?;
+    let mut resume_signal = signal(SignalKind::user_defined2())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = pause_signal.recv() => pause.pause(),
+                _ = resume_signal.recv() => pause.resume(),
+            }
+        }
+    });
+    Ok(())
+}
+
+// No signals to pause with, only PAUSE_FILE
+#[cfg(not(unix))]
+fn pause_on_signals(_pause: indexer::Pause) -> Result<()> {
+    Ok(())
+}
+
+// Wait for Ctrl-C (or SIGTERM on unix)
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+// Run blocking indexing work until it returns or a shutdown signal arrives
+// RPC calls block on their own runtime and Diesel is synchronous, so the work runs on a
+// dedicated thread instead of the async runtime. On shutdown the work gets `shutdown_timeout`
+// to finish its current range; if it is stuck (e.g. in a hung RPC call) it is abandoned and an
+// error is returned, so the process exits non-zero. The in-flight range is never committed in
+// that case, so the sync pointer doesn't advance.
+async fn run_until_shutdown(
+    shutdown: indexer::Shutdown,
+    shutdown_timeout: std::time::Duration,
+    work: impl FnOnce() -> Result<()> + Send + 'static,
+) -> Result<()> {
+    run_until(shutdown_signal(), shutdown, shutdown_timeout, work).await
+}
+
+// Same as run_until_shutdown with the shutdown trigger given by the caller
+async fn run_until(
+    signal: impl std::future::Future<Output = Result<()>>,
+    shutdown: indexer::Shutdown,
+    shutdown_timeout: std::time::Duration,
+    work: impl FnOnce() -> Result<()> + Send + 'static,
+) -> Result<()> {
+    let (tx, mut rx) = tokio::sync::oneshot::channel();
+    // A detached thread (unlike spawn_blocking) doesn't keep the process alive on exit
+    std::thread::spawn(move || {
+        let _ = tx.send(work());
+    });
+
+    tokio::select! {
+        result = &mut rx => return result?,
+        result = signal => result?,
+    }
+
+    info!(
+        "Shutdown requested, waiting up to {:?} for the current range",
+        shutdown_timeout
+    );
+    shutdown.request();
+    match tokio::time::timeout(shutdown_timeout, rx).await {
+        Ok(result) => result?,
+        Err(_) => Err(anyhow::anyhow!(
+            "Shutdown timed out after {:?}, abandoned the in-flight range",
+            shutdown_timeout
+        )),
+    }
+}
+
+// Fetch chain_id from RPC and validate against config, then probe the eth_getLogs filter
+fn verify_rpc(config: &Config, provider: &mut impl indexer::LogsProvider) -> Result<()> {
     let rpc_chain_id = provider
         .chain_id()
         .map_err(|e| anyhow::anyhow!("Failed to get chain ID: {}", e))?;
-    if rpc_chain_id != config.chain_id {
+    if rpc_chain_id == config.chain_id {
+        info!("Chain ID verified: {} (matches RPC)", rpc_chain_id);
+    } else if config.allow_chain_id_mismatch {
+        warn!(
+            "Chain ID mismatch: RPC returned {} but config has {}, storing as chain {} \
+             (ALLOW_CHAIN_ID_MISMATCH)",
+            rpc_chain_id, config.chain_id, config.chain_id
+        );
+    } else {
         return Err(anyhow::anyhow!(
-            "Chain ID mismatch: RPC returned {} but config has {}",
+            "Chain ID mismatch: RPC returned {} but config has {} \
+             (set ALLOW_CHAIN_ID_MISMATCH=true for a fork)",
             rpc_chain_id,
             config.chain_id
         ));
     }
-    info!("Chain ID verified: {} (matches RPC)", rpc_chain_id);
+
+    if !config.skip_code_check {
+        indexer::check_token_code(provider, config.token_address)
+            .inspect_err(|e| error!("{}", e))?;
+    }
+    indexer::probe_logs(provider).inspect_err(|e| error!("{}", e))?;
+    Ok(())
+}
+
+// Validate the chain, seed the sync pointer and run the event loop (blocks until interrupted)
+fn index_chain(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    if let Some(end_block) = config.end_block.filter(|end| *end < config.start_block) {
+        return Err(anyhow::anyhow!(
+            "END_BLOCK {} is before START_BLOCK {}",
+            end_block,
+            config.start_block
+        ));
+    }
+
+    let mut provider = throttled(config, provider);
+    verify_rpc(config, &mut provider)?;
+
+    // Detect the token's symbol and name once, for display
+    let metadata = indexer::detect_token_metadata(&provider, config.token_address)?;
+    storage::set_token_metadata(conn, config.chain_id, config.token_address, &metadata)?;
+    match metadata.decimals {
+        Some(decimals) => info!(
+            "Token: {} ({}, {} decimals)",
+            metadata.symbol, metadata.name, decimals
+        ),
+        None => info!("Token: {} ({})", metadata.symbol, metadata.name),
+    }
 
     // Set start block if not already set
-    let is_start_set = indexer::start_from(&mut conn, config.chain_id, config.start_block)?;
+    let is_start_set = indexer::start_from(conn, config.chain_id, config.start_block)?;
     if is_start_set {
         info!("Start block set to {}", config.start_block);
     }
 
+    // Re-scan the last blocks in case the previous run stopped uncleanly
+    if config.rewind_blocks > 0 {
+        let rewound = indexer::rewind(
+            conn,
+            config.chain_id,
+            config.rewind_blocks,
+            config.start_block,
+        )?;
+        if let Some(next_block) = rewound {
+            info!(
+                "Rewound up to {} blocks, re-scanning from block {}",
+                config.rewind_blocks, next_block
+            );
+        }
+    }
+
+    // Blocks a pruned provider no longer serves would fail range after range, say so up front
+    if config.log_retention_blocks > 0 {
+        let head = provider
+            .latest_block()
+            .map_err(|e| anyhow::anyhow!("Failed to get the head block: {}", e))?;
+        let gap = indexer::retention_gap(conn, config.chain_id, head, config.log_retention_blocks)?;
+        match gap {
+            Some(gap) if config.skip_retention_gap => {
+                storage::write_transaction(conn, |conn| {
+                    indexer::skip_retention_gap(conn, config.chain_id, gap)
+                })?;
+                warn!(
+                    "Skipped blocks {}..={}, older than the provider's {} block log retention; \
+                     they are in failed_ranges for `retry-failed` against an archive RPC",
+                    gap.from_block, gap.to_block, config.log_retention_blocks
+                );
+            }
+            Some(gap) => warn!(
+                "Blocks {}..={} are older than the provider's {} block log retention and will \
+                 likely fail: point RPC_URL at an archive node, or set SKIP_RETENTION_GAP=true to \
+                 resume at block {} and dead-letter the gap",
+                gap.from_block,
+                gap.to_block,
+                config.log_retention_blocks,
+                gap.to_block + 1
+            ),
+            None => {}
+        }
+    }
+
     // Run event loop (blocks until interrupted)
-    indexer::event_loop(&mut conn, config.chain_id, provider, 100)?;
+    indexer::event_loop(conn, config.chain_id, provider, options)?;
 
     Ok(())
 }
+
+// Re-attempt all dead-lettered ranges of the configured chain, then exit
+pub async fn retry_failed(config: Config) -> Result<()> {
+    let mut conn = establish_connection(&config)?;
+    let options = loop_options(&config)?;
+    let shutdown = options.shutdown.clone();
+    let shutdown_timeout = std::time::Duration::from_millis(config.shutdown_timeout_ms);
+
+    run_until_shutdown(shutdown, shutdown_timeout, move || {
+        retry_chain(&mut conn, &config, configured_provider(&config)?, &options)
+    })
+    .await
+}
+
+fn retry_chain(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    let provider = throttled(config, provider);
+    let (cleared, still_failing) =
+        indexer::retry_failed_ranges(conn, config.chain_id, &provider, options)?;
+    info!(
+        "Retried failed ranges: {} cleared, {} still failing",
+        cleared, still_failing
+    );
+    Ok(())
+}
+
+// Index a fixed block range without touching the live sync pointer, then exit
+// Progress is persisted per sub-range, so re-running the same range resumes an interrupted run
+pub async fn backfill(
+    config: Config,
+    from_block: u64,
+    to_block: u64,
+    sample_every: Option<u64>,
+    estimate: bool,
+) -> Result<()> {
+    let mut conn = establish_connection(&config)?;
+    let options = indexer::LoopOptions {
+        sample_every: sample_every.unwrap_or_default(),
+        ..loop_options(&config)?
+    };
+    if options.sample_every > 1 {
+        warn!(
+            "Sampled backfill: only every {} ranges are indexed, the others are recorded in failed_ranges",
+            options.sample_every
+        );
+    }
+    let shutdown = options.shutdown.clone();
+    let shutdown_timeout = std::time::Duration::from_millis(config.shutdown_timeout_ms);
+
+    run_until_shutdown(shutdown, shutdown_timeout, move || {
+        let provider = configured_provider(&config)?;
+        if estimate {
+            return print_backfill_estimate(
+                &mut conn, &config, provider, from_block, to_block, &options,
+            );
+        }
+        backfill_chain(&mut conn, &config, provider, from_block, to_block, &options)
+    })
+    .await
+}
+
+// `backfill --estimate`: print the work left in the backfill and exit without fetching logs
+// The time span needs the timestamps of both ends, so blocks past the head are left out of it
+fn print_backfill_estimate(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    from_block: u64,
+    to_block: u64,
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    let mut provider = throttled(config, provider);
+    verify_rpc(config, &mut provider)?;
+
+    let head = provider.latest_block()?;
+    if to_block > head {
+        warn!(
+            "--to-block {} is past the chain head {}, the backfill would fail there",
+            to_block, head
+        );
+    }
+    let next_block = indexer::backfill_next_block(conn, config.chain_id, from_block, to_block)?;
+    let fitted = options.fitted_to(&provider.capabilities());
+    let options = fitted.as_ref().unwrap_or(options);
+    let estimate = indexer::estimate_backfill(from_block, to_block, next_block, options);
+
+    let Some(next_block) = next_block else {
+        println!("backfill {}..={}: already complete", from_block, to_block);
+        return Ok(());
+    };
+    println!(
+        "backfill {}..={}: {} blocks left from block {} (chain head {})",
+        from_block, to_block, estimate.blocks, next_block, head
+    );
+    println!(
+        "ranges: {} of up to {} blocks, {} fetched",
+        estimate.ranges,
+        options.range_size.max(1),
+        estimate.fetched_ranges
+    );
+    println!(
+        "requests: ~{} (enrichment and retries not included)",
+        estimate.requests
+    );
+
+    // Best effort: a provider without block headers just leaves the time span out
+    let last_block = to_block.min(head);
+    if next_block > last_block {
+        println!("time span: none, no mined blocks left");
+        return Ok(());
+    }
+    let timestamps = provider
+        .block_info(next_block)
+        .and_then(|first| Ok((first.timestamp, provider.block_info(last_block)?.timestamp)));
+    match timestamps {
+        Ok((first, last)) => println!(
+            "time span: {}s of chain time (unix time {}..={})",
+            last.saturating_sub(first),
+            first,
+            last
+        ),
+        Err(e) => println!("time span: unknown ({})", e),
+    }
+    Ok(())
+}
+
+fn backfill_chain(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    from_block: u64,
+    to_block: u64,
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    let mut provider = throttled(config, provider);
+    verify_rpc(config, &mut provider)?;
+
+    let inserted = indexer::backfill(
+        conn,
+        config.chain_id,
+        &provider,
+        from_block,
+        to_block,
+        options,
+    )?;
+    info!(
+        "Backfill {}..={}: {} new transfers",
+        from_block, to_block, inserted
+    );
+    Ok(())
+}
+
+// Index only the given blocks (and the ones listed in `file`), then exit
+// The live sync pointer is left untouched
+pub async fn index_blocks(
+    config: Config,
+    mut blocks: Vec<u64>,
+    file: Option<std::path::PathBuf>,
+) -> Result<()> {
+    if let Some(file) = file {
+        blocks.extend(read_block_list(&std::fs::read_to_string(&file)?)?);
+    }
+    if blocks.is_empty() {
+        return Err(anyhow::anyhow!("No blocks given"));
+    }
+
+    let mut conn = establish_connection(&config)?;
+    let options = loop_options(&config)?;
+    let shutdown = options.shutdown.clone();
+    let shutdown_timeout = std::time::Duration::from_millis(config.shutdown_timeout_ms);
+
+    run_until_shutdown(shutdown, shutdown_timeout, move || {
+        index_blocks_chain(
+            &mut conn,
+            &config,
+            configured_provider(&config)?,
+            &blocks,
+            &options,
+        )
+    })
+    .await
+}
+
+fn index_blocks_chain(
+    conn: &mut SqliteConnection,
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    blocks: &[u64],
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    let mut provider = throttled(config, provider);
+    verify_rpc(config, &mut provider)?;
+
+    let inserted = indexer::index_blocks(conn, config.chain_id, &provider, blocks, options)?;
+    info!(
+        "Indexed {} blocks: {} new transfers",
+        blocks.len(),
+        inserted
+    );
+    Ok(())
+}
+
+// Parse a block list: numbers separated by commas or whitespace, `#` starts a comment
+fn read_block_list(raw: &str) -> Result<Vec<u64>> {
+    raw.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|block| !block.is_empty())
+        .map(|block| {
+            block
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid block number '{}'", block))
+        })
+        .collect()
+}
+
+// Print new transfers to stdout as they are mined, until interrupted
+// `unconfirmed` drops the confirmation depth, so transfers that may still be reorged are shown
+pub async fn tail(config: Config, unconfirmed: bool) -> Result<()> {
+    let mut options = loop_options(&config)?;
+    if unconfirmed {
+        options.confirmations = 0;
+    }
+    let shutdown = options.shutdown.clone();
+    let shutdown_timeout = std::time::Duration::from_millis(config.shutdown_timeout_ms);
+
+    run_until_shutdown(shutdown, shutdown_timeout, move || {
+        tail_chain(&config, configured_provider(&config)?, &options)
+    })
+    .await
+}
+
+fn tail_chain(
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    options: &indexer::LoopOptions,
+) -> Result<()> {
+    let mut provider = throttled(config, provider);
+    verify_rpc(config, &mut provider)?;
+
+    let decimals = match config.token_decimals {
+        Some(decimals) => Some(decimals),
+        None => indexer::detect_token_metadata(&provider, config.token_address)?.decimals,
+    };
+    if decimals.is_none() {
+        warn!("The token's decimals are unknown, printing raw values (set TOKEN_DECIMALS)");
+    }
+
+    indexer::tail(
+        config.chain_id,
+        provider,
+        options,
+        decimals.map(|decimals| config.value_format(decimals)),
+        config.address_format(true),
+        &mut std::io::stdout().lock(),
+    )?;
+    Ok(())
+}
+
+// Blocks compared by `validate-rpc` when no range is given
+const VALIDATE_RPC_BLOCKS: u64 = 100;
+
+// Check that the RPC returns every log of the token for a range query: fetch the range once and
+// block by block, and print the logs that only one of them returned. Defaults to the last
+// VALIDATE_RPC_BLOCKS confirmed blocks. Fails if anything is missing; no database access.
+pub async fn validate_rpc(
+    config: Config,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) -> Result<()> {
+    let shutdown = indexer::Shutdown::default();
+    let shutdown_timeout = std::time::Duration::from_millis(config.shutdown_timeout_ms);
+
+    run_until_shutdown(shutdown, shutdown_timeout, move || {
+        validate_chain(&config, configured_provider(&config)?, from_block, to_block)
+    })
+    .await
+}
+
+fn validate_chain(
+    config: &Config,
+    provider: impl indexer::LogsProvider,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+) -> Result<()> {
+    let mut provider = throttled(config, provider);
+    verify_rpc(config, &mut provider)?;
+
+    let to_block = match to_block {
+        Some(to_block) => to_block,
+        None => provider
+            .latest_block()?
+            .saturating_sub(config.confirmations_for(config.chain_id)),
+    };
+    let from_block = from_block.unwrap_or_else(|| to_block.saturating_sub(VALIDATE_RPC_BLOCKS - 1));
+    if from_block > to_block {
+        return Err(anyhow::anyhow!(
+            "--from-block {} is after --to-block {}",
+            from_block,
+            to_block
+        ));
+    }
+
+    info!(
+        "Comparing the logs of blocks {}..={} fetched as one range and block by block",
+        from_block, to_block
+    );
+    let completeness = indexer::validate_logs(&provider, from_block, to_block)?;
+    for key in &completeness.missing_from_range {
+        println!(
+            "missing from the range query: block {} {:#x}#{}",
+            key.block_number, key.tx_hash, key.log_index
+        );
+    }
+    for key in &completeness.missing_from_blocks {
+        println!(
+            "missing from the block queries: block {} {:#x}#{}",
+            key.block_number, key.tx_hash, key.log_index
+        );
+    }
+    if !completeness.is_complete() {
+        return Err(anyhow::anyhow!(
+            "The RPC dropped logs: {} from the range query ({} returned), {} from the block queries ({} returned)",
+            completeness.missing_from_range.len(),
+            completeness.range_logs,
+            completeness.missing_from_blocks.len(),
+            completeness.block_logs
+        ));
+    }
+    println!(
+        "blocks {}..={}: {} logs, range and block queries agree",
+        from_block, to_block, completeness.range_logs
+    );
+    Ok(())
+}
+
+// Print the sync state of the configured chain, including why it last failed
+// Read-only like `checksum`, so it can run next to the indexer
+pub fn status(config: Config) -> Result<()> {
+    let mut store =
+        storage::ReadOnlyStore::open_with_prefix(&config.db_path, &config.table_prefix)?;
+
+    match store.last_synced_block(config.chain_id)? {
+        Some(block) => println!("chain {}: synced up to block {}", config.chain_id, block),
+        None => println!("chain {}: nothing indexed yet", config.chain_id),
+    }
+    match store.sync_updated_at(config.chain_id)? {
+        Some(updated_at) => println!(
+            "pointer last advanced: at {} (unix time), {}s ago",
+            updated_at,
+            unix_now().saturating_sub(updated_at)
+        ),
+        None => println!("pointer last advanced: unknown"),
+    }
+    println!(
+        "failed ranges: {}",
+        store.failed_ranges(config.chain_id)?.len()
+    );
+    match store.last_error(config.chain_id)? {
+        Some(last_error) => println!(
+            "last error: blocks {}..={} at {} (unix time): {}",
+            last_error.from_block, last_error.to_block, last_error.failed_at, last_error.error
+        ),
+        None => println!("last error: none"),
+    }
+    Ok(())
+}
+
+// Print the lowest and highest block with stored transfers of every chain in the database, next
+// to its sync pointer, to sanity-check coverage. Read-only, like `status`.
+pub fn block_coverage(config: Config) -> Result<()> {
+    require_shared_transfers(&config, "range")?;
+    let mut store =
+        storage::ReadOnlyStore::open_with_prefix(&config.db_path, &config.table_prefix)?;
+    let chains = store.block_coverage()?;
+    if chains.is_empty() {
+        println!("nothing indexed yet");
+    }
+
+    let block = |block: Option<u64>| block.map_or("-".to_string(), |block| block.to_string());
+    for chain in chains {
+        println!(
+            "chain {}: transfers in blocks {}..={}, synced up to block {}",
+            chain.chain_id,
+            block(chain.min_block),
+            block(chain.max_block),
+            block(chain.synced_block)
+        );
+    }
+    Ok(())
+}
+
+// Writer locks of the chains configured in DB_PATH (CHAIN_ID and the CHAIN_<n> chains stored in
+// the same file), taken by maintenance commands so they refuse to run next to `run`, and no
+// `run` starts until they are done
+fn database_locks(config: &Config, command: &str) -> Result<Vec<lock::WriterLock>> {
+    let mut chain_ids = vec![config.chain_id];
+    for chain in &config.chains {
+        let chain_config = config.for_chain(chain);
+        if chain_config.db_path == config.db_path && !chain_ids.contains(&chain_config.chain_id) {
+            chain_ids.push(chain_config.chain_id);
+        }
+    }
+    chain_ids
+        .into_iter()
+        .map(|chain_id| {
+            lock::WriterLock::acquire(&config.db_path, chain_id)
+                .map_err(|e| anyhow::anyhow!("{}; stop it before running {}", e, command))
+        })
+        .collect()
+}
+
+// VACUUM the database to give the space of deleted rows back to the filesystem, and print how
+// much was reclaimed. VACUUM locks out the writer for as long as it runs, so it refuses while a
+// `run` holds the writer lock of a chain of the file (see database_locks); `force` skips that
+// check (e.g. for a writer of a chain configured elsewhere).
+pub fn compact(config: Config, force: bool) -> Result<()> {
+    let _locks = if force {
+        Vec::new()
+    } else {
+        database_locks(&config, "compact")?
+    };
+    let mut conn = establish_connection(&config)?;
+
+    let before = database_size(&config.db_path);
+    storage::compact(&mut conn)?;
+    let after = database_size(&config.db_path);
+    println!(
+        "Compacted {}: {} -> {} bytes ({} reclaimed)",
+        config.db_path,
+        before,
+        after,
+        before.saturating_sub(after)
+    );
+    Ok(())
+}
+
+// Size of the database file plus its WAL file, if any
+fn database_size(db_path: &str) -> u64 {
+    [db_path.to_string(), format!("{}-wal", db_path)]
+        .iter()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+// Current time in seconds since the Unix epoch
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Refuse a command that only reads the shared `transfers` table when TABLE_PER_TOKEN or
+// PARTITION_BLOCKS store the transfers elsewhere, rather than work on partial data
+fn require_shared_transfers(config: &Config, command: &str) -> Result<()> {
+    if config.table_per_token || config.partition_blocks > 0 {
+        return Err(anyhow::anyhow!(
+            "{} reads the shared transfers table and doesn't support TABLE_PER_TOKEN or PARTITION_BLOCKS",
+            command
+        ));
+    }
+    Ok(())
+}
+
+// Recompute the `balances` table of the configured chain from the stored transfers
+pub fn rebuild_balances(config: Config) -> Result<()> {
+    require_shared_transfers(&config, "rebuild-balances")?;
+
+    // The loop updates the balances of the chain as it indexes, so it must not be running
+    let _lock = lock::WriterLock::acquire(&config.db_path, config.chain_id)
+        .map_err(|e| anyhow::anyhow!("{}; stop it before running rebuild-balances", e))?;
+    let mut conn = establish_connection(&config)?;
+    let balances = storage::write_transaction(&mut conn, |conn| {
+        storage::rebuild_balances(conn, config.chain_id)
+    })?;
+    info!("Rebuilt {} balances of chain {}", balances, config.chain_id);
+    Ok(())
+}
+
+// Export the indexed transfers of the configured chain (up to `to_block`) as CSV or JSON lines,
+// to `output` or stdout, optionally gzip-compressed
+// Read-only like `checksum`, so it can run against a replica or next to a running indexer
+pub fn export(
+    config: Config,
+    format: export::ExportFormat,
+    output: Option<std::path::PathBuf>,
+    compression: export::Compression,
+    to_block: Option<u64>,
+    display_values: bool,
+) -> Result<()> {
+    require_shared_transfers(&config, "export")?;
+    let mut store =
+        storage::ReadOnlyStore::open_with_prefix(&config.db_path, &config.table_prefix)?;
+    // Decimals come from TOKEN_DECIMALS or the metadata stored by `run` (export never calls the RPC)
+    let values = if display_values {
+        let decimals = match config.token_decimals {
+            Some(decimals) => Some(decimals),
+            None => store
+                .token_metadata(config.chain_id, config.token_address)?
+                .and_then(|metadata| metadata.decimals),
+        };
+        let decimals = decimals.ok_or_else(|| {
+            anyhow::anyhow!(
+                "The token's decimals are unknown, set TOKEN_DECIMALS or run the indexer once"
+            )
+        })?;
+        Some(config.value_format(decimals))
+    } else {
+        None
+    };
+    match output {
+        Some(path) => {
+            let path = compression.file_name(path);
+            let file = std::fs::File::create(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path.display(), e))?;
+            let rows = export::write_transfers(
+                &mut store,
+                config.chain_id,
+                to_block,
+                format,
+                compression,
+                values,
+                config.address_format(false),
+                file,
+            )?;
+            info!("Exported {} transfers to {}", rows, path.display());
+        }
+        None => {
+            export::write_transfers(
+                &mut store,
+                config.chain_id,
+                to_block,
+                format,
+                compression,
+                values,
+                config.address_format(false),
+                std::io::stdout().lock(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Print the checksum of all indexed transfers of the configured chain
+// Read-only, so it can run against a replica or next to a running indexer
+pub fn checksum(config: Config, to_block: Option<u64>) -> Result<()> {
+    require_shared_transfers(&config, "checksum")?;
+    let mut store =
+        storage::ReadOnlyStore::open_with_prefix(&config.db_path, &config.table_prefix)?;
+    let checksum = store.transfers_checksum(config.chain_id, to_block)?;
+
+    let up_to = to_block.map_or_else(|| "latest".to_string(), |block| block.to_string());
+    println!(
+        "chain {} up to block {}: {} transfers, checksum {:#x}",
+        config.chain_id, up_to, checksum.rows, checksum.hash
+    );
+    Ok(())
+}
+
+// Transfers inserted per transaction by `import`
+const IMPORT_BATCH: usize = 10_000;
+
+// Insert the transfers of an export file (CSV or JSONL, gzipped or not, detected from the file
+// name unless `format` is given) through the indexer's idempotent insert, so rows already
+// stored are skipped and importing twice is harmless. Rows of other chains than CHAIN_ID are
+// skipped. With `set_sync_pointer`, a pointer behind the highest imported block is moved to it,
+// so `run` continues right after the dump; that takes the writer locks (see database_locks), as
+// a running writer would otherwise have its pointer moved under it.
+pub fn import(
+    config: Config,
+    path: &std::path::Path,
+    format: Option<export::ExportFormat>,
+    set_sync_pointer: bool,
+) -> Result<()> {
+    let _locks = if set_sync_pointer {
+        database_locks(&config, "import --set-sync-pointer")?
+    } else {
+        Vec::new()
+    };
+    let (detected, compression) = export::detect_format(path);
+    let format = format.unwrap_or(detected);
+    let file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let mut conn = establish_connection(&config)?;
+    let write = write_options(&config);
+
+    let mut rows = 0;
+    let mut inserted = 0;
+    let mut other_chains = 0;
+    let mut max_block = None;
+    let mut batch = Vec::with_capacity(IMPORT_BATCH);
+    // Batches are committed as they fill up: an invalid row stops the import, and the rows
+    // before it stay stored (running it again once the file is fixed skips them)
+    for event in export::read_transfers(file, format, compression) {
+        let event = event.map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        if event.chain_id != config.chain_id {
+            other_chains += 1;
+            continue;
+        }
+        rows += 1;
+        max_block = max_block.max(Some(event.block_number));
+        batch.push(types::TransferChange::Added(event));
+        if batch.len() == IMPORT_BATCH {
+            inserted += import_batch(&mut conn, &batch, &write)?;
+            batch.clear();
+        }
+    }
+    inserted += import_batch(&mut conn, &batch, &write)?;
+
+    println!(
+        "{}: {} transfers, {} inserted, {} already stored",
+        path.display(),
+        rows,
+        inserted,
+        rows - inserted
+    );
+    if other_chains > 0 {
+        warn!(
+            "Skipped {} transfers of chains other than {}",
+            other_chains, config.chain_id
+        );
+    }
+
+    let Some(max_block) = max_block.filter(|_| set_sync_pointer) else {
+        return Ok(());
+    };
+    let pointer = storage::get_last_synced_block(&mut conn, config.chain_id)?;
+    if pointer.is_none_or(|pointer| pointer < max_block) {
+        storage::write_transaction(&mut conn, |conn| {
+            storage::set_last_synced_block(conn, config.chain_id, max_block)
+        })?;
+        info!("Sync pointer moved to block {}", max_block);
+    }
+    Ok(())
+}
+
+fn import_batch(
+    conn: &mut SqliteConnection,
+    batch: &[types::TransferChange],
+    write: &storage::WriteOptions,
+) -> Result<usize> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    let applied = storage::write_transaction(conn, |conn| {
+        storage::apply_transfer_changes(conn, batch, write)
+    })?;
+    Ok(applied.inserted)
+}
+
+// Print the transfers found in only one of DB_PATH and `other` within [from_block, to_block]:
+// `-` lines are only in DB_PATH, `+` lines only in `other`. Fails if there is any difference,
+// so scripts can rely on the exit code. Read-only on both sides.
+pub fn diff(config: Config, other: &str, from_block: u64, to_block: Option<u64>) -> Result<()> {
+    require_shared_transfers(&config, "diff")?;
+    let mut left = diff::DiffSource::open(&config.db_path, &config.table_prefix)?;
+    let mut right = diff::DiffSource::open(other, &config.table_prefix)?;
+    // Block numbers are stored as i64, so that is the highest block a row can have
+    let to_block = to_block.unwrap_or(i64::MAX as u64);
+    let differences =
+        diff::diff_transfers(&mut left, &mut right, config.chain_id, from_block, to_block)?;
+
+    for event in &differences.only_left {
+        println!("- {}", event);
+    }
+    for event in &differences.only_right {
+        println!("+ {}", event);
+    }
+    if !differences.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} transfers only in {}, {} only in {}",
+            differences.only_left.len(),
+            config.db_path,
+            differences.only_right.len(),
+            other
+        ));
+    }
+    println!("No differences between {} and {}", config.db_path, other);
+    Ok(())
+}
+
+// Print every resolved setting as NAME=value, with the API key and header values redacted
+pub fn print_config(config: &Config) {
+    for (var, value) in config.redacted_settings() {
+        println!("{}={}", var, value);
+    }
+    for (var, value) in config.chain_settings() {
+        println!("{}={}", var, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{FakeProvider, open_db, transfer_log};
+    use alloy::primitives::{Address, U256};
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn shutdown_gives_up_on_a_hung_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hung.db").display().to_string();
+        let mut conn = open_db(&path);
+        let provider = FakeProvider {
+            delay: Duration::from_secs(3600),
+            ..FakeProvider::new(
+                20,
+                vec![transfer_log(
+                    5,
+                    0,
+                    Address::repeat_byte(1),
+                    Address::repeat_byte(2),
+                    U256::from(7),
+                )],
+            )
+        };
+        let options = indexer::LoopOptions::default();
+        let shutdown = options.shutdown.clone();
+
+        let started = Instant::now();
+        // Shutdown arrives once the loop is stuck fetching its first range
+        let result = run_until(
+            async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(())
+            },
+            shutdown,
+            Duration::from_millis(100),
+            move || {
+                indexer::event_loop(&mut conn, testing::CHAIN_ID, provider, &options)?;
+                Ok(())
+            },
+        )
+        .await;
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("Shutdown timed out"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        // The abandoned range was never committed
+        let mut conn = open_db(&path);
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, testing::CHAIN_ID).unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn work_finishing_before_shutdown_returns_its_result() {
+        let result = run_until(
+            std::future::pending(),
+            indexer::Shutdown::default(),
+            Duration::from_millis(100),
+            || Err(anyhow::anyhow!("boom")),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().to_string(), "boom");
+    }
+
+    #[test]
+    fn verbosity_sets_the_default_level_and_rust_log_wins() {
+        let hint = |level, rust_log| log_filter(level, rust_log).unwrap().max_level_hint();
+
+        assert_eq!(hint(Level::INFO, None), Some(LevelFilter::INFO));
+        assert_eq!(hint(Level::DEBUG, None), Some(LevelFilter::DEBUG));
+        assert_eq!(hint(Level::TRACE, Some("")), Some(LevelFilter::TRACE));
+        assert_eq!(hint(Level::WARN, None), Some(LevelFilter::WARN));
+        // A bare level in RUST_LOG replaces -v/-q
+        assert_eq!(hint(Level::TRACE, Some("error")), Some(LevelFilter::ERROR));
+        assert_eq!(hint(Level::WARN, Some("debug")), Some(LevelFilter::DEBUG));
+
+        // Target directives refine the level of -q instead of replacing it
+        let filter = log_filter(Level::WARN, Some("rust_indexer=debug")).unwrap();
+        assert_eq!(filter.to_string(), "rust_indexer=debug,warn");
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::DEBUG));
+
+        assert!(log_filter(Level::INFO, Some("rust_indexer=loud")).is_err());
+    }
+
+    #[test]
+    fn block_lists_take_commas_whitespace_and_comments() {
+        let raw = "# snapshot heights\n100, 200\n300 400 # end of Q1\n\n500";
+        assert_eq!(read_block_list(raw).unwrap(), vec![100, 200, 300, 400, 500]);
+        let error = read_block_list("100, 2O0").unwrap_err();
+        assert!(error.to_string().contains("'2O0'"), "{}", error);
+    }
+
+    fn test_config(dir: &tempfile::TempDir) -> Config {
+        let _env = testing::env_lock();
+        Config::load(&cli::ConfigArgs {
+            token_address: Some(testing::TOKEN),
+            db_path: Some(dir.path().join("indexer.db").display().to_string()),
+            ..cli::ConfigArgs::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn maintenance_commands_refuse_while_a_writer_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&dir);
+        establish_connection(&config).unwrap();
+
+        let writer = lock::WriterLock::acquire(&config.db_path, config.chain_id).unwrap();
+        let error = compact(config.clone(), false).unwrap_err().to_string();
+        assert!(
+            error.contains("stop it before running compact"),
+            "{}",
+            error
+        );
+        let error = rebuild_balances(config.clone()).unwrap_err().to_string();
+        assert!(
+            error.contains("stop it before running rebuild-balances"),
+            "{}",
+            error
+        );
+        let dump = dir.path().join("dump.csv");
+        std::fs::write(&dump, "").unwrap();
+        let error = import(config.clone(), &dump, None, true)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("stop it before running import --set-sync-pointer"),
+            "{}",
+            error
+        );
+        // Without moving the pointer, importing next to a writer is fine
+        import(config.clone(), &dump, None, false).unwrap();
+        compact(config.clone(), true).unwrap();
+
+        drop(writer);
+        compact(config.clone(), false).unwrap();
+        rebuild_balances(config.clone()).unwrap();
+        import(config.clone(), &dump, None, true).unwrap();
+        // The commands released their locks
+        lock::WriterLock::acquire(&config.db_path, config.chain_id).unwrap();
+    }
+
+    #[test]
+    fn shared_table_commands_refuse_split_transfers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            partition_blocks: 1_000,
+            ..test_config(&dir)
+        };
+        establish_connection(&config).unwrap();
+
+        let results = [
+            ("checksum", checksum(config.clone(), None)),
+            ("range", block_coverage(config.clone())),
+            ("diff", diff(config.clone(), &config.db_path, 0, None)),
+            ("rebuild-balances", rebuild_balances(config.clone())),
+            (
+                "export",
+                export(
+                    config.clone(),
+                    export::ExportFormat::Csv,
+                    Some(dir.path().join("transfers.csv")),
+                    export::Compression::None,
+                    None,
+                    false,
+                ),
+            ),
+        ];
+        for (command, result) in results {
+            let error = result.unwrap_err().to_string();
+            assert!(
+                error.starts_with(command) && error.contains("PARTITION_BLOCKS"),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn export_then_import_round_trips_the_transfers() {
+        let source = tempfile::tempdir().unwrap();
+        let source_db = source.path().join("source.db").display().to_string();
+        // Every other transfer enriched (the checksum leaves enrichment out, so it is compared
+        // on its own)
+        let transfers: Vec<types::TransferEvent> = testing::gen_transfers(11, 300)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_, transfer))| match index % 2 {
+                0 => types::TransferEvent {
+                    base_fee: Some(7_000_000_000 + index as u64),
+                    block_timestamp: Some(1_700_000_000 + transfer.block_number),
+                    gas_used: Some(21_000 + index as u64),
+                    tx_status: Some(index % 4 == 0),
+                    ..transfer
+                },
+                _ => transfer,
+            })
+            .collect();
+        storage::insert_transfers(&mut open_db(&source_db), &transfers).unwrap();
+        let mut store = storage::ReadOnlyStore::open(&source_db).unwrap();
+        let expected = store.transfers_checksum(testing::CHAIN_ID, None).unwrap();
+        let max_block = transfers.last().unwrap().block_number;
+        let enrichment = |transfers: &[types::TransferEvent]| {
+            transfers
+                .iter()
+                .map(|transfer| {
+                    (
+                        transfer.base_fee,
+                        transfer.block_timestamp,
+                        transfer.gas_used,
+                        transfer.tx_status,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected_enrichment = enrichment(
+            &store
+                .finalized_transfers(testing::CHAIN_ID, max_block, 0, None, transfers.len())
+                .unwrap(),
+        );
+
+        for (format, compression, name) in [
+            (
+                export::ExportFormat::Csv,
+                export::Compression::None,
+                "dump.csv",
+            ),
+            (
+                export::ExportFormat::Jsonl,
+                export::Compression::Gzip,
+                "dump.jsonl.gz",
+            ),
+        ] {
+            let path = source.path().join(name);
+            let mut file = std::fs::File::create(&path).unwrap();
+            export::write_transfers(
+                &mut store,
+                testing::CHAIN_ID,
+                None,
+                format,
+                compression,
+                None,
+                types::AddressFormat::Lowercase,
+                &mut file,
+            )
+            .unwrap();
+            drop(file);
+
+            let dir = tempfile::tempdir().unwrap();
+            let config = Config {
+                chain_id: testing::CHAIN_ID,
+                ..test_config(&dir)
+            };
+            // The format is detected from the file name; a second import finds every row stored
+            import(config.clone(), &path, None, true).unwrap();
+            import(config.clone(), &path, None, true).unwrap();
+
+            let mut conn = establish_connection(&config).unwrap();
+            assert_eq!(
+                storage::transfers_checksum(&mut conn, testing::CHAIN_ID, None).unwrap(),
+                expected,
+                "{}",
+                name
+            );
+            assert_eq!(
+                storage::get_last_synced_block(&mut conn, testing::CHAIN_ID).unwrap(),
+                Some(max_block)
+            );
+            let imported = storage::finalized_transfers(
+                &mut conn,
+                testing::CHAIN_ID,
+                max_block,
+                0,
+                None,
+                transfers.len(),
+            )
+            .unwrap();
+            assert_eq!(enrichment(&imported), expected_enrichment, "{}", name);
+        }
+    }
+
+    #[test]
+    fn redirected_logs_carry_no_ansi_sequences() {
+        assert!(log_color(false, None, true));
+        assert!(!log_color(false, None, false));
+        assert!(!log_color(true, None, true));
+        assert!(!log_color(false, Some("1"), true));
+        // An empty NO_COLOR doesn't count (no-color.org)
+        assert!(log_color(false, Some(""), true));
+
+        // The layer of init_logging, writing to a file instead of a terminal
+        let logged = |color: bool| {
+            let buffer = testing::LogBuffer::default();
+            let writer = buffer.clone();
+            let subscriber = tracing_subscriber::registry().with(
+                fmt::layer()
+                    .with_ansi(color)
+                    .with_writer(move || writer.clone()),
+            );
+            tracing::subscriber::with_default(subscriber, || {
+                tracing::warn!(block = 5, "Indexed blocks");
+            });
+            String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap()
+        };
+        let redirected = logged(log_color(false, None, false));
+        assert!(redirected.contains("Indexed blocks"), "{}", redirected);
+        assert!(!redirected.contains('\x1b'), "{:?}", redirected);
+        assert!(logged(log_color(false, None, true)).contains('\x1b'));
+    }
+
+    #[test]
+    fn token_without_code_fails_the_startup_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            chain_id: testing::CHAIN_ID,
+            ..test_config(&dir)
+        };
+        // An EOA, or a typo in TOKEN_ADDRESS
+        let mut provider = FakeProvider {
+            code: alloy::primitives::Bytes::new(),
+            ..FakeProvider::new(10, Vec::new())
+        };
+
+        let error = verify_rpc(&config, &mut provider).unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<indexer::IndexerError>(),
+                Some(indexer::IndexerError::NoTokenCode(address)) if *address == testing::TOKEN
+            ),
+            "{}",
+            error
+        );
+        assert!(error.to_string().contains("SKIP_CODE_CHECK"), "{}", error);
+
+        // Not deployed yet at the start block: the check is skipped on request
+        let config = Config {
+            skip_code_check: true,
+            ..config
+        };
+        verify_rpc(&config, &mut provider).unwrap();
+        verify_rpc(
+            &Config {
+                skip_code_check: false,
+                ..config
+            },
+            &mut FakeProvider::new(10, Vec::new()),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn missing_parent_directories_of_the_database_are_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/nested/indexer.db");
+        let config = Config {
+            db_path: path.display().to_string(),
+            db_file_mode: Some(0o600),
+            ..test_config(&dir)
+        };
+        establish_connection(&config).unwrap();
+        assert!(path.is_file());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // A parent that can't be a directory is reported clearly
+        let file = dir.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        let config = Config {
+            db_path: file.join("indexer.db").display().to_string(),
+            ..config
+        };
+        let Err(e) = establish_connection(&config) else {
+            panic!("a database under a file can't be created");
+        };
+        assert!(
+            e.to_string()
+                .starts_with("Failed to create the directory of DB_PATH"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn chain_id_mismatch_fails_unless_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            chain_id: testing::CHAIN_ID,
+            end_block: Some(10),
+            ..test_config(&dir)
+        };
+        let options = indexer::LoopOptions {
+            end_block: Some(10),
+            ..indexer::LoopOptions::default()
+        };
+        // An anvil fork of CHAIN_ID
+        let fork = || FakeProvider {
+            chain_id: 31337,
+            ..FakeProvider::new(
+                20,
+                vec![transfer_log(
+                    5,
+                    0,
+                    Address::ZERO,
+                    Address::repeat_byte(1),
+                    U256::ONE,
+                )],
+            )
+        };
+        let mut conn = open_db(&config.db_path);
+
+        let error = index_chain(&mut conn, &config, fork(), &options).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("Chain ID mismatch: RPC returned 31337 but config has 1"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, testing::CHAIN_ID).unwrap(),
+            None
+        );
+
+        // With the override the fork is indexed under the configured chain id
+        let config = Config {
+            allow_chain_id_mismatch: true,
+            ..config
+        };
+        index_chain(&mut conn, &config, fork(), &options).unwrap();
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, testing::CHAIN_ID).unwrap(),
+            Some(10)
+        );
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, 31337).unwrap(),
+            None
+        );
+        let stored = storage::transfers_in_range(&mut conn, testing::CHAIN_ID, 0, 10).unwrap();
+        assert_eq!(stored.len(), 1);
+    }
+
+    #[test]
+    fn database_with_foreign_indexer_tables_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&dir);
+        let mut other = SqliteConnection::establish(&config.db_path).unwrap();
+        diesel::sql_query("CREATE TABLE transfers (id INTEGER PRIMARY KEY, amount TEXT)")
+            .execute(&mut other)
+            .unwrap();
+        diesel::sql_query("CREATE TABLE orders (id INTEGER PRIMARY KEY)")
+            .execute(&mut other)
+            .unwrap();
+
+        let Err(error) = establish_connection(&config) else {
+            panic!("a database holding another transfers table was accepted");
+        };
+        let error = error.to_string();
+        assert!(
+            error.contains("already has tables named like the indexer's (transfers)"),
+            "{}",
+            error
+        );
+
+        // Indexes and the tables of TABLE_PER_TOKEN and PARTITION_BLOCKS clash too
+        for sql in [
+            "CREATE INDEX idx_block ON orders(id)",
+            "CREATE TABLE transfers_blocks_0 (id INTEGER PRIMARY KEY)",
+            "CREATE VIEW transfers_partitioned AS SELECT * FROM orders",
+        ] {
+            diesel::sql_query(sql).execute(&mut other).unwrap();
+        }
+        let token_table = storage::token_table_name(testing::TOKEN);
+        diesel::sql_query(format!(
+            "CREATE TABLE {} (id INTEGER PRIMARY KEY)",
+            token_table
+        ))
+        .execute(&mut other)
+        .unwrap();
+        // Nothing was migrated into the other application's file
+        assert_eq!(
+            storage::conflicting_tables(&mut other, "").unwrap(),
+            vec![
+                "transfers",
+                "idx_block",
+                "transfers_blocks_0",
+                "transfers_partitioned",
+                token_table.as_str(),
+            ]
+        );
+        // None of them is a name the indexer uses under a prefix
+        assert!(
+            storage::conflicting_tables(&mut other, "indexer_")
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn database_shared_with_unrelated_tables_is_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&dir);
+        let mut other = SqliteConnection::establish(&config.db_path).unwrap();
+        diesel::sql_query("CREATE TABLE orders (id INTEGER PRIMARY KEY)")
+            .execute(&mut other)
+            .unwrap();
+
+        let mut conn = establish_connection(&config).unwrap();
+        // Once migrated, the indexer's own tables are no longer conflicts
+        assert!(
+            storage::conflicting_tables(&mut conn, "")
+                .unwrap()
+                .is_empty()
+        );
+        establish_connection(&config).unwrap();
+    }
+
+    #[test]
+    fn prefixed_tables_are_read_and_written_next_to_the_other_applications() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            table_prefix: "indexer_".to_string(),
+            chain_id: testing::CHAIN_ID,
+            ..test_config(&dir)
+        };
+        // Another application's `sync` and `transfers`, with an index named like the indexer's
+        let mut other = SqliteConnection::establish(&config.db_path).unwrap();
+        for sql in [
+            "CREATE TABLE sync (last_run TEXT)",
+            "CREATE TABLE transfers (id INTEGER PRIMARY KEY, amount TEXT)",
+            "CREATE INDEX idx_block ON transfers(amount)",
+            "INSERT INTO transfers (amount) VALUES ('12.50')",
+        ] {
+            diesel::sql_query(sql).execute(&mut other).unwrap();
+        }
+
+        let transfers: Vec<types::TransferEvent> = testing::gen_transfers(3, 20)
+            .into_iter()
+            .map(|(_, transfer)| transfer)
+            .collect();
+        let changes: Vec<types::TransferChange> = transfers
+            .iter()
+            .cloned()
+            .map(types::TransferChange::Added)
+            .collect();
+        let max_block = transfers.last().unwrap().block_number;
+        let mut conn = establish_connection(&config).unwrap();
+        storage::write_transaction(&mut conn, |conn| {
+            storage::apply_transfer_changes(conn, &changes, &write_options(&config))?;
+            storage::set_last_synced_block(conn, testing::CHAIN_ID, max_block)
+        })
+        .unwrap();
+        // Moved twice, so the second write replaces the row
+        storage::set_last_synced_block(&mut conn, testing::CHAIN_ID, max_block + 1).unwrap();
+
+        // The rows went to the prefixed tables and the other application's are untouched
+        let count = |conn: &mut SqliteConnection, table: &str| {
+            diesel::dsl::sql::<diesel::sql_types::BigInt>(&format!(
+                "SELECT COUNT(*) FROM main.{}",
+                table
+            ))
+            .get_result::<i64>(conn)
+            .unwrap()
+        };
+        assert_eq!(
+            count(&mut other, "indexer_transfers"),
+            transfers.len() as i64
+        );
+        assert_eq!(count(&mut other, "indexer_sync"), 1);
+        assert_eq!(count(&mut other, "transfers"), 1);
+        assert_eq!(count(&mut other, "sync"), 0);
+
+        // Every read of the indexer sees them, on a new connection and read-only too
+        let mut conn = establish_connection(&config).unwrap();
+        assert_eq!(
+            storage::get_last_synced_block(&mut conn, testing::CHAIN_ID).unwrap(),
+            Some(max_block + 1)
+        );
+        assert_eq!(
+            storage::transfers_in_range(&mut conn, testing::CHAIN_ID, 0, max_block)
+                .unwrap()
+                .len(),
+            transfers.len()
+        );
+        let mut store =
+            storage::ReadOnlyStore::open_with_prefix(&config.db_path, &config.table_prefix)
+                .unwrap();
+        assert_eq!(
+            store.last_synced_block(testing::CHAIN_ID).unwrap(),
+            Some(max_block + 1)
+        );
+        assert_eq!(
+            store
+                .finalized_transfers(testing::CHAIN_ID, max_block, 0, None, transfers.len())
+                .unwrap()
+                .len(),
+            transfers.len()
+        );
+
+        // Re-applying inserts nothing, and a reorg deletes from the prefixed table
+        let removed = [types::TransferChange::Removed(transfers[0].clone())];
+        let write = write_options(&config);
+        let applied = storage::apply_transfer_changes(&mut conn, &changes, &write).unwrap();
+        assert_eq!(applied.inserted, 0);
+        let applied = storage::apply_transfer_changes(&mut conn, &removed, &write).unwrap();
+        assert_eq!(applied.removed, 1);
+        assert_eq!(
+            count(&mut other, "indexer_transfers"),
+            transfers.len() as i64 - 1
+        );
+
+        // Without the prefix, the other application's tables are not taken for the indexer's
+        let unprefixed = Config {
+            table_prefix: String::new(),
+            ..config.clone()
+        };
+        let Err(error) = establish_connection(&unprefixed) else {
+            panic!("the other application's tables were taken for the indexer's");
+        };
+        assert!(
+            error.to_string().contains("TABLE_PREFIX must stay"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+// Advisory lock held by the `run` indexing a chain into a database, so a second `run` for the
+// same chain and file refuses to start instead of interleaving its sync pointer updates with the
+// first one's. Other chains can still be indexed into the same file by other processes.
+// The OS releases the lock when the process exits, even after a crash, so a leftover file never
+// blocks a restart; it only holds the pid of the last holder, for the error message.
+// The file is removed on a graceful shutdown while still locked. Another process may have
+// opened it just before, and then lock the removed file; acquire checks that the locked file is
+// still the one at the path and starts over otherwise, so two holders never coexist.
+#[derive(Debug)]
+pub struct WriterLock {
+    path: PathBuf,
+    _file: File,
+}
+
+impl WriterLock {
+    pub fn acquire(db_path: &str, chain_id: u64) -> anyhow::Result<Self> {
+        let path = PathBuf::from(format!("{}.chain-{}.lock", db_path, chain_id));
+        loop {
+            if let Some(lock) = Self::try_acquire(&path, db_path, chain_id)? {
+                return Ok(lock);
+            }
+        }
+    }
+
+    // Lock the file at `path`, None if it was removed or replaced before it could be locked
+    fn try_acquire(path: &Path, db_path: &str, chain_id: u64) -> anyhow::Result<Option<Self>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| anyhow::anyhow!("Failed to open {}: {}", path.display(), e))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                return Err(anyhow::anyhow!(
+                    "Another indexer (pid {}) is already running chain {} against {} (lock file {})",
+                    holder.trim(),
+                    chain_id,
+                    db_path,
+                    path.display()
+                ));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(anyhow::anyhow!("Failed to lock {}: {}", path.display(), e));
+            }
+        }
+
+        if !is_file_at(&file, path)? {
+            return Ok(None);
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        Ok(Some(WriterLock {
+            path: path.to_path_buf(),
+            _file: file,
+        }))
+    }
+}
+
+// Whether `file` is still the file found at `path` (same device and inode)
+#[cfg(unix)]
+fn is_file_at(file: &File, path: &Path) -> anyhow::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let opened = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(current) => Ok(opened.dev() == current.dev() && opened.ino() == current.ino()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(anyhow::anyhow!("Failed to stat {}: {}", path.display(), e)),
+    }
+}
+
+// Files can't be removed while open here, so the file at the path is always the opened one
+#[cfg(not(unix))]
+fn is_file_at(_file: &File, _path: &Path) -> anyhow::Result<bool> {
+    Ok(true)
+}
+
+// Removed on a graceful shutdown, before the handle (and the lock) is released
+impl Drop for WriterLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db_path(dir: &tempfile::TempDir) -> String {
+        dir.path().join("indexer.db").display().to_string()
+    }
+
+    #[test]
+    fn second_writer_of_a_chain_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = db_path(&dir);
+        let lock = WriterLock::acquire(&db_path, 1).unwrap();
+
+        let error = WriterLock::acquire(&db_path, 1).unwrap_err().to_string();
+        assert!(error.contains("already running chain 1"), "{}", error);
+        assert!(
+            error.contains(&format!("pid {}", std::process::id())),
+            "{}",
+            error
+        );
+        // Other chains of the same file are independent
+        let other_chain = WriterLock::acquire(&db_path, 2).unwrap();
+
+        drop(lock);
+        drop(other_chain);
+        assert!(!Path::new(&format!("{}.chain-1.lock", db_path)).exists());
+        WriterLock::acquire(&db_path, 1).unwrap();
+    }
+
+    #[test]
+    fn a_removed_lock_file_is_not_trusted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("removed.lock");
+        let file = File::create(&path).unwrap();
+        assert!(is_file_at(&file, &path).unwrap());
+
+        // Removed by the previous holder, then created again by a third process
+        std::fs::remove_file(&path).unwrap();
+        assert!(!is_file_at(&file, &path).unwrap());
+        let _recreated = File::create(&path).unwrap();
+        assert!(!is_file_at(&file, &path).unwrap());
+    }
+}
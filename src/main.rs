@@ -1,15 +1,86 @@
 use anyhow::Result;
+use clap::Parser;
 use dotenvy::dotenv;
-use rust_indexer::{Config, init_logging, run};
+use rust_indexer::cli::{Cli, Command};
+use rust_indexer::{
+    Config, backfill, block_coverage, checksum, compact, diff, export, import, index_blocks,
+    init_logging, print_config, rebuild_balances, retry_failed, run, shutdown_logging, status,
+    tail, validate_rpc,
+};
 use tracing::error;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
-    init_logging()?;
+    let cli = Cli::parse();
+    init_logging(cli.log_level(), cli.log_color())?;
 
-    let config = Config::from_env()?;
-    run(config).await.inspect_err(|e| error!(?e, "run error"))?;
+    let result = execute(cli).await;
+    shutdown_logging();
+    result
+}
+
+async fn execute(cli: Cli) -> Result<()> {
+    let config = Config::load(&cli.config)?;
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(config).await.inspect_err(|e| error!(?e, "run error"))?,
+        Command::RetryFailed => retry_failed(config)
+            .await
+            .inspect_err(|e| error!(?e, "retry-failed error"))?,
+        Command::Backfill {
+            from_block,
+            to_block,
+            sample_every,
+            estimate,
+        } => backfill(config, from_block, to_block, sample_every, estimate)
+            .await
+            .inspect_err(|e| error!(?e, "backfill error"))?,
+        Command::IndexBlocks { blocks, file } => index_blocks(config, blocks, file)
+            .await
+            .inspect_err(|e| error!(?e, "index-blocks error"))?,
+        Command::Tail { unconfirmed } => tail(config, unconfirmed)
+            .await
+            .inspect_err(|e| error!(?e, "tail error"))?,
+        Command::ValidateRpc {
+            from_block,
+            to_block,
+        } => validate_rpc(config, from_block, to_block)
+            .await
+            .inspect_err(|e| error!(?e, "validate-rpc error"))?,
+        Command::RebuildBalances => {
+            rebuild_balances(config).inspect_err(|e| error!(?e, "rebuild-balances error"))?
+        }
+        Command::Status => status(config).inspect_err(|e| error!(?e, "status error"))?,
+        Command::Range => block_coverage(config).inspect_err(|e| error!(?e, "range error"))?,
+        Command::Export {
+            format,
+            output,
+            compress,
+            to_block,
+            display_values,
+        } => export(config, format, output, compress, to_block, display_values)
+            .inspect_err(|e| error!(?e, "export error"))?,
+        Command::Import {
+            file,
+            format,
+            set_sync_pointer,
+        } => import(config, &file, format, set_sync_pointer)
+            .inspect_err(|e| error!(?e, "import error"))?,
+        Command::Checksum { to_block } => {
+            checksum(config, to_block).inspect_err(|e| error!(?e, "checksum error"))?
+        }
+        Command::Compact { force } => {
+            compact(config, force).inspect_err(|e| error!(?e, "compact error"))?
+        }
+        Command::Diff {
+            other,
+            from_block,
+            to_block,
+        } => {
+            diff(config, &other, from_block, to_block).inspect_err(|e| error!(?e, "diff error"))?
+        }
+        Command::PrintConfig => print_config(&config),
+    }
 
     Ok(())
 }
@@ -0,0 +1,71 @@
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::net::SocketAddr;
+
+// Serve the metrics in the Prometheus text format on http://<addr>/metrics
+// Installs the global recorder, so it can only be called once per process
+pub fn serve(addr: SocketAddr) -> anyhow::Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .install()
+        .map_err(|e| anyhow::anyhow!("Failed to serve metrics on {}: {}", addr, e))
+}
+
+// Chain head seen by the event loop of a chain (`head_block{chain_id}`)
+pub fn record_head_block(chain_id: u64, head: u64) {
+    metrics::gauge!("head_block", "chain_id" => chain_id.to_string()).set(head as f64);
+}
+
+// Times the event loop of a chain fell more than LAG_ALERT_BLOCKS behind (`lag_alerts{chain_id}`)
+pub fn record_lag_alert(chain_id: u64) {
+    metrics::counter!("lag_alerts", "chain_id" => chain_id.to_string()).increment(1);
+}
+
+// Last block committed by the event loop of a chain (`synced_block{chain_id}`)
+pub fn record_synced_block(chain_id: u64, block: u64) {
+    metrics::gauge!("synced_block", "chain_id" => chain_id.to_string()).set(block as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexer::{LoopOptions, event_loop};
+    use crate::testing::{FakeProvider, in_memory_db};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+
+    #[test]
+    fn gauges_reflect_the_latest_pass_of_each_chain() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            for (chain_id, head) in [(1, 50), (137, 80)] {
+                let options = LoopOptions {
+                    range_size: 20,
+                    end_block: Some(head - 10),
+                    ..LoopOptions::default()
+                };
+                let mut conn = in_memory_db();
+                event_loop(
+                    &mut conn,
+                    chain_id,
+                    FakeProvider::new(head, Vec::new()),
+                    &options,
+                )
+                .unwrap();
+            }
+        });
+
+        let rendered = handle.render();
+        for line in [
+            "synced_block{chain_id=\"1\"} 40",
+            "head_block{chain_id=\"1\"} 50",
+            "synced_block{chain_id=\"137\"} 70",
+            "head_block{chain_id=\"137\"} 80",
+        ] {
+            assert!(
+                rendered.lines().any(|rendered| rendered == line),
+                "{} missing from:\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use std::sync::OnceLock;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+// Reported as service.name unless OTEL_SERVICE_NAME is set
+const SERVICE_NAME: &str = "rust-indexer";
+
+// Kept to flush the spans still batched when the process exits (see `shutdown`)
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+// Layer exporting the tracing spans (e.g. the `range` span of every processed range) over
+// OTLP/HTTP, or None when no OTLP endpoint is configured
+// The exporter is configured by the standard variables: OTEL_EXPORTER_OTLP_ENDPOINT (or
+// OTEL_EXPORTER_OTLP_TRACES_ENDPOINT), OTEL_EXPORTER_OTLP_HEADERS, OTEL_SERVICE_NAME and
+// OTEL_RESOURCE_ATTRIBUTES. Spans are exported in batches from a background thread.
+pub fn layer<S>() -> anyhow::Result<Option<OpenTelemetryLayer<S, Tracer>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|var| std::env::var(var).is_ok_and(|value| !value.is_empty()));
+    if !configured {
+        return Ok(None);
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to build the OTLP exporter: {}", e))?;
+    let mut resource = Resource::builder();
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(SERVICE_NAME);
+    }
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build();
+
+    let tracer = provider.tracer(SERVICE_NAME);
+    let _ = PROVIDER.set(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+// Export the spans still batched; call once before exiting, later spans are not exported
+pub fn shutdown() {
+    let Some(provider) = PROVIDER.get() else {
+        return;
+    };
+    if let Err(e) = provider.shutdown() {
+        eprintln!("Failed to flush the OpenTelemetry spans: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn exporter_initializes_only_when_an_endpoint_is_set() {
+        let _env = crate::testing::env_lock();
+        // SAFETY: the variables are only read by `layer`, under the env lock
+        unsafe {
+            std::env::remove_var("OTEL_EXPORTER_OTLP_ENDPOINT");
+            std::env::remove_var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT");
+        }
+        assert!(layer::<tracing_subscriber::Registry>().unwrap().is_none());
+
+        // Nothing listens there: spans are recorded, their export fails in the background
+        unsafe { std::env::set_var("OTEL_EXPORTER_OTLP_ENDPOINT", "http://127.0.0.1:9") };
+        let layer = layer::<tracing_subscriber::Registry>();
+        unsafe { std::env::remove_var("OTEL_EXPORTER_OTLP_ENDPOINT") };
+        let layer = layer.unwrap().expect("an endpoint enables the exporter");
+        assert!(PROVIDER.get().is_some());
+
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = crate::indexer::range_span(1, 10, 19).entered();
+            tracing::info!("inside the range span");
+        });
+        shutdown();
+    }
+}
@@ -0,0 +1,201 @@
+use crate::indexer::{IndexerError, Result};
+
+// Split the inclusive block range [from, to] into consecutive chunks of at most `size` blocks
+// Empty when from > to. The last chunk ends exactly at `to`, including to == u64::MAX, without
+// overflowing. A zero size is an error since it would never make progress.
+pub fn chunk_ranges(from: u64, to: u64, size: u64) -> Result<impl Iterator<Item = (u64, u64)>> {
+    if size == 0 {
+        return Err(IndexerError::Parse(
+            "Range size must be at least 1 block".to_string(),
+        ));
+    }
+
+    let mut next = (from <= to).then_some(from);
+    Ok(std::iter::from_fn(move || {
+        let start = next?;
+        // Both ends are inclusive, so a chunk of `size` blocks ends at start + size - 1
+        let end = start.saturating_add(size - 1).min(to);
+        next = end.checked_add(1).filter(|block| *block <= to);
+        Some((start, end))
+    }))
+}
+
+// Sync progression: which inclusive block range to process next
+//
+// `pointer` is the last fully processed block, or None if nothing has been processed yet
+// (a fresh sync row seeded with `start_block - 1` reads as Some(start_block - 1), or None
+// for start_block 0). Ranges never go past `head - confirmations`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeCursor {
+    pub pointer: Option<u64>,
+    pub range_size: u64,
+    pub confirmations: u64,
+}
+
+impl RangeCursor {
+    pub fn new(pointer: Option<u64>, range_size: u64, confirmations: u64) -> Self {
+        RangeCursor {
+            pointer,
+            // A zero range size would never make progress
+            range_size: range_size.max(1),
+            confirmations,
+        }
+    }
+
+    // First block that hasn't been processed yet (None once u64::MAX is processed)
+    pub fn next_block(&self) -> Option<u64> {
+        match self.pointer {
+            Some(pointer) => pointer.checked_add(1),
+            None => Some(0),
+        }
+    }
+
+    // Next range (from, to) to process given the chain head, or None when caught up
+    pub fn next_range(&self, head: u64) -> Option<(u64, u64)> {
+        // Blocks within `confirmations` of the head are still reorg-prone
+        let safe_head = head.checked_sub(self.confirmations)?;
+        let from = self.next_block()?;
+        chunk_ranges(from, safe_head, self.range_size).ok()?.next()
+    }
+
+    // Whether every block up to `end_block` (inclusive) has been processed
+    pub fn reached(&self, end_block: u64) -> bool {
+        self.next_block().is_none_or(|next| next > end_block)
+    }
+
+    // Record that every block up to `to_block` (inclusive) has been processed
+    pub fn advance(&mut self, to_block: u64) {
+        self.pointer = Some(to_block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_range_starts_at_the_seeded_block() {
+        // Fresh database (or START_BLOCK 0): nothing processed yet
+        let cursor = RangeCursor::new(None, 10, 0);
+        assert_eq!(cursor.next_block(), Some(0));
+        assert_eq!(cursor.next_range(100), Some((0, 9)));
+        // Seeded with START_BLOCK 500
+        let cursor = RangeCursor::new(Some(499), 10, 0);
+        assert_eq!(cursor.next_range(1000), Some((500, 509)));
+    }
+
+    #[test]
+    fn range_is_capped_by_the_confirmed_head() {
+        let cursor = RangeCursor::new(Some(99), 100, 5);
+        assert_eq!(cursor.next_range(120), Some((100, 115)));
+        // Only block 100 is confirmed
+        assert_eq!(cursor.next_range(105), Some((100, 100)));
+        // The confirmed head is below the next block
+        assert_eq!(cursor.next_range(104), None);
+        // The head is within the confirmations of genesis
+        assert_eq!(RangeCursor::new(None, 10, 5).next_range(4), None);
+        assert_eq!(RangeCursor::new(None, 10, 5).next_range(5), Some((0, 0)));
+    }
+
+    #[test]
+    fn caught_up_at_the_head() {
+        let cursor = RangeCursor::new(Some(100), 10, 0);
+        assert_eq!(cursor.next_range(100), None);
+        assert_eq!(cursor.next_range(99), None);
+        assert_eq!(cursor.next_range(101), Some((101, 101)));
+    }
+
+    #[test]
+    fn single_block_ranges() {
+        let mut cursor = RangeCursor::new(None, 1, 0);
+        let mut ranges = Vec::new();
+        while let Some((from, to)) = cursor.next_range(3) {
+            ranges.push((from, to));
+            cursor.advance(to);
+        }
+        assert_eq!(ranges, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+        // A zero size counts as 1
+        assert_eq!(RangeCursor::new(None, 0, 0).next_range(3), Some((0, 0)));
+    }
+
+    #[test]
+    fn ends_at_u64_max_without_overflowing() {
+        let mut cursor = RangeCursor::new(Some(u64::MAX - 3), 10, 0);
+        assert_eq!(cursor.next_range(u64::MAX), Some((u64::MAX - 2, u64::MAX)));
+        assert!(!cursor.reached(u64::MAX));
+        cursor.advance(u64::MAX);
+        assert_eq!(cursor.next_block(), None);
+        assert_eq!(cursor.next_range(u64::MAX), None);
+        assert!(cursor.reached(u64::MAX));
+        // A huge range size is capped by the head
+        let cursor = RangeCursor::new(Some(5), u64::MAX, 0);
+        assert_eq!(cursor.next_range(u64::MAX), Some((6, u64::MAX)));
+    }
+
+    #[test]
+    fn resumes_after_a_partial_advance() {
+        // A commit batch stored blocks 10..=14 of the range 10..=19
+        let mut cursor = RangeCursor::new(Some(9), 10, 0);
+        assert_eq!(cursor.next_range(100), Some((10, 19)));
+        cursor.advance(14);
+        assert_eq!(cursor.next_block(), Some(15));
+        assert_eq!(cursor.next_range(100), Some((15, 24)));
+        assert!(cursor.reached(14));
+        assert!(!cursor.reached(15));
+    }
+
+    fn chunks(from: u64, to: u64, size: u64) -> Vec<(u64, u64)> {
+        chunk_ranges(from, to, size).unwrap().collect()
+    }
+
+    #[test]
+    fn chunks_cover_the_range_exactly() {
+        assert_eq!(chunks(0, 9, 5), vec![(0, 4), (5, 9)]);
+        assert_eq!(chunks(0, 10, 5), vec![(0, 4), (5, 9), (10, 10)]);
+        assert_eq!(chunks(3, 3, 5), vec![(3, 3)]);
+        assert_eq!(chunks(1, 3, 1), vec![(1, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn size_larger_than_the_span_is_one_chunk() {
+        assert_eq!(chunks(10, 20, 1000), vec![(10, 20)]);
+        assert_eq!(
+            chunks(0, u64::MAX, u64::MAX),
+            vec![(0, u64::MAX - 1), (u64::MAX, u64::MAX)]
+        );
+        assert_eq!(chunks(1, u64::MAX, u64::MAX), vec![(1, u64::MAX)]);
+    }
+
+    #[test]
+    fn chunks_end_at_u64_max_without_overflowing() {
+        assert_eq!(
+            chunks(u64::MAX - 4, u64::MAX, 2),
+            vec![
+                (u64::MAX - 4, u64::MAX - 3),
+                (u64::MAX - 2, u64::MAX - 1),
+                (u64::MAX, u64::MAX),
+            ]
+        );
+        assert_eq!(chunks(u64::MAX, u64::MAX, 10), vec![(u64::MAX, u64::MAX)]);
+        assert_eq!(
+            chunks(u64::MAX - 1, u64::MAX, 2),
+            vec![(u64::MAX - 1, u64::MAX)]
+        );
+    }
+
+    #[test]
+    fn reversed_range_is_empty() {
+        assert!(chunks(10, 9, 5).is_empty());
+        assert!(chunks(u64::MAX, 0, 1).is_empty());
+    }
+
+    #[test]
+    fn zero_size_is_an_error() {
+        assert!(matches!(
+            chunk_ranges(0, 10, 0),
+            Err(IndexerError::Parse(_))
+        ));
+        // Even for an empty range
+        assert!(chunk_ranges(10, 0, 0).is_err());
+    }
+}
@@ -1,9 +1,69 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    backfill_progress (chain_id, from_block, to_block) {
+        chain_id -> Integer,
+        from_block -> BigInt,
+        to_block -> BigInt,
+        last_block -> BigInt,
+    }
+}
+
+diesel::table! {
+    balances (chain_id, token_address, address) {
+        chain_id -> Integer,
+        token_address -> Text,
+        address -> Text,
+        balance -> Text,
+    }
+}
+
+diesel::table! {
+    events (chain_id, tx_hash, log_index) {
+        chain_id -> Integer,
+        block_number -> BigInt,
+        tx_hash -> Text,
+        log_index -> BigInt,
+        event_name -> Text,
+        json_args -> Text,
+    }
+}
+
+diesel::table! {
+    failed_ranges (chain_id, from_block, to_block) {
+        chain_id -> Integer,
+        from_block -> BigInt,
+        to_block -> BigInt,
+        error -> Text,
+        failed_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    indexer_state (chain_id) {
+        chain_id -> Integer,
+        last_error -> Nullable<Text>,
+        last_error_at -> Nullable<BigInt>,
+        last_error_from_block -> Nullable<BigInt>,
+        last_error_to_block -> Nullable<BigInt>,
+    }
+}
+
 diesel::table! {
     sync (chain_id) {
         chain_id -> Integer,
         block_number -> BigInt,
+        updated_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    token_metadata (chain_id, token_address) {
+        chain_id -> Integer,
+        token_address -> Text,
+        symbol -> Text,
+        name -> Text,
+        decimals -> Nullable<Integer>,
     }
 }
 
@@ -17,7 +77,20 @@ diesel::table! {
         to_addr -> Text,
         value -> Text,
         log_index -> BigInt,
+        base_fee -> Nullable<BigInt>,
+        block_timestamp -> Nullable<BigInt>,
+        gas_used -> Nullable<BigInt>,
+        tx_status -> Nullable<Bool>,
     }
 }
 
-diesel::allow_tables_to_appear_in_same_query!(sync, transfers,);
+diesel::allow_tables_to_appear_in_same_query!(
+    backfill_progress,
+    balances,
+    events,
+    failed_ranges,
+    indexer_state,
+    sync,
+    token_metadata,
+    transfers,
+);
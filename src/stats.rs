@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Number of most recent ranges kept for the percentile summary
+const TIMINGS_CAPACITY: usize = 256;
+
+// Time spent on a single processed range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeTiming {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub fetch: Duration,  // RPC fetch + decode
+    pub insert: Duration, // DB transaction (inserts + sync pointer)
+}
+
+// p50/p95 of the recorded fetch and insert times
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingSummary {
+    pub ranges: usize,
+    pub fetch_p50: Duration,
+    pub fetch_p95: Duration,
+    pub insert_p50: Duration,
+    pub insert_p95: Duration,
+}
+
+// Ring buffer of the latest range timings, shared between the event loop and its embedder
+// Shows whether the RPC or the DB is the bottleneck when tuning RANGE_SIZE
+#[derive(Debug, Clone, Default)]
+pub struct RangeTimings(Arc<Mutex<VecDeque<RangeTiming>>>);
+
+impl RangeTimings {
+    pub fn record(&self, timing: RangeTiming) {
+        let mut timings = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if timings.len() == TIMINGS_CAPACITY {
+            timings.pop_front();
+        }
+        timings.push_back(timing);
+    }
+
+    // Recorded timings, oldest first
+    pub fn snapshot(&self) -> Vec<RangeTiming> {
+        let timings = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        timings.iter().copied().collect()
+    }
+
+    // Percentiles over the recorded timings, None if nothing was recorded yet
+    pub fn summary(&self) -> Option<TimingSummary> {
+        let timings = self.snapshot();
+        if timings.is_empty() {
+            return None;
+        }
+
+        let mut fetch: Vec<Duration> = timings.iter().map(|t| t.fetch).collect();
+        let mut insert: Vec<Duration> = timings.iter().map(|t| t.insert).collect();
+        fetch.sort_unstable();
+        insert.sort_unstable();
+
+        Some(TimingSummary {
+            ranges: timings.len(),
+            fetch_p50: percentile(&fetch, 50),
+            fetch_p95: percentile(&fetch, 95),
+            insert_p50: percentile(&insert, 50),
+            insert_p95: percentile(&insert, 95),
+        })
+    }
+}
+
+// Nearest-rank percentile of a sorted, non-empty slice
+fn percentile(sorted: &[Duration], pct: usize) -> Duration {
+    let rank = (pct * sorted.len()).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(block: u64, fetch_ms: u64, insert_ms: u64) -> RangeTiming {
+        RangeTiming {
+            from_block: block,
+            to_block: block,
+            fetch: Duration::from_millis(fetch_ms),
+            insert: Duration::from_millis(insert_ms),
+        }
+    }
+
+    #[test]
+    fn summary_reports_nearest_rank_percentiles() {
+        let timings = RangeTimings::default();
+        assert_eq!(timings.summary(), None);
+
+        for block in 1..=100 {
+            timings.record(timing(block, block, 101 - block));
+        }
+        let summary = timings.summary().unwrap();
+        assert_eq!(summary.ranges, 100);
+        assert_eq!(summary.fetch_p50, Duration::from_millis(50));
+        assert_eq!(summary.fetch_p95, Duration::from_millis(95));
+        assert_eq!(summary.insert_p50, Duration::from_millis(50));
+        assert_eq!(summary.insert_p95, Duration::from_millis(95));
+    }
+
+    #[test]
+    fn only_the_latest_ranges_are_kept() {
+        let timings = RangeTimings::default();
+        for block in 0..TIMINGS_CAPACITY as u64 + 10 {
+            timings.record(timing(block, 1, 1));
+        }
+        let kept = timings.snapshot();
+        assert_eq!(kept.len(), TIMINGS_CAPACITY);
+        assert_eq!(kept[0].from_block, 10);
+    }
+}
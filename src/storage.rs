@@ -0,0 +1,3465 @@
+use crate::indexer::{IndexerError, Result};
+use crate::schema;
+use crate::types::{EventChange, EventLog, TokenMetadata, TransferChange, TransferEvent};
+use alloy_primitives::{Address, B256, Keccak256, U256};
+use diesel::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+use tracing::warn;
+
+// Canonical storage encoding of a transfer value: the plain decimal string (no sign, no 0x,
+// no leading zeros) in a TEXT column. Decimal keeps the column readable and lets SQLite compare
+// equal values as equal strings; U256 never fits SQLite's native numeric types losslessly.
+pub fn value_to_storage(value: U256) -> String {
+    value.to_string()
+}
+
+// Parse a stored transfer value, rejecting anything that isn't the canonical decimal encoding
+// A sign is rejected too: values are unsigned, a "-1" in the column is corruption, not a debit
+pub fn value_from_storage(value: &str) -> Result<U256> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(IndexerError::Parse(format!(
+            "Invalid stored value '{}' (expected a decimal integer)",
+            value
+        )));
+    }
+
+    U256::from_str_radix(value, 10)
+        .map_err(|e| IndexerError::Parse(format!("Invalid stored value '{}': {:?}", value, e)))
+}
+
+// Block numbers are u64 on chain but stored in BigInt (i64) columns
+// A block past i64::MAX is rejected instead of being cast into a negative number, which would
+// silently sort before every other row and break the pointer arithmetic
+pub fn block_to_storage(block_number: u64) -> Result<i64> {
+    u64_to_storage(block_number, "Block number")
+}
+
+// Any other u64 stored in a BigInt column (log index, base fee, timestamp, gas used), checked
+// like block numbers
+pub fn u64_to_storage(value: u64, what: &str) -> Result<i64> {
+    i64::try_from(value).map_err(|_| {
+        IndexerError::Overflow(format!(
+            "{} {} is out of range for storage (max {})",
+            what,
+            value,
+            i64::MAX
+        ))
+    })
+}
+
+// A BigInt column read back as u64; a negative value can only come from a corrupt or foreign row
+pub fn u64_from_storage(value: i64, what: &str) -> Result<u64> {
+    u64::try_from(value)
+        .map_err(|_| IndexerError::Overflow(format!("Stored {} {} is negative", what, value)))
+}
+
+// Chain ids are u64 but stored in Integer (i32) columns, checked the same way
+pub fn chain_to_storage(chain_id: u64) -> Result<i32> {
+    i32::try_from(chain_id).map_err(|_| {
+        IndexerError::Overflow(format!(
+            "Chain id {} is out of range for storage (max {})",
+            chain_id,
+            i32::MAX
+        ))
+    })
+}
+
+// A stored chain id read back as u64
+fn chain_from_storage(chain_id: i32) -> Result<u64> {
+    u64::try_from(chain_id)
+        .map_err(|_| IndexerError::Overflow(format!("Stored chain id {} is negative", chain_id)))
+}
+
+// Row representation of a transfer in the `transfers` table
+#[derive(Insertable)]
+#[diesel(table_name = schema::transfers)]
+pub struct NewTransfer {
+    pub chain_id: i32,
+    pub block_number: i64,
+    pub tx_hash: String,
+    pub token_address: String,
+    pub from_addr: String,
+    pub to_addr: String,
+    pub value: String,
+    pub log_index: i64,
+    pub base_fee: Option<i64>,
+    pub block_timestamp: Option<i64>,
+    pub gas_used: Option<i64>,
+    pub tx_status: Option<bool>,
+}
+
+impl TryFrom<&TransferEvent> for NewTransfer {
+    type Error = IndexerError;
+
+    fn try_from(event: &TransferEvent) -> Result<Self> {
+        Ok(NewTransfer {
+            chain_id: chain_to_storage(event.chain_id)?,
+            block_number: block_to_storage(event.block_number)?,
+            tx_hash: format!("{:#x}", event.tx_hash),
+            token_address: format!("{:#x}", event.token_address),
+            from_addr: format!("{:#x}", event.from_addr),
+            to_addr: format!("{:#x}", event.to_addr),
+            value: value_to_storage(event.value),
+            log_index: u64_to_storage(event.log_index, "Log index")?,
+            base_fee: event
+                .base_fee
+                .map(|fee| u64_to_storage(fee, "Base fee"))
+                .transpose()?,
+            block_timestamp: event
+                .block_timestamp
+                .map(|ts| u64_to_storage(ts, "Block timestamp"))
+                .transpose()?,
+            gas_used: event
+                .gas_used
+                .map(|gas| u64_to_storage(gas, "Gas used"))
+                .transpose()?,
+            tx_status: event.tx_status,
+        })
+    }
+}
+
+// Row of the `transfers` table as stored (hex strings and decimal value)
+// Also loads rows of the raw SQL tables (per-token and partitions), which share its columns
+#[derive(Queryable, QueryableByName, Selectable, Debug, Clone)]
+#[diesel(table_name = schema::transfers)]
+pub struct TransferRow {
+    pub chain_id: i32,
+    pub block_number: i64,
+    pub tx_hash: String,
+    pub token_address: String,
+    pub from_addr: String,
+    pub to_addr: String,
+    pub value: String,
+    pub log_index: i64,
+    pub base_fee: Option<i64>,
+    pub block_timestamp: Option<i64>,
+    pub gas_used: Option<i64>,
+    pub tx_status: Option<bool>,
+}
+
+impl TryFrom<TransferRow> for TransferEvent {
+    type Error = IndexerError;
+
+    fn try_from(row: TransferRow) -> Result<Self> {
+        let parse_err = |field: &str, e: &dyn std::fmt::Debug| {
+            IndexerError::Parse(format!("Invalid {} in transfers row: {:?}", field, e))
+        };
+
+        Ok(TransferEvent {
+            chain_id: chain_from_storage(row.chain_id)?,
+            block_number: u64_from_storage(row.block_number, "block number")?,
+            tx_hash: row.tx_hash.parse().map_err(|e| parse_err("tx_hash", &e))?,
+            token_address: row
+                .token_address
+                .parse()
+                .map_err(|e| parse_err("token_address", &e))?,
+            from_addr: row
+                .from_addr
+                .parse()
+                .map_err(|e| parse_err("from_addr", &e))?,
+            to_addr: row.to_addr.parse().map_err(|e| parse_err("to_addr", &e))?,
+            value: value_from_storage(&row.value)?,
+            log_index: u64_from_storage(row.log_index, "log index")?,
+            base_fee: row
+                .base_fee
+                .map(|fee| u64_from_storage(fee, "base fee"))
+                .transpose()?,
+            block_timestamp: row
+                .block_timestamp
+                .map(|ts| u64_from_storage(ts, "block timestamp"))
+                .transpose()?,
+            gas_used: row
+                .gas_used
+                .map(|gas| u64_from_storage(gas, "gas used"))
+                .transpose()?,
+            tx_status: row.tx_status,
+        })
+    }
+}
+
+// sync.block_number of a chain seeded at block 0: no block has been processed yet
+const BEFORE_GENESIS: i64 = -1;
+
+// Seed the sync pointer so that indexing begins exactly at `start_block`
+// The pointer is the last processed block, so it is stored as start_block - 1 and reads back as
+// Some(start_block - 1), or as None for start_block 0 (BEFORE_GENESIS)
+pub fn seed_sync_pointer(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    start_block: u64,
+) -> Result<()> {
+    let pointer = seeded_pointer(start_block)?;
+    let updated_at = unix_now();
+    // REPLACE rather than an upsert, which SQLite refuses on the view of use_table_prefix
+    diesel::replace_into(schema::sync::table)
+        .values((
+            schema::sync::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::sync::block_number.eq(pointer),
+            schema::sync::updated_at.eq(updated_at),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// sync.block_number written by seed_sync_pointer for `start_block`
+pub fn seeded_pointer(start_block: u64) -> Result<i64> {
+    match start_block.checked_sub(1) {
+        Some(pointer) => block_to_storage(pointer),
+        None => Ok(BEFORE_GENESIS),
+    }
+}
+
+// sync.block_number of a chain as stored (BEFORE_GENESIS included), None without a sync row
+pub fn stored_sync_pointer(conn: &mut SqliteConnection, chain_id: u64) -> Result<Option<i64>> {
+    Ok(schema::sync::table
+        .filter(schema::sync::chain_id.eq(chain_to_storage(chain_id)?))
+        .select(schema::sync::block_number)
+        .first::<i64>(conn)
+        .optional()?)
+}
+
+// Get the last fully indexed block for a chain
+// Returns None if nothing has been indexed yet: no sync row, or a row seeded at block 0
+// (BEFORE_GENESIS). Either way the event loop then starts at block 0.
+pub fn get_last_synced_block(conn: &mut SqliteConnection, chain_id: u64) -> Result<Option<u64>> {
+    let block_number = stored_sync_pointer(conn, chain_id)?;
+    Ok(block_number.and_then(|block| u64::try_from(block).ok()))
+}
+
+// Move the sync pointer of a chain to the given block, stamping sync.updated_at with the
+// current time
+pub fn set_last_synced_block(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    block_number: u64,
+) -> Result<()> {
+    let block_number = block_to_storage(block_number)?;
+    let updated_at = unix_now();
+    // REPLACE rather than an upsert, which SQLite refuses on the view of use_table_prefix
+    diesel::replace_into(schema::sync::table)
+        .values((
+            schema::sync::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::sync::block_number.eq(block_number),
+            schema::sync::updated_at.eq(updated_at),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// Unix time at which the sync pointer of a chain last moved
+// None if there is no sync row, or it was last written before sync.updated_at existed.
+// Staleness can be checked from the database alone: now - updated_at is how long the indexer
+// has made no progress, whichever process wrote it.
+pub fn get_sync_updated_at(conn: &mut SqliteConnection, chain_id: u64) -> Result<Option<u64>> {
+    let updated_at = schema::sync::table
+        .filter(schema::sync::chain_id.eq(chain_to_storage(chain_id)?))
+        .select(schema::sync::updated_at)
+        .first::<Option<i64>>(conn)
+        .optional()?;
+
+    updated_at
+        .flatten()
+        .map(|ts| u64_from_storage(ts, "updated_at"))
+        .transpose()
+}
+
+// Insert transfers, ignoring rows that are already stored
+// Re-processing a range is therefore safe; returns the number of newly inserted rows
+// Diesel can't combine batch inserts with ON CONFLICT on SQLite, so rows are inserted one by one
+// (callers wrap this in a transaction, which keeps it fast)
+pub fn insert_transfers(conn: &mut SqliteConnection, transfers: &[TransferEvent]) -> Result<usize> {
+    let mut inserted = 0;
+    for transfer in transfers {
+        if insert_transfer(conn, transfer)? {
+            inserted += 1;
+        }
+    }
+
+    Ok(inserted)
+}
+
+// Insert a single transfer unless it is already stored; returns whether a row was inserted
+//
+// The conflict target is the primary key (chain_id, tx_hash, log_index): the on-chain identity
+// of a log. token_address is deliberately not part of it, since a log (and thus its emitter) is
+// fully identified by its transaction and index. A second row with the same key but a different
+// token can only come from synthetic or corrupted data; the stored row wins and a warning is
+// logged instead of silently keeping both.
+fn insert_transfer(conn: &mut SqliteConnection, transfer: &TransferEvent) -> Result<bool> {
+    let row = NewTransfer::try_from(transfer)?;
+    let inserted = diesel::insert_into(schema::transfers::table)
+        .values(&row)
+        .on_conflict((
+            schema::transfers::chain_id,
+            schema::transfers::tx_hash,
+            schema::transfers::log_index,
+        ))
+        .do_nothing()
+        .execute(conn)?;
+
+    if inserted == 0 {
+        let stored_token = schema::transfers::table
+            .filter(schema::transfers::chain_id.eq(row.chain_id))
+            .filter(schema::transfers::tx_hash.eq(&row.tx_hash))
+            .filter(schema::transfers::log_index.eq(row.log_index))
+            .select(schema::transfers::token_address)
+            .first::<String>(conn)?;
+        if stored_token != row.token_address {
+            warn!(
+                "Transfer {}#{} is already stored for token {}, ignoring the one from token {}",
+                row.tx_hash, row.log_index, stored_token, row.token_address
+            );
+        }
+    }
+
+    Ok(inserted > 0)
+}
+
+// Delete the row of a transfer by its key (chain_id, tx_hash, log_index)
+// Returns whether a row was deleted
+pub fn delete_transfer(conn: &mut SqliteConnection, transfer: &TransferEvent) -> Result<bool> {
+    let deleted = diesel::delete(
+        schema::transfers::table
+            .filter(schema::transfers::chain_id.eq(chain_to_storage(transfer.chain_id)?))
+            .filter(schema::transfers::tx_hash.eq(format!("{:#x}", transfer.tx_hash)))
+            .filter(
+                schema::transfers::log_index.eq(u64_to_storage(transfer.log_index, "Log index")?),
+            ),
+    )
+    .execute(conn)?;
+
+    Ok(deleted > 0)
+}
+
+// Where transfers are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransferTables {
+    // Every token in the `transfers` table
+    #[default]
+    Shared,
+    // One `transfers_<token address>` table per token, created on first use (TABLE_PER_TOKEN)
+    PerToken,
+    // One `transfers_blocks_<first block>` table per `blocks` blocks, created on first use and
+    // unioned by the PARTITIONS_VIEW view (PARTITION_BLOCKS)
+    Partitioned {
+        blocks: u64,
+    },
+}
+
+// View over every partition table of TransferTables::Partitioned, recreated with each new one
+pub const PARTITIONS_VIEW: &str = "transfers_partitioned";
+
+// Prefix of the partition tables, followed by the first block of the partition
+const PARTITION_PREFIX: &str = "transfers_blocks_";
+
+// Name of the table holding the transfers of a token in TransferTables::PerToken mode
+// Suffixed by the lowercase address (no 0x) rather than the symbol, which is neither unique nor
+// trusted; the name therefore only ever contains [a-z0-9_] and is safe to splice into SQL.
+pub fn token_table_name(token: Address) -> String {
+    format!("transfers_{}", hex::encode(token))
+}
+
+// Name of the partition table holding `block_number` with partitions of `blocks` blocks
+// Named after the partition's first block, e.g. `transfers_blocks_18000000`.
+pub fn partition_table_name(block_number: u64, blocks: u64) -> String {
+    let blocks = blocks.max(1);
+    format!("{}{}", PARTITION_PREFIX, block_number / blocks * blocks)
+}
+
+// Table a transfer is written to (and deleted from) with these options
+fn transfer_table_name(write: &WriteOptions, transfer: &TransferEvent) -> String {
+    match write.tables {
+        TransferTables::Shared => prefixed_name(&write.table_prefix, "transfers"),
+        TransferTables::PerToken => token_table_name(transfer.token_address),
+        TransferTables::Partitioned { blocks } => {
+            partition_table_name(transfer.block_number, blocks)
+        }
+    }
+}
+
+// Create the per-token table (same layout and key as `transfers`) unless it already exists
+// These tables are not part of the Diesel schema, so they are queried with raw SQL.
+pub fn create_token_table(conn: &mut SqliteConnection, token: Address) -> Result<()> {
+    create_transfers_table(conn, &token_table_name(token))?;
+    Ok(())
+}
+
+// Create a partition table unless it already exists, then recreate PARTITIONS_VIEW over every
+// partition so queries through the view see the new one
+// The view is also recreated when an existing partition gets new columns; every partition is
+// then brought up to date, so the UNION ALL of the view lines up.
+pub fn create_partition_table(conn: &mut SqliteConnection, table: &str) -> Result<()> {
+    if !create_transfers_table(conn, table)? {
+        return Ok(());
+    }
+
+    let partitions = partition_tables(conn)?;
+    for partition in &partitions {
+        create_transfers_table(conn, partition)?;
+    }
+    // Columns are listed rather than `*`, so the view doesn't depend on the column order of
+    // partitions created by different versions
+    let columns = transfer_column_list();
+    let selects: Vec<String> = partitions
+        .iter()
+        .map(|name| format!("SELECT {} FROM {}", columns, name))
+        .collect();
+    diesel::sql_query(format!("DROP VIEW IF EXISTS {}", PARTITIONS_VIEW)).execute(conn)?;
+    diesel::sql_query(format!(
+        "CREATE VIEW {} ({}) AS {}",
+        PARTITIONS_VIEW,
+        columns,
+        selects.join(" UNION ALL ")
+    ))
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct TableName {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    name: String,
+}
+
+// Tables created by the migrations. `sync` and `transfers` are renamed by TABLE_PREFIX (see
+// use_table_prefix), the others keep these names.
+pub const MIGRATED_TABLES: [&str; 8] = [
+    "sync",
+    "transfers",
+    "failed_ranges",
+    "backfill_progress",
+    "token_metadata",
+    "indexer_state",
+    "balances",
+    "events",
+];
+
+// Indexes created by the migrations; they share one namespace with the tables
+pub const MIGRATED_INDEXES: [&str; 7] = [
+    "idx_block",
+    "idx_token",
+    "idx_from",
+    "idx_to",
+    "idx_events_block",
+    "idx_events_name",
+    "idx_transfers_timestamp",
+];
+
+// Names TABLE_PREFIX applies to: `sync`, `transfers` (and the `transfers_new` a migration rebuilds
+// it through) and every index of the migrations
+fn is_prefixed_name(name: &str) -> bool {
+    matches!(name, "sync" | "transfers" | "transfers_new") || name.starts_with("idx_")
+}
+
+// Name of a migrated table or index under TABLE_PREFIX, e.g. `app_transfers` for `transfers`
+pub fn prefixed_name(prefix: &str, name: &str) -> String {
+    if is_prefixed_name(name) {
+        format!("{}{}", prefix, name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Names the indexer creates in a database with this TABLE_PREFIX: the migrated tables and indexes,
+// and without a prefix the per-token and partition tables, their indexes and PARTITIONS_VIEW
+// (TABLE_PER_TOKEN and PARTITION_BLOCKS can't be combined with a prefix)
+fn is_indexer_name(prefix: &str, name: &str) -> bool {
+    let migrated = MIGRATED_TABLES
+        .iter()
+        .chain(&["transfers_new"])
+        .chain(&MIGRATED_INDEXES)
+        .any(|migrated| prefixed_name(prefix, migrated) == name);
+    let split_index = TRANSFER_INDEXES.iter().any(|(suffix, _)| {
+        name.strip_prefix("idx_")
+            .and_then(|name| name.strip_suffix(suffix))
+            .and_then(|name| name.strip_suffix('_'))
+            .is_some_and(is_split_transfers_table)
+    });
+    let split = is_split_transfers_table(name) || name == PARTITIONS_VIEW || split_index;
+    migrated || (prefix.is_empty() && split)
+}
+
+// Tables, views and indexes of a database the indexer has never migrated that clash with the
+// names it creates under this TABLE_PREFIX, i.e. another application's in a shared file. Empty
+// once the migrations table exists.
+pub fn conflicting_tables(conn: &mut SqliteConnection, prefix: &str) -> Result<Vec<String>> {
+    let tables: Vec<String> = diesel::sql_query(
+        "SELECT name FROM sqlite_master WHERE type IN ('table', 'view', 'index')",
+    )
+    .load::<TableName>(conn)?
+    .into_iter()
+    .map(|table| table.name)
+    .collect();
+    if tables
+        .iter()
+        .any(|name| name == "__diesel_schema_migrations")
+    {
+        return Ok(Vec::new());
+    }
+    Ok(tables
+        .into_iter()
+        .filter(|name| is_indexer_name(prefix, name))
+        .collect())
+}
+
+// up.sql of every migration by name, to run them under a TABLE_PREFIX (EmbeddedMigrations doesn't
+// expose its SQL). A new migration is added here too.
+const MIGRATION_SQL: [(&str, &str); 13] = [
+    (
+        "2024-01-01-000001_initial",
+        include_str!("../migrations/2024-01-01-000001_initial/up.sql"),
+    ),
+    (
+        "2026-10-14-000001_failed_ranges",
+        include_str!("../migrations/2026-10-14-000001_failed_ranges/up.sql"),
+    ),
+    (
+        "2026-10-14-000002_transfers_value_text",
+        include_str!("../migrations/2026-10-14-000002_transfers_value_text/up.sql"),
+    ),
+    (
+        "2026-10-14-000003_transfers_base_fee",
+        include_str!("../migrations/2026-10-14-000003_transfers_base_fee/up.sql"),
+    ),
+    (
+        "2026-10-15-000001_backfill_progress",
+        include_str!("../migrations/2026-10-15-000001_backfill_progress/up.sql"),
+    ),
+    (
+        "2026-10-15-000002_token_metadata",
+        include_str!("../migrations/2026-10-15-000002_token_metadata/up.sql"),
+    ),
+    (
+        "2026-10-15-000003_indexer_state",
+        include_str!("../migrations/2026-10-15-000003_indexer_state/up.sql"),
+    ),
+    (
+        "2026-10-15-000004_balances",
+        include_str!("../migrations/2026-10-15-000004_balances/up.sql"),
+    ),
+    (
+        "2026-10-15-000005_events",
+        include_str!("../migrations/2026-10-15-000005_events/up.sql"),
+    ),
+    (
+        "2026-10-15-000006_transfers_block_timestamp",
+        include_str!("../migrations/2026-10-15-000006_transfers_block_timestamp/up.sql"),
+    ),
+    (
+        "2026-10-15-000007_token_metadata_decimals",
+        include_str!("../migrations/2026-10-15-000007_token_metadata_decimals/up.sql"),
+    ),
+    (
+        "2026-10-15-000008_sync_updated_at",
+        include_str!("../migrations/2026-10-15-000008_sync_updated_at/up.sql"),
+    ),
+    (
+        "2026-10-15-000009_transfers_receipt",
+        include_str!("../migrations/2026-10-15-000009_transfers_receipt/up.sql"),
+    ),
+];
+
+// Migration SQL with every identifier passed through prefixed_name (comments included, which is
+// harmless). The migrations only use plain [A-Za-z0-9_] identifiers, and a prefix is one too.
+fn prefixed_sql(sql: &str, prefix: &str) -> String {
+    let mut prefixed = String::with_capacity(sql.len());
+    let mut word = String::new();
+    for c in sql.chars().chain(std::iter::once('\n')) {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            word.push(c);
+            continue;
+        }
+        prefixed.push_str(&prefixed_name(prefix, &word));
+        word.clear();
+        prefixed.push(c);
+    }
+    prefixed.pop();
+    prefixed
+}
+
+// The migrations of crate::MIGRATIONS with `sync`, `transfers` and the indexes renamed by a
+// TABLE_PREFIX. They are recorded under the same versions, so a database is migrated under one
+// prefix only (use_table_prefix checks it).
+pub struct PrefixedMigrations<'a> {
+    pub prefix: &'a str,
+}
+
+impl diesel::migration::MigrationSource<diesel::sqlite::Sqlite> for PrefixedMigrations<'_> {
+    fn migrations(
+        &self,
+    ) -> diesel::migration::Result<Vec<Box<dyn diesel::migration::Migration<diesel::sqlite::Sqlite>>>>
+    {
+        let embedded = diesel::migration::MigrationSource::<diesel::sqlite::Sqlite>::migrations(
+            &crate::MIGRATIONS,
+        )?;
+        embedded
+            .into_iter()
+            .map(|migration| {
+                let name = migration.name().to_string();
+                let (_, sql) = MIGRATION_SQL
+                    .iter()
+                    .find(|(migration_name, _)| *migration_name == name)
+                    .ok_or_else(|| format!("No SQL for migration {} in MIGRATION_SQL", name))?;
+                let prefixed: Box<dyn diesel::migration::Migration<diesel::sqlite::Sqlite>> =
+                    Box::new(PrefixedMigration {
+                        migration,
+                        sql: prefixed_sql(sql, self.prefix),
+                    });
+                Ok(prefixed)
+            })
+            .collect()
+    }
+}
+
+struct PrefixedMigration {
+    migration: Box<dyn diesel::migration::Migration<diesel::sqlite::Sqlite>>,
+    sql: String,
+}
+
+impl diesel::migration::Migration<diesel::sqlite::Sqlite> for PrefixedMigration {
+    fn run(
+        &self,
+        conn: &mut dyn diesel::connection::BoxableConnection<diesel::sqlite::Sqlite>,
+    ) -> diesel::migration::Result<()> {
+        conn.batch_execute(&self.sql)?;
+        Ok(())
+    }
+
+    fn revert(
+        &self,
+        _conn: &mut dyn diesel::connection::BoxableConnection<diesel::sqlite::Sqlite>,
+    ) -> diesel::migration::Result<()> {
+        Err("Migrations under a TABLE_PREFIX can't be reverted".into())
+    }
+
+    fn metadata(&self) -> &dyn diesel::migration::MigrationMetadata {
+        self.migration.metadata()
+    }
+
+    fn name(&self) -> &dyn diesel::migration::MigrationName {
+        self.migration.name()
+    }
+}
+
+// Columns of `sync`, for the INSTEAD OF trigger of use_table_prefix
+// Must follow the migrations like TRANSFER_COLUMNS.
+const SYNC_COLUMNS: [&str; 3] = ["chain_id", "block_number", "updated_at"];
+
+// Check that `sync` and `transfers` under this TABLE_PREFIX are the indexer's tables, then make
+// this connection use them. TEMP views named `sync` and `transfers` shadow the tables of `main`
+// (SQLite resolves an unqualified name in `temp` first), so every query of the Diesel schema
+// reads the prefixed tables. Writes to `sync` go through an INSTEAD OF trigger; transfers are
+// written to the prefixed table by name (WriteOptions::table_prefix), since rows a trigger writes
+// don't count as changes. Without a prefix only the check is done.
+pub fn use_table_prefix(conn: &mut SqliteConnection, prefix: &str) -> Result<()> {
+    for (table, columns) in [
+        ("sync", SYNC_COLUMNS.to_vec()),
+        ("transfers", TRANSFER_COLUMNS.map(|(name, _)| name).to_vec()),
+    ] {
+        let table = prefixed_name(prefix, table);
+        let existing: Vec<String> = diesel::sql_query(format!(
+            "SELECT name FROM pragma_table_info('{}', 'main')",
+            table
+        ))
+        .load::<TableName>(conn)?
+        .into_iter()
+        .map(|column| column.name)
+        .collect();
+        if let Some(missing) = columns
+            .iter()
+            .find(|column| !existing.iter().any(|name| name == *column))
+        {
+            return Err(IndexerError::Unsupported(format!(
+                "{} is not the indexer's table (no {} column): TABLE_PREFIX must stay the one the database was first migrated with",
+                table, missing
+            )));
+        }
+    }
+    if prefix.is_empty() {
+        return Ok(());
+    }
+
+    for table in ["sync", "transfers"] {
+        diesel::sql_query(format!(
+            "CREATE TEMP VIEW IF NOT EXISTS {} AS SELECT * FROM main.{}",
+            table,
+            prefixed_name(prefix, table)
+        ))
+        .execute(conn)?;
+    }
+    // Statements inside a trigger can't name the schema; nothing in `temp` has the prefixed name
+    let columns = SYNC_COLUMNS.join(", ");
+    let values: Vec<String> = SYNC_COLUMNS
+        .iter()
+        .map(|column| format!("NEW.{}", column))
+        .collect();
+    diesel::sql_query(format!(
+        "CREATE TEMP TRIGGER IF NOT EXISTS sync_insert INSTEAD OF INSERT ON sync BEGIN \
+         INSERT INTO {} ({}) VALUES ({}); END",
+        prefixed_name(prefix, "sync"),
+        columns,
+        values.join(", ")
+    ))
+    .execute(conn)?;
+    Ok(())
+}
+
+// Names of the existing partition tables, in block order
+pub fn partition_tables(conn: &mut SqliteConnection) -> Result<Vec<String>> {
+    // LIKE treats `_` as a wildcard, so the names are checked again when parsing the block
+    let mut tables: Vec<(u64, String)> = diesel::sql_query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'transfers_blocks_%'",
+    )
+    .load::<TableName>(conn)?
+    .into_iter()
+    .filter_map(|table| {
+        let first_block = table.name.strip_prefix(PARTITION_PREFIX)?.parse().ok()?;
+        Some((first_block, table.name))
+    })
+    .collect();
+    tables.sort();
+    Ok(tables.into_iter().map(|(_, name)| name).collect())
+}
+
+// Whether a table holds transfers outside `transfers`: a per-token table (TransferTables::PerToken)
+// or a partition (TransferTables::Partitioned)
+fn is_split_transfers_table(name: &str) -> bool {
+    let token_table = name.strip_prefix("transfers_").is_some_and(|suffix| {
+        suffix.len() == 40
+            && suffix
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+    });
+    let partition = name
+        .strip_prefix(PARTITION_PREFIX)
+        .is_some_and(|block| block.parse::<u64>().is_ok());
+    token_table || partition
+}
+
+// Fail when transfers are stored outside `transfers` (TABLE_PER_TOKEN or PARTITION_BLOCKS), for
+// the queries that only read the shared table and would silently miss them
+fn require_shared_transfers(conn: &mut SqliteConnection) -> Result<()> {
+    let split = diesel::sql_query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'transfers_%'",
+    )
+    .load::<TableName>(conn)?
+    .into_iter()
+    .find(|table| is_split_transfers_table(&table.name));
+    match split {
+        Some(table) => Err(IndexerError::Unsupported(format!(
+            "transfers are stored in per-token or partition tables (e.g. {}), this query only reads the shared transfers table",
+            table.name
+        ))),
+        None => Ok(()),
+    }
+}
+
+// Columns of `transfers` (name and SQL type), in order, for the raw SQL tables
+// Must follow the migrations: a column added to `transfers` is added here too.
+const TRANSFER_COLUMNS: [(&str, &str); 12] = [
+    ("chain_id", "INTEGER NOT NULL"),
+    ("block_number", "INTEGER NOT NULL"),
+    ("tx_hash", "CHAR(66) NOT NULL"),
+    ("token_address", "CHAR(42) NOT NULL"),
+    ("from_addr", "CHAR(42) NOT NULL"),
+    ("to_addr", "CHAR(42) NOT NULL"),
+    ("value", "TEXT NOT NULL"),
+    ("log_index", "INTEGER NOT NULL"),
+    ("base_fee", "INTEGER"),
+    ("block_timestamp", "INTEGER"),
+    ("gas_used", "INTEGER"),
+    ("tx_status", "BOOLEAN"),
+];
+
+// Indexes of `transfers` (name suffix and columns), created on the raw SQL tables too
+const TRANSFER_INDEXES: [(&str, &str); 5] = [
+    ("block", "chain_id, block_number"),
+    ("token", "chain_id, token_address"),
+    ("from", "from_addr"),
+    ("to", "to_addr"),
+    ("timestamp", "chain_id, block_timestamp"),
+];
+
+// Comma-separated TRANSFER_COLUMNS names
+fn transfer_column_list() -> String {
+    TRANSFER_COLUMNS.map(|(name, _)| name).join(", ")
+}
+
+// Create a table with the columns, key and indexes of the `transfers` table unless it exists
+// A table created by an older version gets the (nullable) columns added since then. Returns
+// whether the table was created or changed.
+fn create_transfers_table(conn: &mut SqliteConnection, table: &str) -> Result<bool> {
+    let existing: Vec<String> =
+        diesel::sql_query(format!("SELECT name FROM pragma_table_info('{}')", table))
+            .load::<TableName>(conn)?
+            .into_iter()
+            .map(|column| column.name)
+            .collect();
+    let changed = if existing.is_empty() {
+        let columns: Vec<String> = TRANSFER_COLUMNS
+            .iter()
+            .map(|(name, sql_type)| format!("{} {}", name, sql_type))
+            .collect();
+        diesel::sql_query(format!(
+            "CREATE TABLE {} ({}, PRIMARY KEY (chain_id, tx_hash, log_index))",
+            table,
+            columns.join(", ")
+        ))
+        .execute(conn)?;
+        true
+    } else {
+        let mut added = false;
+        for (name, sql_type) in TRANSFER_COLUMNS {
+            if !existing.iter().any(|column| column == name) {
+                diesel::sql_query(format!(
+                    "ALTER TABLE {} ADD COLUMN {} {}",
+                    table, name, sql_type
+                ))
+                .execute(conn)?;
+                added = true;
+            }
+        }
+        added
+    };
+    for (suffix, columns) in TRANSFER_INDEXES {
+        diesel::sql_query(format!(
+            "CREATE INDEX IF NOT EXISTS idx_{}_{} ON {}({})",
+            table, suffix, table, columns
+        ))
+        .execute(conn)?;
+    }
+
+    Ok(changed)
+}
+
+// Insert a transfer into a raw SQL table (per-token or partition) unless it is already stored
+// Returns whether a row was inserted
+fn insert_table_transfer(
+    conn: &mut SqliteConnection,
+    table: &str,
+    transfer: &TransferEvent,
+) -> Result<bool> {
+    use diesel::sql_types::{BigInt, Bool, Integer, Nullable, Text};
+
+    let row = NewTransfer::try_from(transfer)?;
+    let inserted = diesel::sql_query(format!(
+        "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (chain_id, tx_hash, log_index) DO NOTHING",
+        table,
+        transfer_column_list(),
+        ["?"; TRANSFER_COLUMNS.len()].join(", ")
+    ))
+    .bind::<Integer, _>(row.chain_id)
+    .bind::<BigInt, _>(row.block_number)
+    .bind::<Text, _>(row.tx_hash)
+    .bind::<Text, _>(row.token_address)
+    .bind::<Text, _>(row.from_addr)
+    .bind::<Text, _>(row.to_addr)
+    .bind::<Text, _>(row.value)
+    .bind::<BigInt, _>(row.log_index)
+    .bind::<Nullable<BigInt>, _>(row.base_fee)
+    .bind::<Nullable<BigInt>, _>(row.block_timestamp)
+    .bind::<Nullable<BigInt>, _>(row.gas_used)
+    .bind::<Nullable<Bool>, _>(row.tx_status)
+    .execute(conn)?;
+
+    Ok(inserted > 0)
+}
+
+// Delete a transfer from a raw SQL table by its key (chain_id, tx_hash, log_index)
+// Returns whether a row was deleted
+fn delete_table_transfer(
+    conn: &mut SqliteConnection,
+    table: &str,
+    transfer: &TransferEvent,
+) -> Result<bool> {
+    use diesel::sql_types::{BigInt, Integer, Text};
+
+    let deleted = diesel::sql_query(format!(
+        "DELETE FROM {} WHERE chain_id = ? AND tx_hash = ? AND log_index = ?",
+        table
+    ))
+    .bind::<Integer, _>(chain_to_storage(transfer.chain_id)?)
+    .bind::<Text, _>(format!("{:#x}", transfer.tx_hash))
+    .bind::<BigInt, _>(u64_to_storage(transfer.log_index, "Log index")?)
+    .execute(conn)?;
+
+    Ok(deleted > 0)
+}
+
+// How applied transfer changes are written
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WriteOptions {
+    pub tables: TransferTables, // Shared `transfers` table or one table per token
+    pub balances: bool,         // Maintain the `balances` table along with the transfers
+    pub table_prefix: String,   // TABLE_PREFIX of the shared table, see use_table_prefix
+}
+
+// Balance movement applied to an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BalanceDelta {
+    Credit(U256),
+    Debit(U256),
+}
+
+// Current materialized balance of `address` for a token, zero if it never received anything
+pub fn get_balance(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: Address,
+    address: Address,
+) -> Result<U256> {
+    get_stored_balance(
+        conn,
+        chain_id,
+        &format!("{:#x}", token_address),
+        &format!("{:#x}", address),
+    )
+}
+
+fn get_stored_balance(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: &str,
+    address: &str,
+) -> Result<U256> {
+    let balance = schema::balances::table
+        .filter(schema::balances::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::balances::token_address.eq(token_address))
+        .filter(schema::balances::address.eq(address))
+        .select(schema::balances::balance)
+        .first::<String>(conn)
+        .optional()?;
+
+    balance.map_or(Ok(U256::ZERO), |balance| value_from_storage(&balance))
+}
+
+fn set_stored_balance(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: &str,
+    address: &str,
+    balance: U256,
+) -> Result<()> {
+    let balance = value_to_storage(balance);
+    diesel::insert_into(schema::balances::table)
+        .values((
+            schema::balances::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::balances::token_address.eq(token_address),
+            schema::balances::address.eq(address),
+            schema::balances::balance.eq(&balance),
+        ))
+        .on_conflict((
+            schema::balances::chain_id,
+            schema::balances::token_address,
+            schema::balances::address,
+        ))
+        .do_update()
+        .set(schema::balances::balance.eq(&balance))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// Apply a balance movement to a stored balance
+fn adjust_balance(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: &str,
+    address: &str,
+    delta: BalanceDelta,
+) -> Result<()> {
+    let balance = get_stored_balance(conn, chain_id, token_address, address)?;
+    let balance = apply_delta(balance, delta, token_address, address)?;
+    set_stored_balance(conn, chain_id, token_address, address, balance)
+}
+
+// New balance after a movement, in unsigned U256 arithmetic
+// Checked on both sides: a debit larger than the balance is reported as an underflow instead of
+// wrapping to a huge number (which a careless consumer could also misread as a negative value)
+fn apply_delta(balance: U256, delta: BalanceDelta, token: &str, address: &str) -> Result<U256> {
+    match delta {
+        BalanceDelta::Credit(amount) => balance.checked_add(amount).ok_or_else(|| {
+            IndexerError::Overflow(format!(
+                "Crediting {} to {} (balance {}) of token {} exceeds uint256",
+                amount, address, balance, token
+            ))
+        }),
+        BalanceDelta::Debit(amount) => {
+            balance
+                .checked_sub(amount)
+                .ok_or_else(|| IndexerError::BalanceUnderflow {
+                    token: token.to_string(),
+                    address: address.to_string(),
+                    balance,
+                    amount,
+                })
+        }
+    }
+}
+
+// Debit the sender and credit the receiver of a transfer (the reverse when `undo` is set, for
+// reorged transfers). The zero address is the mint source / burn sink and has no balance.
+fn apply_transfer_balances(
+    conn: &mut SqliteConnection,
+    transfer: &TransferEvent,
+    undo: bool,
+) -> Result<()> {
+    let token = format!("{:#x}", transfer.token_address);
+    let (from_delta, to_delta) = if undo {
+        (
+            BalanceDelta::Credit(transfer.value),
+            BalanceDelta::Debit(transfer.value),
+        )
+    } else {
+        (
+            BalanceDelta::Debit(transfer.value),
+            BalanceDelta::Credit(transfer.value),
+        )
+    };
+
+    if !transfer.from_addr.is_zero() {
+        let from = format!("{:#x}", transfer.from_addr);
+        adjust_balance(conn, transfer.chain_id, &token, &from, from_delta)?;
+    }
+    if !transfer.to_addr.is_zero() {
+        let to = format!("{:#x}", transfer.to_addr);
+        adjust_balance(conn, transfer.chain_id, &token, &to, to_delta)?;
+    }
+
+    Ok(())
+}
+
+// Recompute the `balances` of a chain from scratch out of the shared `transfers` table
+// Transfers are replayed in canonical (block_number, log_index) order; returns the number of
+// balances written. Callers should run it in a transaction.
+pub fn rebuild_balances(conn: &mut SqliteConnection, chain_id: u64) -> Result<usize> {
+    require_shared_transfers(conn)?;
+    diesel::delete(
+        schema::balances::table.filter(schema::balances::chain_id.eq(chain_to_storage(chain_id)?)),
+    )
+    .execute(conn)?;
+
+    let mut balances: HashMap<(String, String), U256> = HashMap::new();
+    let rows = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .order((
+            schema::transfers::block_number.asc(),
+            schema::transfers::log_index.asc(),
+        ))
+        .select(TransferRow::as_select())
+        .load_iter::<TransferRow, diesel::connection::DefaultLoadingMode>(conn)?;
+    for row in rows {
+        let transfer = TransferEvent::try_from(row?)?;
+        let token = format!("{:#x}", transfer.token_address);
+        for (address, delta) in [
+            (transfer.from_addr, BalanceDelta::Debit(transfer.value)),
+            (transfer.to_addr, BalanceDelta::Credit(transfer.value)),
+        ] {
+            if address.is_zero() {
+                continue;
+            }
+            let address = format!("{:#x}", address);
+            let balance = balances
+                .entry((token.clone(), address.clone()))
+                .or_default();
+            *balance = apply_delta(*balance, delta, &token, &address)?;
+        }
+    }
+
+    for ((token, address), balance) in &balances {
+        set_stored_balance(conn, chain_id, token, address, *balance)?;
+    }
+    Ok(balances.len())
+}
+
+// Rows affected by applying a batch of transfer changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AppliedChanges {
+    pub inserted: usize,
+    pub removed: usize,
+}
+
+// Apply fetched changes in order: insert added transfers (idempotently), delete removed ones
+// Order matters when a log is both reverted and re-included within the same batch
+// Balances (if enabled) only move for rows actually inserted or deleted, so re-processing a
+// range doesn't count its transfers twice
+pub fn apply_transfer_changes(
+    conn: &mut SqliteConnection,
+    changes: &[TransferChange],
+    write: &WriteOptions,
+) -> Result<AppliedChanges> {
+    let tables = write.tables;
+    // Through the Diesel schema unless the shared table is prefixed
+    let by_name = tables != TransferTables::Shared || !write.table_prefix.is_empty();
+    match tables {
+        TransferTables::Shared => {}
+        TransferTables::PerToken => {
+            let tokens: HashSet<Address> = changes
+                .iter()
+                .map(|change| change.event().token_address)
+                .collect();
+            for token in tokens {
+                create_token_table(conn, token)?;
+            }
+        }
+        TransferTables::Partitioned { .. } => {
+            let partitions: BTreeSet<String> = changes
+                .iter()
+                .map(|change| transfer_table_name(write, change.event()))
+                .collect();
+            for table in partitions {
+                create_partition_table(conn, &table)?;
+            }
+        }
+    }
+
+    let mut applied = AppliedChanges::default();
+    for change in changes {
+        match change {
+            TransferChange::Added(transfer) => {
+                let inserted = if by_name {
+                    insert_table_transfer(conn, &transfer_table_name(write, transfer), transfer)?
+                } else {
+                    insert_transfer(conn, transfer)?
+                };
+                if inserted {
+                    applied.inserted += 1;
+                    if write.balances {
+                        apply_transfer_balances(conn, transfer, false)?;
+                    }
+                }
+            }
+            TransferChange::Removed(transfer) => {
+                let removed = if by_name {
+                    delete_table_transfer(conn, &transfer_table_name(write, transfer), transfer)?
+                } else {
+                    delete_transfer(conn, transfer)?
+                };
+                if removed {
+                    applied.removed += 1;
+                    if write.balances {
+                        apply_transfer_balances(conn, transfer, true)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(applied)
+}
+
+// Row representation of a custom event in the `events` table
+#[derive(Insertable)]
+#[diesel(table_name = schema::events)]
+pub struct NewEvent {
+    pub chain_id: i32,
+    pub block_number: i64,
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub event_name: String,
+    pub json_args: String,
+}
+
+impl TryFrom<&EventLog> for NewEvent {
+    type Error = IndexerError;
+
+    fn try_from(event: &EventLog) -> Result<Self> {
+        Ok(NewEvent {
+            chain_id: chain_to_storage(event.chain_id)?,
+            block_number: block_to_storage(event.block_number)?,
+            tx_hash: format!("{:#x}", event.tx_hash),
+            log_index: u64_to_storage(event.log_index, "Log index")?,
+            event_name: event.event_name.clone(),
+            json_args: event.json_args.clone(),
+        })
+    }
+}
+
+// Apply fetched custom event changes in order, like apply_transfer_changes: insert added logs
+// (ignoring ones already stored) and delete reorged ones by (chain_id, tx_hash, log_index)
+pub fn apply_event_changes(
+    conn: &mut SqliteConnection,
+    changes: &[EventChange],
+) -> Result<AppliedChanges> {
+    let mut applied = AppliedChanges::default();
+    for change in changes {
+        match change {
+            EventChange::Added(event) => {
+                applied.inserted += diesel::insert_into(schema::events::table)
+                    .values(NewEvent::try_from(event)?)
+                    .on_conflict((
+                        schema::events::chain_id,
+                        schema::events::tx_hash,
+                        schema::events::log_index,
+                    ))
+                    .do_nothing()
+                    .execute(conn)?;
+            }
+            EventChange::Removed(event) => {
+                applied.removed += diesel::delete(
+                    schema::events::table
+                        .filter(schema::events::chain_id.eq(chain_to_storage(event.chain_id)?))
+                        .filter(schema::events::tx_hash.eq(format!("{:#x}", event.tx_hash)))
+                        .filter(
+                            schema::events::log_index
+                                .eq(u64_to_storage(event.log_index, "Log index")?),
+                        ),
+                )
+                .execute(conn)?;
+            }
+        }
+    }
+
+    Ok(applied)
+}
+
+// Transfers of a chain that can be considered final: block_number <= head - confirmations
+// Lets consumers read only reorg-safe data even when the indexer stores unconfirmed blocks
+// (CONFIRMATIONS=0). Returns at most `limit` rows in canonical (block_number, log_index) order,
+// starting after the `after` (block_number, log_index) position if given, so callers can page
+// through the result by passing the position of the last row they received.
+pub fn finalized_transfers(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    head: u64,
+    confirmations: u64,
+    after: Option<(u64, u64)>,
+    limit: usize,
+) -> Result<Vec<TransferEvent>> {
+    require_shared_transfers(conn)?;
+    // Nothing is final until the chain is deeper than the confirmation window
+    let Some(final_block) = head.checked_sub(confirmations) else {
+        return Ok(Vec::new());
+    };
+
+    let mut query = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::block_number.le(block_to_storage(final_block)?))
+        .order((
+            schema::transfers::block_number.asc(),
+            schema::transfers::log_index.asc(),
+        ))
+        .limit(limit as i64)
+        .select(TransferRow::as_select())
+        .into_boxed();
+    if let Some((block_number, log_index)) = after {
+        let block_number = block_to_storage(block_number)?;
+        query = query.filter(
+            schema::transfers::block_number
+                .gt(block_number)
+                .or(schema::transfers::block_number
+                    .eq(block_number)
+                    .and(schema::transfers::log_index.gt(u64_to_storage(log_index, "Log index")?))),
+        );
+    }
+
+    query
+        .load::<TransferRow>(conn)?
+        .into_iter()
+        .map(TransferEvent::try_from)
+        .collect()
+}
+
+// Transfers of a chain whose block timestamp is in [start_ts, end_ts) (Unix seconds), so
+// consecutive windows don't overlap. Only rows stored with ENRICH_TIMESTAMP have a timestamp;
+// the others never match. Paged like `finalized_transfers`: at most `limit` rows in
+// (block_number, log_index) order, starting after the `after` position if given.
+pub fn transfers_between(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    start_ts: u64,
+    end_ts: u64,
+    after: Option<(u64, u64)>,
+    limit: usize,
+) -> Result<Vec<TransferEvent>> {
+    require_shared_transfers(conn)?;
+    // Timestamps are stored as i64, clamp so a huge bound doesn't wrap negative
+    let clamp = |ts: u64| ts.min(i64::MAX as u64) as i64;
+    let mut query = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::block_timestamp.ge(clamp(start_ts)))
+        .filter(schema::transfers::block_timestamp.lt(clamp(end_ts)))
+        .order((
+            schema::transfers::block_number.asc(),
+            schema::transfers::log_index.asc(),
+        ))
+        .limit(limit as i64)
+        .select(TransferRow::as_select())
+        .into_boxed();
+    if let Some((block_number, log_index)) = after {
+        let block_number = block_to_storage(block_number)?;
+        query = query.filter(
+            schema::transfers::block_number
+                .gt(block_number)
+                .or(schema::transfers::block_number
+                    .eq(block_number)
+                    .and(schema::transfers::log_index.gt(u64_to_storage(log_index, "Log index")?))),
+        );
+    }
+
+    query
+        .load::<TransferRow>(conn)?
+        .into_iter()
+        .map(TransferEvent::try_from)
+        .collect()
+}
+
+// All transfers emitted in a transaction, in log_index order (e.g. for an explorer lookup)
+// The hash is accepted with or without `0x` and in any case, and canonicalized to the stored
+// `0x`-prefixed lowercase form; anything that isn't 32 bytes of hex is a Parse error.
+pub fn transfers_by_tx(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    tx_hash: &str,
+) -> Result<Vec<TransferEvent>> {
+    require_shared_transfers(conn)?;
+    let hex = tx_hash.trim();
+    let hex = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+    let tx_hash: B256 = hex
+        .parse()
+        .map_err(|e| IndexerError::Parse(format!("Invalid tx hash '{}': {:?}", tx_hash, e)))?;
+
+    schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::tx_hash.eq(format!("{:#x}", tx_hash)))
+        .order(schema::transfers::log_index.asc())
+        .select(TransferRow::as_select())
+        .load::<TransferRow>(conn)?
+        .into_iter()
+        .map(TransferEvent::try_from)
+        .collect()
+}
+
+// Earliest block in which `address` sent or received a transfer of the chain (any token), None
+// if it never did. One MIN per side, so each uses its address index (idx_from, idx_to) instead
+// of scanning the chain's rows.
+pub fn first_seen_block(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    address: Address,
+) -> Result<Option<u64>> {
+    require_shared_transfers(conn)?;
+    let address = format!("{:#x}", address);
+    let as_sender = schema::transfers::table
+        .filter(schema::transfers::from_addr.eq(&address))
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .select(diesel::dsl::min(schema::transfers::block_number))
+        .first::<Option<i64>>(conn)?;
+    let as_recipient = schema::transfers::table
+        .filter(schema::transfers::to_addr.eq(&address))
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .select(diesel::dsl::min(schema::transfers::block_number))
+        .first::<Option<i64>>(conn)?;
+
+    as_sender
+        .into_iter()
+        .chain(as_recipient)
+        .min()
+        .map(|block| u64_from_storage(block, "block number"))
+        .transpose()
+}
+
+// One transfer of an address's ledger and the balance it leaves the address with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub block_number: u64,
+    pub log_index: u64,
+    pub tx_hash: B256,
+    pub delta: BalanceDelta, // Credit(0) for a transfer to itself
+    pub balance: U256,       // Running balance after this transfer
+}
+
+// Every transfer of a token sent or received by `address`, in canonical (block_number,
+// log_index) order, with the running balance it leaves: a wallet's history, replayed from the
+// `transfers` table alone (MATERIALIZE_BALANCES isn't needed). The running balance is checked
+// like the materialized one, so a debit the history can't cover (e.g. the indexer started after
+// the address was funded) is a BalanceUnderflow error. The zero address has no ledger.
+pub fn ledger(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    address: Address,
+    token_address: Address,
+) -> Result<Vec<LedgerEntry>> {
+    require_shared_transfers(conn)?;
+    if address.is_zero() {
+        return Ok(Vec::new());
+    }
+
+    let token = format!("{:#x}", token_address);
+    let account = format!("{:#x}", address);
+    let rows = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::token_address.eq(&token))
+        .filter(
+            schema::transfers::from_addr
+                .eq(&account)
+                .or(schema::transfers::to_addr.eq(&account)),
+        )
+        .order((
+            schema::transfers::block_number.asc(),
+            schema::transfers::log_index.asc(),
+        ))
+        .select(TransferRow::as_select())
+        .load::<TransferRow>(conn)?;
+
+    let mut balance = U256::ZERO;
+    let mut entries = Vec::with_capacity(rows.len());
+    for row in rows {
+        let transfer = TransferEvent::try_from(row)?;
+        let delta = match (transfer.from_addr == address, transfer.to_addr == address) {
+            // Still checked: sending to itself needs the balance like any other transfer
+            (true, true) => {
+                apply_delta(
+                    balance,
+                    BalanceDelta::Debit(transfer.value),
+                    &token,
+                    &account,
+                )?;
+                BalanceDelta::Credit(U256::ZERO)
+            }
+            (true, false) => BalanceDelta::Debit(transfer.value),
+            _ => BalanceDelta::Credit(transfer.value),
+        };
+        balance = apply_delta(balance, delta, &token, &account)?;
+        entries.push(LedgerEntry {
+            block_number: transfer.block_number,
+            log_index: transfer.log_index,
+            tx_hash: transfer.tx_hash,
+            delta,
+            balance,
+        });
+    }
+    Ok(entries)
+}
+
+// Lowest and highest block with a stored transfer of a chain, next to its sync pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockCoverage {
+    pub chain_id: u64,
+    pub min_block: Option<u64>,    // None without stored transfers
+    pub max_block: Option<u64>,    // None without stored transfers
+    pub synced_block: Option<u64>, // As get_last_synced_block
+}
+
+// Coverage of every chain that has transfers or a sync pointer, by chain id
+// One grouped MIN/MAX over `transfers` (using its (chain_id, block_number) index) plus the sync
+// rows; a max past the pointer or a min far after START_BLOCK is worth a look.
+pub fn block_coverage(conn: &mut SqliteConnection) -> Result<Vec<BlockCoverage>> {
+    require_shared_transfers(conn)?;
+    fn chain(chains: &mut BTreeMap<u64, BlockCoverage>, chain_id: i32) -> &mut BlockCoverage {
+        let chain_id = chain_id as u64;
+        chains.entry(chain_id).or_insert(BlockCoverage {
+            chain_id,
+            min_block: None,
+            max_block: None,
+            synced_block: None,
+        })
+    }
+
+    let mut chains = BTreeMap::new();
+    let stored = schema::transfers::table
+        .group_by(schema::transfers::chain_id)
+        .select((
+            schema::transfers::chain_id,
+            diesel::dsl::min(schema::transfers::block_number),
+            diesel::dsl::max(schema::transfers::block_number),
+        ))
+        .load::<(i32, Option<i64>, Option<i64>)>(conn)?;
+    for (chain_id, min_block, max_block) in stored {
+        let coverage = chain(&mut chains, chain_id);
+        coverage.min_block = min_block
+            .map(|block| u64_from_storage(block, "block number"))
+            .transpose()?;
+        coverage.max_block = max_block
+            .map(|block| u64_from_storage(block, "block number"))
+            .transpose()?;
+    }
+
+    let pointers = schema::sync::table
+        .select((schema::sync::chain_id, schema::sync::block_number))
+        .load::<(i32, i64)>(conn)?;
+    for (chain_id, pointer) in pointers {
+        // A pointer seeded before block 0 (BEFORE_GENESIS) means nothing synced yet
+        chain(&mut chains, chain_id).synced_block = u64::try_from(pointer).ok();
+    }
+
+    Ok(chains.into_values().collect())
+}
+
+// How long SQLite waits on a lock held by another connection before failing (busy_timeout)
+pub const BUSY_TIMEOUT_MS: u64 = 5_000;
+
+// Attempts of a write transaction that still finds the database locked, and the first pause
+// between them (doubled after each attempt)
+const BUSY_ATTEMPTS: u32 = 5;
+const BUSY_BACKOFF: Duration = Duration::from_millis(50);
+
+// Diesel instrumentation logging every query that takes at least `threshold` (SLOW_QUERY_MS),
+// with its SQL and binds, to find what slows a backfill down on the database side
+#[derive(Debug)]
+pub struct SlowQueryLog {
+    threshold: Duration,
+    started: Option<std::time::Instant>,
+}
+
+impl SlowQueryLog {
+    pub fn new(threshold: Duration) -> Self {
+        SlowQueryLog {
+            threshold,
+            started: None,
+        }
+    }
+}
+
+impl diesel::connection::Instrumentation for SlowQueryLog {
+    fn on_connection_event(&mut self, event: diesel::connection::InstrumentationEvent<'_>) {
+        use diesel::connection::InstrumentationEvent;
+
+        match event {
+            InstrumentationEvent::StartQuery { .. } => {
+                self.started = Some(std::time::Instant::now());
+            }
+            InstrumentationEvent::FinishQuery { query, .. } => {
+                let Some(started) = self.started.take() else {
+                    return;
+                };
+                let elapsed = started.elapsed();
+                if elapsed >= self.threshold {
+                    warn!("Slow query ({:?}): {}", elapsed, query);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Whether an error is SQLite reporting the database as locked by another connection
+// (SQLITE_BUSY "database is locked", SQLITE_LOCKED "database table is locked"). Diesel maps both
+// to DatabaseErrorKind::Unknown, so only the message tells them apart from real failures.
+pub fn is_busy_error(error: &IndexerError) -> bool {
+    match error {
+        IndexerError::Database(diesel::result::Error::DatabaseError(_, info)) => {
+            info.message().contains("is locked")
+        }
+        _ => false,
+    }
+}
+
+// Run `write` in a transaction, retrying the whole transaction with a short backoff while the
+// database is locked. busy_timeout already waits for most locks, but SQLite returns SQLITE_BUSY
+// right away when a deferred transaction can't upgrade to a write lock (e.g. a reader of an API
+// started first), and only a fresh transaction can proceed. Other errors are returned as is.
+pub fn write_transaction<T>(
+    conn: &mut SqliteConnection,
+    mut write: impl FnMut(&mut SqliteConnection) -> Result<T>,
+) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match conn.transaction(&mut write) {
+            Err(e) if attempt < BUSY_ATTEMPTS && is_busy_error(&e) => {
+                let delay = BUSY_BACKOFF.saturating_mul(2u32.saturating_pow(attempt - 1));
+                warn!(
+                    "Database is locked (attempt {}/{}), retrying in {:?}",
+                    attempt, BUSY_ATTEMPTS, delay
+                );
+                std::thread::sleep(delay);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Rebuild the database file without its free pages (VACUUM), so space left by deleted rows is
+// returned to the filesystem; a WAL file, if any, is checkpointed and truncated first.
+// Needs an exclusive lock for the whole rebuild and can't run inside a transaction.
+pub fn compact(conn: &mut SqliteConnection) -> Result<()> {
+    diesel::sql_query("PRAGMA wal_checkpoint(TRUNCATE)").execute(conn)?;
+    diesel::sql_query("VACUUM").execute(conn)?;
+    Ok(())
+}
+
+// Current time in seconds since the Unix epoch
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+// Aggregates over the transfers of a block range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeStats {
+    pub count: u64,
+    pub total_value: U256,
+    pub unique_senders: u64,
+    pub unique_receivers: u64,
+}
+
+// Transfer count, volume and distinct senders/receivers of a chain in [from_block, to_block]
+// Computed in a single streamed scan; the volume is summed as U256 since SQLite can't sum
+// values stored as decimal text without losing precision
+pub fn stats_for_range(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> Result<RangeStats> {
+    require_shared_transfers(conn)?;
+    let rows = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::block_number.ge(block_to_storage(from_block)?))
+        .filter(schema::transfers::block_number.le(block_to_storage(to_block)?))
+        .select((
+            schema::transfers::from_addr,
+            schema::transfers::to_addr,
+            schema::transfers::value,
+        ))
+        .load_iter::<(String, String, String), diesel::connection::DefaultLoadingMode>(conn)?;
+
+    let mut count = 0;
+    let mut total_value = U256::ZERO;
+    let mut senders = HashSet::new();
+    let mut receivers = HashSet::new();
+    for row in rows {
+        let (from_addr, to_addr, value) = row?;
+        total_value = total_value
+            .checked_add(value_from_storage(&value)?)
+            .ok_or_else(|| {
+                IndexerError::Overflow(format!(
+                    "Transfer volume of blocks {}..={} exceeds uint256",
+                    from_block, to_block
+                ))
+            })?;
+        senders.insert(from_addr);
+        receivers.insert(to_addr);
+        count += 1;
+    }
+
+    Ok(RangeStats {
+        count,
+        total_value,
+        unique_senders: senders.len() as u64,
+        unique_receivers: receivers.len() as u64,
+    })
+}
+
+// Values covered by a histogram bucket: [low, high), high None for a bucket without upper bound
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketRange {
+    pub low: U256,
+    pub high: Option<U256>,
+}
+
+// Largest number of decimal digits of a uint256 (2^256 - 1 has 78)
+const U256_DIGITS: usize = 78;
+
+// Histogram of the transfer values of a token, in at most `buckets` logarithmic buckets
+// Values span many orders of magnitude, so the buckets are decades: every bucket covers the same
+// number of decimal digits, chosen so that `buckets` buckets reach the largest value, e.g. with
+// values below 10^24 and 6 buckets: [0, 10^4), [10^4, 10^8), ..., [10^20, 10^24). The first
+// bucket includes zero-value transfers. Every bucket up to the largest value is returned, in
+// order, even when empty; no transfers (or 0 buckets) gives an empty histogram. Computed in a
+// single streamed scan, from the length of the stored decimal values.
+pub fn value_histogram(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: Address,
+    buckets: usize,
+) -> Result<Vec<(BucketRange, u64)>> {
+    require_shared_transfers(conn)?;
+    if buckets == 0 {
+        return Ok(Vec::new());
+    }
+
+    let rows = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::token_address.eq(format!("{:#x}", token_address)))
+        .select(schema::transfers::value)
+        .load_iter::<String, diesel::connection::DefaultLoadingMode>(conn)?;
+
+    // counts[d] = number of values with d + 1 decimal digits (zero has one digit)
+    let mut counts = [0u64; U256_DIGITS];
+    for row in rows {
+        let value = row?;
+        value_from_storage(&value)?;
+        // The canonical encoding has no leading zeros, so its length is the number of digits
+        counts[value.len() - 1] += 1;
+    }
+
+    let Some(max_digits) = counts.iter().rposition(|count| *count > 0).map(|i| i + 1) else {
+        return Ok(Vec::new());
+    };
+    let width = max_digits.div_ceil(buckets); // Decades per bucket
+    let power_of_ten = |exponent: usize| U256::from(10u8).checked_pow(U256::from(exponent));
+
+    let mut histogram = Vec::with_capacity(max_digits.div_ceil(width));
+    for (index, decades) in counts[..max_digits].chunks(width).enumerate() {
+        let low = match index {
+            0 => U256::ZERO,
+            _ => power_of_ten(index * width).unwrap_or(U256::MAX),
+        };
+        let range = BucketRange {
+            low,
+            high: power_of_ten((index + 1) * width),
+        };
+        histogram.push((range, decades.iter().sum()));
+    }
+    Ok(histogram)
+}
+
+// Number of transfers of a token per bucket of `bucket_blocks` blocks, for activity charts
+// A bucket is keyed by its first block (a multiple of `bucket_blocks`). Every bucket from the
+// first to the last one holding a transfer is returned, in order, with a zero count when no
+// transfer falls in it; no transfers (or 0-block buckets) gives an empty series.
+pub fn velocity(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: Address,
+    bucket_blocks: u64,
+) -> Result<Vec<(u64, u64)>> {
+    require_shared_transfers(conn)?;
+    if bucket_blocks == 0 {
+        return Ok(Vec::new());
+    }
+
+    let rows = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::token_address.eq(format!("{:#x}", token_address)))
+        .select(schema::transfers::block_number)
+        .load_iter::<i64, diesel::connection::DefaultLoadingMode>(conn)?;
+
+    let mut counts = BTreeMap::new();
+    for row in rows {
+        let block = u64_from_storage(row?, "block number")?;
+        *counts.entry(block / bucket_blocks).or_insert(0u64) += 1;
+    }
+
+    let (Some(first), Some(last)) = (counts.keys().next(), counts.keys().next_back()) else {
+        return Ok(Vec::new());
+    };
+    Ok((*first..=*last)
+        .map(|bucket| {
+            let count = counts.get(&bucket).copied().unwrap_or(0);
+            (bucket * bucket_blocks, count)
+        })
+        .collect())
+}
+
+// Record a block range that could not be processed so it can be retried later
+pub fn record_failed_range(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+    error: &str,
+) -> Result<()> {
+    let failed_at = unix_now();
+
+    diesel::insert_into(schema::failed_ranges::table)
+        .values((
+            schema::failed_ranges::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::failed_ranges::from_block.eq(block_to_storage(from_block)?),
+            schema::failed_ranges::to_block.eq(block_to_storage(to_block)?),
+            schema::failed_ranges::error.eq(error),
+            schema::failed_ranges::failed_at.eq(failed_at),
+        ))
+        .on_conflict((
+            schema::failed_ranges::chain_id,
+            schema::failed_ranges::from_block,
+            schema::failed_ranges::to_block,
+        ))
+        .do_update()
+        .set((
+            schema::failed_ranges::error.eq(error),
+            schema::failed_ranges::failed_at.eq(failed_at),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// A block range that exhausted its retries during indexing
+#[derive(Debug, Clone)]
+pub struct FailedRange {
+    pub chain_id: u64,
+    pub from_block: u64,
+    pub to_block: u64,
+    pub error: String,
+    pub failed_at: i64,
+}
+
+// List the dead-lettered ranges of a chain, oldest block first
+pub fn failed_ranges(conn: &mut SqliteConnection, chain_id: u64) -> Result<Vec<FailedRange>> {
+    let rows = schema::failed_ranges::table
+        .filter(schema::failed_ranges::chain_id.eq(chain_to_storage(chain_id)?))
+        .order(schema::failed_ranges::from_block.asc())
+        .select((
+            schema::failed_ranges::from_block,
+            schema::failed_ranges::to_block,
+            schema::failed_ranges::error,
+            schema::failed_ranges::failed_at,
+        ))
+        .load::<(i64, i64, String, i64)>(conn)?;
+
+    rows.into_iter()
+        .map(|(from_block, to_block, error, failed_at)| {
+            Ok(FailedRange {
+                chain_id,
+                from_block: u64_from_storage(from_block, "block number")?,
+                to_block: u64_from_storage(to_block, "block number")?,
+                error,
+                failed_at,
+            })
+        })
+        .collect()
+}
+
+// Remove a dead-lettered range once it has been processed successfully
+pub fn delete_failed_range(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> Result<()> {
+    diesel::delete(
+        schema::failed_ranges::table
+            .filter(schema::failed_ranges::chain_id.eq(chain_to_storage(chain_id)?))
+            .filter(schema::failed_ranges::from_block.eq(block_to_storage(from_block)?))
+            .filter(schema::failed_ranges::to_block.eq(block_to_storage(to_block)?)),
+    )
+    .execute(conn)?;
+
+    Ok(())
+}
+
+// Last block completed by the backfill job covering [from_block, to_block], None if the job
+// hasn't completed any sub-range yet
+// Jobs are keyed by their bounds, so re-running the same backfill resumes it
+pub fn get_backfill_progress(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Option<u64>> {
+    let last_block = schema::backfill_progress::table
+        .filter(schema::backfill_progress::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::backfill_progress::from_block.eq(block_to_storage(from_block)?))
+        .filter(schema::backfill_progress::to_block.eq(block_to_storage(to_block)?))
+        .select(schema::backfill_progress::last_block)
+        .first::<i64>(conn)
+        .optional()?;
+
+    last_block
+        .map(|block| u64_from_storage(block, "block number"))
+        .transpose()
+}
+
+// Record that the backfill job covering [from_block, to_block] completed every block up to
+// `last_block`. Independent of the live `sync` pointer.
+pub fn set_backfill_progress(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+    last_block: u64,
+) -> Result<()> {
+    let last_block = block_to_storage(last_block)?;
+    diesel::insert_into(schema::backfill_progress::table)
+        .values((
+            schema::backfill_progress::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::backfill_progress::from_block.eq(block_to_storage(from_block)?),
+            schema::backfill_progress::to_block.eq(block_to_storage(to_block)?),
+            schema::backfill_progress::last_block.eq(last_block),
+        ))
+        .on_conflict((
+            schema::backfill_progress::chain_id,
+            schema::backfill_progress::from_block,
+            schema::backfill_progress::to_block,
+        ))
+        .do_update()
+        .set(schema::backfill_progress::last_block.eq(last_block))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// Move the progress of a backfill back to `last_block`, or forget it (the backfill starts over
+// at from_block) with None
+pub fn reset_backfill_progress(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+    last_block: Option<u64>,
+) -> Result<()> {
+    if let Some(last_block) = last_block {
+        return set_backfill_progress(conn, chain_id, from_block, to_block, last_block);
+    }
+    diesel::delete(
+        schema::backfill_progress::table
+            .filter(schema::backfill_progress::chain_id.eq(chain_to_storage(chain_id)?))
+            .filter(schema::backfill_progress::from_block.eq(block_to_storage(from_block)?))
+            .filter(schema::backfill_progress::to_block.eq(block_to_storage(to_block)?)),
+    )
+    .execute(conn)?;
+
+    Ok(())
+}
+
+// Store the detected metadata of a token, replacing what an earlier run stored
+pub fn set_token_metadata(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: Address,
+    metadata: &TokenMetadata,
+) -> Result<()> {
+    diesel::insert_into(schema::token_metadata::table)
+        .values((
+            schema::token_metadata::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::token_metadata::token_address.eq(format!("{:#x}", token_address)),
+            schema::token_metadata::symbol.eq(&metadata.symbol),
+            schema::token_metadata::name.eq(&metadata.name),
+            schema::token_metadata::decimals.eq(metadata.decimals.map(i32::from)),
+        ))
+        .on_conflict((
+            schema::token_metadata::chain_id,
+            schema::token_metadata::token_address,
+        ))
+        .do_update()
+        .set((
+            schema::token_metadata::symbol.eq(&metadata.symbol),
+            schema::token_metadata::name.eq(&metadata.name),
+            schema::token_metadata::decimals.eq(metadata.decimals.map(i32::from)),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// Stored metadata of a token, None if it was never detected
+pub fn get_token_metadata(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    token_address: Address,
+) -> Result<Option<TokenMetadata>> {
+    let metadata = schema::token_metadata::table
+        .filter(schema::token_metadata::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::token_metadata::token_address.eq(format!("{:#x}", token_address)))
+        .select((
+            schema::token_metadata::symbol,
+            schema::token_metadata::name,
+            schema::token_metadata::decimals,
+        ))
+        .first::<(String, String, Option<i32>)>(conn)
+        .optional()?;
+
+    Ok(metadata.map(|(symbol, name, decimals)| TokenMetadata {
+        symbol,
+        name,
+        decimals: decimals.and_then(|decimals| u8::try_from(decimals).ok()),
+    }))
+}
+
+// Most recent error of the indexer on a chain, kept until the next successful range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastError {
+    pub error: String,
+    pub failed_at: i64,
+    pub from_block: u64,
+    pub to_block: u64,
+}
+
+// Remember why processing [from_block, to_block] failed, replacing the previous error
+pub fn record_last_error(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+    error: &str,
+) -> Result<()> {
+    let failed_at = unix_now();
+    let (from_block, to_block) = (block_to_storage(from_block)?, block_to_storage(to_block)?);
+    diesel::insert_into(schema::indexer_state::table)
+        .values((
+            schema::indexer_state::chain_id.eq(chain_to_storage(chain_id)?),
+            schema::indexer_state::last_error.eq(error),
+            schema::indexer_state::last_error_at.eq(failed_at),
+            schema::indexer_state::last_error_from_block.eq(from_block),
+            schema::indexer_state::last_error_to_block.eq(to_block),
+        ))
+        .on_conflict(schema::indexer_state::chain_id)
+        .do_update()
+        .set((
+            schema::indexer_state::last_error.eq(error),
+            schema::indexer_state::last_error_at.eq(failed_at),
+            schema::indexer_state::last_error_from_block.eq(from_block),
+            schema::indexer_state::last_error_to_block.eq(to_block),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+// Forget the last error of a chain once a range went through again
+pub fn clear_last_error(conn: &mut SqliteConnection, chain_id: u64) -> Result<()> {
+    diesel::update(
+        schema::indexer_state::table
+            .filter(schema::indexer_state::chain_id.eq(chain_to_storage(chain_id)?)),
+    )
+    .set((
+        schema::indexer_state::last_error.eq(None::<String>),
+        schema::indexer_state::last_error_at.eq(None::<i64>),
+        schema::indexer_state::last_error_from_block.eq(None::<i64>),
+        schema::indexer_state::last_error_to_block.eq(None::<i64>),
+    ))
+    .execute(conn)?;
+
+    Ok(())
+}
+
+// Last error of a chain, None if the last range succeeded (or nothing ever failed)
+pub fn get_last_error(conn: &mut SqliteConnection, chain_id: u64) -> Result<Option<LastError>> {
+    let row = schema::indexer_state::table
+        .filter(schema::indexer_state::chain_id.eq(chain_to_storage(chain_id)?))
+        .select((
+            schema::indexer_state::last_error,
+            schema::indexer_state::last_error_at,
+            schema::indexer_state::last_error_from_block,
+            schema::indexer_state::last_error_to_block,
+        ))
+        .first::<(Option<String>, Option<i64>, Option<i64>, Option<i64>)>(conn)
+        .optional()?;
+
+    Ok(match row {
+        Some((Some(error), Some(failed_at), Some(from_block), Some(to_block))) => Some(LastError {
+            error,
+            failed_at,
+            from_block: u64_from_storage(from_block, "block number")?,
+            to_block: u64_from_storage(to_block, "block number")?,
+        }),
+        _ => None,
+    })
+}
+
+// Fingerprint of the indexed transfers of a chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Checksum {
+    pub rows: u64,
+    pub hash: B256,
+}
+
+impl Checksum {
+    // Fold the next row (in canonical order) into the hash chain:
+    // h_0 = 0, h_i = keccak256(h_(i-1) || keccak256(canonical_bytes(row_i)))
+    pub fn add(&mut self, event: &TransferEvent) {
+        let row_hash = alloy_primitives::keccak256(event.canonical_bytes());
+
+        let mut hasher = Keccak256::new();
+        hasher.update(self.hash);
+        hasher.update(row_hash);
+        self.hash = hasher.finalize();
+        self.rows += 1;
+    }
+}
+
+// Compute a deterministic fingerprint over all transfers of a chain up to `to_block` (inclusive)
+// Rows are streamed in canonical order (block_number, log_index) and folded into a hash chain
+// (see Checksum::add). Two instances that indexed the same data produce the same hash
+// regardless of insert order
+pub fn transfers_checksum(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    to_block: Option<u64>,
+) -> Result<Checksum> {
+    // Block numbers are stored as i64, so that is the highest block a row can have
+    range_checksum(conn, chain_id, 0, to_block.unwrap_or(i64::MAX as u64))
+}
+
+// Fingerprint of the transfers of a chain in [from_block, to_block], like `transfers_checksum`
+// Used by `diff` to find the block ranges where two databases diverge
+pub fn range_checksum(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Checksum> {
+    require_shared_transfers(conn)?;
+    // Block numbers are stored as i64, clamp so a huge bound doesn't wrap negative
+    let clamp = |block: u64| block.min(i64::MAX as u64) as i64;
+    let rows = schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::block_number.ge(clamp(from_block)))
+        .filter(schema::transfers::block_number.le(clamp(to_block)))
+        .order((
+            schema::transfers::block_number.asc(),
+            schema::transfers::log_index.asc(),
+        ))
+        .select(TransferRow::as_select())
+        .load_iter::<TransferRow, diesel::connection::DefaultLoadingMode>(conn)?;
+
+    let mut checksum = Checksum::default();
+    for row in rows {
+        checksum.add(&TransferEvent::try_from(row?)?);
+    }
+    Ok(checksum)
+}
+
+// Transfers of a chain in [from_block, to_block], in canonical (block_number, log_index) order
+// Loads the whole range at once, meant for small ranges (see `finalized_transfers` to page)
+pub fn transfers_in_range(
+    conn: &mut SqliteConnection,
+    chain_id: u64,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<TransferEvent>> {
+    require_shared_transfers(conn)?;
+    let clamp = |block: u64| block.min(i64::MAX as u64) as i64;
+    schema::transfers::table
+        .filter(schema::transfers::chain_id.eq(chain_to_storage(chain_id)?))
+        .filter(schema::transfers::block_number.ge(clamp(from_block)))
+        .filter(schema::transfers::block_number.le(clamp(to_block)))
+        .order((
+            schema::transfers::block_number.asc(),
+            schema::transfers::log_index.asc(),
+        ))
+        .select(TransferRow::as_select())
+        .load::<TransferRow>(conn)?
+        .into_iter()
+        .map(TransferEvent::try_from)
+        .collect()
+}
+
+// Read-only view of an indexer database, e.g. a replica shipped from the writer
+// The connection is opened with SQLITE_OPEN_READONLY (`mode=ro`) and no migrations are run,
+// so nothing can write to the file: any write attempt fails with SQLITE_READONLY.
+// Diesel needs `&mut` to run any query, so the guarantee comes from the connection, not the types.
+pub struct ReadOnlyStore {
+    conn: SqliteConnection,
+}
+
+impl ReadOnlyStore {
+    // Open an existing database read-only (fails instead of creating a missing file)
+    pub fn open(db_path: &str) -> Result<Self> {
+        Self::open_with_prefix(db_path, "")
+    }
+
+    // Open a database whose `sync` and `transfers` tables have a TABLE_PREFIX read-only
+    // The TEMP views of use_table_prefix live in the connection's own `temp` schema, which a
+    // read-only connection can still create.
+    pub fn open_with_prefix(db_path: &str, table_prefix: &str) -> Result<Self> {
+        let mut conn = SqliteConnection::establish(&format!("file:{}?mode=ro", db_path))?;
+        use_table_prefix(&mut conn, table_prefix)?;
+        Ok(ReadOnlyStore { conn })
+    }
+
+    pub fn last_synced_block(&mut self, chain_id: u64) -> Result<Option<u64>> {
+        get_last_synced_block(&mut self.conn, chain_id)
+    }
+
+    pub fn sync_updated_at(&mut self, chain_id: u64) -> Result<Option<u64>> {
+        get_sync_updated_at(&mut self.conn, chain_id)
+    }
+
+    pub fn failed_ranges(&mut self, chain_id: u64) -> Result<Vec<FailedRange>> {
+        failed_ranges(&mut self.conn, chain_id)
+    }
+
+    pub fn finalized_transfers(
+        &mut self,
+        chain_id: u64,
+        head: u64,
+        confirmations: u64,
+        after: Option<(u64, u64)>,
+        limit: usize,
+    ) -> Result<Vec<TransferEvent>> {
+        finalized_transfers(&mut self.conn, chain_id, head, confirmations, after, limit)
+    }
+
+    pub fn transfers_between(
+        &mut self,
+        chain_id: u64,
+        start_ts: u64,
+        end_ts: u64,
+        after: Option<(u64, u64)>,
+        limit: usize,
+    ) -> Result<Vec<TransferEvent>> {
+        transfers_between(&mut self.conn, chain_id, start_ts, end_ts, after, limit)
+    }
+
+    pub fn transfers_by_tx(&mut self, chain_id: u64, tx_hash: &str) -> Result<Vec<TransferEvent>> {
+        transfers_by_tx(&mut self.conn, chain_id, tx_hash)
+    }
+
+    pub fn first_seen_block(&mut self, chain_id: u64, address: Address) -> Result<Option<u64>> {
+        first_seen_block(&mut self.conn, chain_id, address)
+    }
+
+    pub fn ledger(
+        &mut self,
+        chain_id: u64,
+        address: Address,
+        token_address: Address,
+    ) -> Result<Vec<LedgerEntry>> {
+        ledger(&mut self.conn, chain_id, address, token_address)
+    }
+
+    pub fn block_coverage(&mut self) -> Result<Vec<BlockCoverage>> {
+        block_coverage(&mut self.conn)
+    }
+
+    pub fn last_error(&mut self, chain_id: u64) -> Result<Option<LastError>> {
+        get_last_error(&mut self.conn, chain_id)
+    }
+
+    pub fn token_metadata(
+        &mut self,
+        chain_id: u64,
+        token_address: Address,
+    ) -> Result<Option<TokenMetadata>> {
+        get_token_metadata(&mut self.conn, chain_id, token_address)
+    }
+
+    pub fn stats_for_range(
+        &mut self,
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<RangeStats> {
+        stats_for_range(&mut self.conn, chain_id, from_block, to_block)
+    }
+
+    pub fn value_histogram(
+        &mut self,
+        chain_id: u64,
+        token_address: Address,
+        buckets: usize,
+    ) -> Result<Vec<(BucketRange, u64)>> {
+        value_histogram(&mut self.conn, chain_id, token_address, buckets)
+    }
+
+    pub fn velocity(
+        &mut self,
+        chain_id: u64,
+        token_address: Address,
+        bucket_blocks: u64,
+    ) -> Result<Vec<(u64, u64)>> {
+        velocity(&mut self.conn, chain_id, token_address, bucket_blocks)
+    }
+
+    pub fn balance(
+        &mut self,
+        chain_id: u64,
+        token_address: Address,
+        address: Address,
+    ) -> Result<U256> {
+        get_balance(&mut self.conn, chain_id, token_address, address)
+    }
+
+    pub fn transfers_checksum(&mut self, chain_id: u64, to_block: Option<u64>) -> Result<Checksum> {
+        transfers_checksum(&mut self.conn, chain_id, to_block)
+    }
+
+    pub fn range_checksum(
+        &mut self,
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Checksum> {
+        range_checksum(&mut self.conn, chain_id, from_block, to_block)
+    }
+
+    pub fn transfers_in_range(
+        &mut self,
+        chain_id: u64,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Vec<TransferEvent>> {
+        transfers_in_range(&mut self.conn, chain_id, from_block, to_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::open_db;
+    use alloy_primitives::{Address, U256};
+    use diesel_migrations::MigrationHarness;
+
+    fn transfer(block_number: u64, log_index: u64) -> TransferEvent {
+        TransferEvent {
+            chain_id: 1,
+            block_number,
+            tx_hash: crate::testing::tx_hash(block_number, log_index),
+            token_address: Address::repeat_byte(0xaa),
+            from_addr: Address::repeat_byte(1),
+            to_addr: Address::repeat_byte(2),
+            value: U256::from(5),
+            log_index,
+            base_fee: Some(7),
+            block_timestamp: Some(1_700_000_000),
+            gas_used: Some(21_000),
+            tx_status: Some(true),
+        }
+    }
+
+    #[test]
+    fn checksum_is_independent_of_insert_order_and_sees_changed_rows() {
+        let transfers: Vec<TransferEvent> = [(1, 0), (1, 1), (5, 0), (9, 3)]
+            .into_iter()
+            .map(|(block, log_index)| transfer(block, log_index))
+            .collect();
+        let mut first = crate::testing::in_memory_db();
+        insert_transfers(&mut first, &transfers).unwrap();
+        let mut reversed: Vec<TransferEvent> = transfers.iter().rev().cloned().collect();
+        // Enrichment is not part of the fingerprint
+        reversed[0].base_fee = None;
+        let mut second = crate::testing::in_memory_db();
+        insert_transfers(&mut second, &reversed).unwrap();
+
+        let checksum = transfers_checksum(&mut first, 1, None).unwrap();
+        assert_eq!(checksum.rows, 4);
+        assert_eq!(checksum, transfers_checksum(&mut second, 1, None).unwrap());
+
+        // A changed value changes the hash, not the row count
+        let mut changed = transfers.clone();
+        changed[2].value = U256::from(6);
+        let mut third = crate::testing::in_memory_db();
+        insert_transfers(&mut third, &changed).unwrap();
+        let other = transfers_checksum(&mut third, 1, None).unwrap();
+        assert_eq!(other.rows, 4);
+        assert_ne!(other.hash, checksum.hash);
+
+        // Rows past to_block are left out, so the databases agree up to block 4
+        assert_eq!(
+            transfers_checksum(&mut first, 1, Some(4)).unwrap(),
+            transfers_checksum(&mut third, 1, Some(4)).unwrap()
+        );
+        assert_eq!(transfers_checksum(&mut first, 1, Some(4)).unwrap().rows, 2);
+    }
+
+    #[test]
+    fn same_log_key_of_another_token_keeps_the_stored_row() {
+        let mut conn = crate::testing::in_memory_db();
+        let first = transfer(3, 1);
+        let other_token = TransferEvent {
+            token_address: Address::repeat_byte(0xbb),
+            value: U256::from(99),
+            ..first.clone()
+        };
+
+        assert_eq!(
+            insert_transfers(&mut conn, std::slice::from_ref(&first)).unwrap(),
+            1
+        );
+        // Same (chain_id, tx_hash, log_index): a duplicate, whatever the token
+        assert_eq!(insert_transfers(&mut conn, &[other_token]).unwrap(), 0);
+
+        let stored = transfers_in_range(&mut conn, 1, 0, 10).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].token_address, first.token_address);
+        assert_eq!(stored[0].value, first.value);
+
+        // Another chain is another key
+        let other_chain = TransferEvent {
+            chain_id: 2,
+            ..first
+        };
+        assert_eq!(insert_transfers(&mut conn, &[other_chain]).unwrap(), 1);
+    }
+
+    #[test]
+    fn large_values_round_trip_without_precision_loss() {
+        let mut conn = crate::testing::in_memory_db();
+        let values = [
+            U256::ZERO,
+            U256::from(i64::MAX as u64) + U256::from(1),
+            U256::from(10).pow(U256::from(30)) + U256::from(1),
+            U256::MAX,
+        ];
+        let transfers: Vec<TransferEvent> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| TransferEvent {
+                value: *value,
+                ..transfer(1, i as u64)
+            })
+            .collect();
+        insert_transfers(&mut conn, &transfers).unwrap();
+
+        let stored: Vec<U256> = transfers_in_range(&mut conn, 1, 0, 10)
+            .unwrap()
+            .iter()
+            .map(|transfer| transfer.value)
+            .collect();
+        assert_eq!(stored, values);
+        assert_eq!(
+            value_to_storage(U256::MAX),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+        assert_eq!(value_from_storage("0").unwrap(), U256::ZERO);
+        for invalid in ["", "-1", "0x10", "1.5", "1e3"] {
+            assert!(value_from_storage(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn numeric_values_of_older_versions_are_rewritten_as_text() {
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        // Schema of the first version, where value was NUMERIC
+        conn.run_next_migration(crate::MIGRATIONS).unwrap();
+        diesel::sql_query(
+            "INSERT INTO transfers VALUES \
+             (1, 1, '0x01', '0xaa', '0x01', '0x02', '42', 0), \
+             (1, 1, '0x01', '0xaa', '0x01', '0x02', '100000000000000000000', 1)",
+        )
+        .execute(&mut conn)
+        .unwrap();
+        conn.run_pending_migrations(crate::MIGRATIONS).unwrap();
+
+        #[derive(QueryableByName)]
+        struct Value {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            value: String,
+        }
+        let values: Vec<String> =
+            diesel::sql_query("SELECT value FROM transfers ORDER BY log_index")
+                .load::<Value>(&mut conn)
+                .unwrap()
+                .into_iter()
+                .map(|row| row.value)
+                .collect();
+        assert_eq!(values, vec!["42", "100000000000000000000"]);
+    }
+
+    #[test]
+    fn prefixed_migrations_follow_the_embedded_ones() {
+        use diesel::migration::MigrationSource;
+
+        let embedded: Vec<String> =
+            MigrationSource::<diesel::sqlite::Sqlite>::migrations(&crate::MIGRATIONS)
+                .unwrap()
+                .iter()
+                .map(|migration| migration.name().to_string())
+                .collect();
+        let listed: Vec<&str> = MIGRATION_SQL.iter().map(|(name, _)| *name).collect();
+        assert_eq!(embedded, listed);
+
+        // Every table and index the migrations create is known to the conflict check
+        let mut tables = BTreeSet::new();
+        let mut indexes = BTreeSet::new();
+        for (_, sql) in MIGRATION_SQL {
+            let words: Vec<&str> = sql.split_whitespace().collect();
+            for window in words.windows(3) {
+                match window {
+                    ["CREATE", "TABLE", name] => tables.insert(*name),
+                    ["CREATE", "INDEX", name] => indexes.insert(*name),
+                    _ => false,
+                };
+            }
+        }
+        let expected: BTreeSet<&str> = MIGRATED_TABLES
+            .into_iter()
+            .chain(["transfers_new"])
+            .collect();
+        assert_eq!(tables, expected);
+        assert_eq!(indexes, MIGRATED_INDEXES.into_iter().collect());
+
+        let mut conn = SqliteConnection::establish(":memory:").unwrap();
+        conn.run_pending_migrations(PrefixedMigrations { prefix: "app_" })
+            .unwrap();
+        let names: BTreeSet<String> =
+            diesel::sql_query("SELECT name FROM sqlite_master WHERE type IN ('table', 'index')")
+                .load::<TableName>(&mut conn)
+                .unwrap()
+                .into_iter()
+                .map(|table| table.name)
+                .filter(|name| !name.starts_with("sqlite_") && !name.starts_with("__diesel"))
+                .collect();
+        let expected: BTreeSet<String> = MIGRATED_TABLES
+            .iter()
+            .chain(&MIGRATED_INDEXES)
+            .map(|name| prefixed_name("app_", name))
+            .collect();
+        assert_eq!(names, expected);
+        assert!(expected.contains("app_transfers") && expected.contains("app_idx_block"));
+        assert!(expected.contains("balances"));
+    }
+
+    #[test]
+    fn read_only_store_queries_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("replica.db").display().to_string();
+        {
+            let mut conn = open_db(&path);
+            insert_transfers(&mut conn, &[transfer(3, 0), transfer(4, 1)]).unwrap();
+            set_last_synced_block(&mut conn, 1, 4).unwrap();
+        }
+        let before = std::fs::read(&path).unwrap();
+
+        let mut store = ReadOnlyStore::open(&path).unwrap();
+        assert_eq!(store.last_synced_block(1).unwrap(), Some(4));
+        assert_eq!(store.transfers_in_range(1, 0, 10).unwrap().len(), 2);
+        assert_eq!(store.transfers_checksum(1, None).unwrap().rows, 2);
+        assert!(store.failed_ranges(1).unwrap().is_empty());
+        assert_eq!(
+            store
+                .balance(1, Address::repeat_byte(0xaa), Address::repeat_byte(9))
+                .unwrap(),
+            U256::ZERO
+        );
+
+        // The connection itself refuses writes
+        let error = set_last_synced_block(&mut store.conn, 1, 5).unwrap_err();
+        assert!(error.to_string().contains("readonly"), "{}", error);
+        drop(store);
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+
+        let missing = dir.path().join("missing.db").display().to_string();
+        assert!(ReadOnlyStore::open(&missing).is_err());
+        assert!(!std::path::Path::new(&missing).exists());
+    }
+
+    fn table_transfers(conn: &mut SqliteConnection, table: &str) -> Vec<TransferEvent> {
+        diesel::sql_query(format!(
+            "SELECT * FROM {} ORDER BY block_number, log_index",
+            table
+        ))
+        .load::<TransferRow>(conn)
+        .unwrap()
+        .into_iter()
+        .map(|row| TransferEvent::try_from(row).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn per_token_tables_hold_each_token_with_every_column() {
+        let mut conn = crate::testing::in_memory_db();
+        let first = transfer(1, 0);
+        let second = TransferEvent {
+            token_address: Address::repeat_byte(0xbb),
+            base_fee: None,
+            gas_used: None,
+            tx_status: Some(false),
+            ..transfer(2, 0)
+        };
+        let changes = [
+            TransferChange::Added(first.clone()),
+            TransferChange::Added(second.clone()),
+        ];
+        let write = WriteOptions {
+            tables: TransferTables::PerToken,
+            ..WriteOptions::default()
+        };
+        let applied = apply_transfer_changes(&mut conn, &changes, &write).unwrap();
+        assert_eq!(applied.inserted, 2);
+
+        let stored = table_transfers(&mut conn, &token_table_name(first.token_address));
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].block_number, 1);
+        assert_eq!(stored[0].value, first.value);
+        assert_eq!(stored[0].base_fee, Some(7));
+        assert_eq!(stored[0].block_timestamp, Some(1_700_000_000));
+        assert_eq!(stored[0].gas_used, Some(21_000));
+        assert_eq!(stored[0].tx_status, Some(true));
+        let stored = table_transfers(&mut conn, &token_table_name(second.token_address));
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].block_number, 2);
+        assert_eq!(stored[0].base_fee, None);
+        assert_eq!(stored[0].gas_used, None);
+        assert_eq!(stored[0].tx_status, Some(false));
+        assert!(table_transfers(&mut conn, "transfers").is_empty());
+
+        // A reorged transfer leaves its token's table
+        let removed = [TransferChange::Removed(first.clone())];
+        apply_transfer_changes(&mut conn, &removed, &write).unwrap();
+        assert!(table_transfers(&mut conn, &token_table_name(first.token_address)).is_empty());
+    }
+
+    #[test]
+    fn shared_table_readers_refuse_split_transfers() {
+        let stored = transfer(1, 0);
+        let refused = |conn: &mut SqliteConnection| {
+            let errors = [
+                transfers_in_range(conn, 1, 0, 10).map(|_| ()),
+                block_coverage(conn).map(|_| ()),
+                stats_for_range(conn, 1, 0, 10).map(|_| ()),
+                first_seen_block(conn, 1, stored.to_addr).map(|_| ()),
+                ledger(conn, 1, stored.to_addr, stored.token_address).map(|_| ()),
+                transfers_checksum(conn, 1, None).map(|_| ()),
+            ];
+            errors
+                .iter()
+                .all(|result| matches!(result, Err(IndexerError::Unsupported(_))))
+        };
+
+        let mut conn = crate::testing::in_memory_db();
+        insert_transfers(&mut conn, std::slice::from_ref(&stored)).unwrap();
+        // Other tables named like `transfers_...` are not split transfers
+        diesel::sql_query("CREATE TABLE transfers_archive (id INTEGER)")
+            .execute(&mut conn)
+            .unwrap();
+        assert_eq!(transfers_in_range(&mut conn, 1, 0, 10).unwrap().len(), 1);
+        create_token_table(&mut conn, stored.token_address).unwrap();
+        assert!(refused(&mut conn));
+
+        let mut conn = crate::testing::in_memory_db();
+        create_partition_table(&mut conn, &partition_table_name(1, 100)).unwrap();
+        assert!(refused(&mut conn));
+    }
+
+    #[test]
+    fn finalized_transfers_stop_at_the_confirmation_boundary() {
+        let mut conn = crate::testing::in_memory_db();
+        let transfers = [(88, 0), (89, 0), (89, 1), (90, 0), (95, 0)].map(|(b, i)| transfer(b, i));
+        insert_transfers(&mut conn, &transfers).unwrap();
+        let positions = |rows: Vec<TransferEvent>| -> Vec<(u64, u64)> {
+            rows.iter().map(|t| (t.block_number, t.log_index)).collect()
+        };
+
+        // Head 100 with 11 confirmations: block 89 is final, block 90 is not
+        let rows = finalized_transfers(&mut conn, 1, 100, 11, None, 100).unwrap();
+        assert_eq!(positions(rows), vec![(88, 0), (89, 0), (89, 1)]);
+        // Paged by position
+        let page = finalized_transfers(&mut conn, 1, 100, 11, None, 2).unwrap();
+        assert_eq!(positions(page), vec![(88, 0), (89, 0)]);
+        let page = finalized_transfers(&mut conn, 1, 100, 11, Some((89, 0)), 2).unwrap();
+        assert_eq!(positions(page), vec![(89, 1)]);
+
+        // No confirmations: everything up to the head
+        assert_eq!(
+            finalized_transfers(&mut conn, 1, 100, 0, None, 100)
+                .unwrap()
+                .len(),
+            5
+        );
+        // A chain shallower than the window has nothing final
+        assert!(
+            finalized_transfers(&mut conn, 1, 10, 11, None, 100)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            finalized_transfers(&mut conn, 2, 100, 0, None, 100)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn range_stats_aggregate_count_volume_and_accounts() {
+        let mut conn = crate::testing::in_memory_db();
+        let account = Address::repeat_byte;
+        let transfers = [
+            (10, 1, 2, U256::from(5)),
+            (11, 1, 3, U256::MAX - U256::from(10)),
+            (11, 2, 3, U256::from(3)),
+            (12, 3, 1, U256::ZERO),
+            (50, 4, 5, U256::from(1000)),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (block, from, to, value))| TransferEvent {
+            from_addr: account(from),
+            to_addr: account(to),
+            value,
+            ..transfer(block, i as u64)
+        })
+        .collect::<Vec<_>>();
+        insert_transfers(&mut conn, &transfers).unwrap();
+
+        let stats = stats_for_range(&mut conn, 1, 10, 12).unwrap();
+        assert_eq!(
+            stats,
+            RangeStats {
+                count: 4,
+                // Past u64 and i64, summed without loss
+                total_value: U256::MAX - U256::from(2),
+                unique_senders: 3,
+                unique_receivers: 3,
+            }
+        );
+        assert_eq!(stats_for_range(&mut conn, 1, 50, 50).unwrap().count, 1);
+        let empty = stats_for_range(&mut conn, 1, 13, 49).unwrap();
+        assert_eq!((empty.count, empty.total_value), (0, U256::ZERO));
+        assert_eq!((empty.unique_senders, empty.unique_receivers), (0, 0));
+    }
+
+    fn moved(block: u64, log_index: u64, from: u8, to: u8, value: u64) -> TransferEvent {
+        TransferEvent {
+            from_addr: Address::repeat_byte(from),
+            to_addr: Address::repeat_byte(to),
+            value: U256::from(value),
+            ..transfer(block, log_index)
+        }
+    }
+
+    fn balance_of(conn: &mut SqliteConnection, account: u8) -> U256 {
+        get_balance(
+            conn,
+            1,
+            Address::repeat_byte(0xaa),
+            Address::repeat_byte(account),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn balances_follow_inserts_and_removals_and_match_a_rebuild() {
+        let mut conn = crate::testing::in_memory_db();
+        let write = WriteOptions {
+            balances: true,
+            ..WriteOptions::default()
+        };
+        // Mint 100 to 1, then 1 -> 2 (30) and 2 -> 3 (10)
+        let added: Vec<TransferChange> = [
+            moved(1, 0, 0, 1, 100),
+            moved(2, 0, 1, 2, 30),
+            moved(3, 0, 2, 3, 10),
+        ]
+        .into_iter()
+        .map(TransferChange::Added)
+        .collect();
+        apply_transfer_changes(&mut conn, &added, &write).unwrap();
+        let balances =
+            |conn: &mut SqliteConnection| [1, 2, 3].map(|account| balance_of(conn, account));
+        assert_eq!(balances(&mut conn), [70, 20, 10].map(U256::from));
+
+        // Re-processing the range doesn't move them twice
+        apply_transfer_changes(&mut conn, &added, &write).unwrap();
+        assert_eq!(balances(&mut conn), [70, 20, 10].map(U256::from));
+
+        // The 2 -> 3 transfer is reorged out
+        let removed = [TransferChange::Removed(moved(3, 0, 2, 3, 10))];
+        apply_transfer_changes(&mut conn, &removed, &write).unwrap();
+        assert_eq!(balances(&mut conn), [70, 30, 0].map(U256::from));
+
+        let incremental = balances(&mut conn);
+        // Account 3 no longer has any transfer, so only two balances are rebuilt
+        assert_eq!(rebuild_balances(&mut conn, 1).unwrap(), 2);
+        assert_eq!(balances(&mut conn), incremental);
+        // The zero address (mint source) has no balance row
+        assert_eq!(balance_of(&mut conn, 0), U256::ZERO);
+    }
+
+    fn assert_overflow<T>(result: Result<T>) {
+        match result {
+            Err(IndexerError::Overflow(_)) => {}
+            Err(e) => panic!("expected an overflow error, got {}", e),
+            Ok(_) => panic!("expected an overflow error"),
+        }
+    }
+
+    #[test]
+    fn burn_exceeding_the_balance_is_a_descriptive_error() {
+        let mut conn = crate::testing::in_memory_db();
+        let write = WriteOptions {
+            balances: true,
+            ..WriteOptions::default()
+        };
+        let mint = [TransferChange::Added(moved(1, 0, 0, 1, 5))];
+        apply_transfer_changes(&mut conn, &mint, &write).unwrap();
+
+        // Burn 7 out of 5
+        let burn = [TransferChange::Added(moved(2, 0, 1, 0, 7))];
+        let error = write_transaction(&mut conn, |conn| {
+            apply_transfer_changes(conn, &burn, &write)
+        })
+        .unwrap_err();
+        match &error {
+            IndexerError::BalanceUnderflow {
+                balance, amount, ..
+            } => assert_eq!((*balance, *amount), (U256::from(5), U256::from(7))),
+            e => panic!("expected a balance underflow, got {}", e),
+        }
+        let message = error.to_string();
+        assert!(
+            message.contains(&format!("{:#x} holds 5", Address::repeat_byte(1))),
+            "{}",
+            message
+        );
+        assert!(message.contains("sends 7"), "{}", message);
+        assert!(!message.contains(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639"
+        ));
+        // Rolled back: neither the row nor the balance moved
+        assert_eq!(balance_of(&mut conn, 1), U256::from(5));
+        assert_eq!(transfers_in_range(&mut conn, 1, 0, 10).unwrap().len(), 1);
+
+        // The same guard protects a rebuild over inconsistent data
+        insert_transfers(&mut conn, &[moved(3, 0, 2, 1, 1)]).unwrap();
+        assert!(matches!(
+            rebuild_balances(&mut conn, 1),
+            Err(IndexerError::BalanceUnderflow { .. })
+        ));
+        assert_overflow(apply_delta(
+            U256::MAX,
+            BalanceDelta::Credit(U256::ONE),
+            "t",
+            "a",
+        ));
+    }
+
+    #[test]
+    fn transfers_by_tx_returns_every_transfer_of_the_transaction() {
+        let mut conn = crate::testing::in_memory_db();
+        let tx = crate::testing::tx_hash(8, 0);
+        // Three transfers in one transaction, inserted out of order, and one in another
+        let transfers = [4, 1, 9]
+            .map(|log_index| TransferEvent {
+                tx_hash: tx,
+                ..transfer(8, log_index)
+            })
+            .into_iter()
+            .chain([transfer(8, 2)])
+            .collect::<Vec<_>>();
+        insert_transfers(&mut conn, &transfers).unwrap();
+
+        let hex = format!("{:x}", tx);
+        for input in [
+            format!("0x{}", hex),
+            hex.clone(),
+            format!("0X{}", hex.to_uppercase()),
+            format!("  0x{}  ", hex),
+        ] {
+            let found = transfers_by_tx(&mut conn, 1, &input).unwrap();
+            let indexes: Vec<u64> = found.iter().map(|t| t.log_index).collect();
+            assert_eq!(indexes, vec![1, 4, 9], "{}", input);
+            assert!(found.iter().all(|t| t.tx_hash == tx));
+        }
+
+        assert!(transfers_by_tx(&mut conn, 2, &hex).unwrap().is_empty());
+        assert!(matches!(
+            transfers_by_tx(&mut conn, 1, "0x1234"),
+            Err(IndexerError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn write_transaction_retries_a_locked_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("locked.db").display().to_string();
+        let mut conn = open_db(&path);
+        // No busy_timeout: a locked database fails right away, as when a deferred transaction
+        // can't upgrade its lock
+        diesel::sql_query("PRAGMA busy_timeout = 0")
+            .execute(&mut conn)
+            .unwrap();
+
+        // Another connection holds an exclusive lock for a moment
+        let (locked, wait_for_lock) = std::sync::mpsc::channel();
+        let holder_path = path.clone();
+        let holder = std::thread::spawn(move || {
+            let mut other = open_db(&holder_path);
+            diesel::sql_query("BEGIN EXCLUSIVE")
+                .execute(&mut other)
+                .unwrap();
+            locked.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(120));
+            diesel::sql_query("COMMIT").execute(&mut other).unwrap();
+        });
+        wait_for_lock.recv().unwrap();
+
+        let mut attempts = 0;
+        write_transaction(&mut conn, |conn| {
+            attempts += 1;
+            diesel::sql_query("INSERT INTO sync (chain_id, block_number) VALUES (1, 10)")
+                .execute(conn)?;
+            Ok(())
+        })
+        .unwrap();
+        holder.join().unwrap();
+
+        assert!(attempts > 1, "the first attempt should have hit the lock");
+        assert_eq!(get_last_synced_block(&mut conn, 1).unwrap(), Some(10));
+    }
+
+    #[test]
+    fn write_transaction_returns_other_errors_at_once() {
+        let mut conn = crate::testing::in_memory_db();
+        let mut attempts = 0;
+        let result: Result<()> = write_transaction(&mut conn, |conn| {
+            attempts += 1;
+            diesel::sql_query("INSERT INTO no_such_table VALUES (1)").execute(conn)?;
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn transfers_between_filters_and_pages_by_timestamp() {
+        let mut conn = crate::testing::in_memory_db();
+        let at = |block: u64, log_index: u64, ts: Option<u64>| TransferEvent {
+            block_timestamp: ts,
+            ..transfer(block, log_index)
+        };
+        let transfers = [
+            at(1, 0, Some(100)),
+            at(2, 0, Some(200)),
+            at(2, 1, Some(200)),
+            at(3, 0, Some(300)),
+            at(4, 0, None), // Stored without ENRICH_TIMESTAMP
+            at(5, 0, Some(1 << 40)),
+        ];
+        insert_transfers(&mut conn, &transfers).unwrap();
+        let positions = |found: Vec<TransferEvent>| -> Vec<(u64, u64)> {
+            found
+                .iter()
+                .map(|transfer| (transfer.block_number, transfer.log_index))
+                .collect()
+        };
+
+        // The end is exclusive
+        let found = transfers_between(&mut conn, 1, 100, 300, None, 10).unwrap();
+        assert_eq!(positions(found), vec![(1, 0), (2, 0), (2, 1)]);
+        let found = transfers_between(&mut conn, 1, 300, u64::MAX, None, 10).unwrap();
+        assert_eq!(positions(found), vec![(3, 0), (5, 0)]);
+        assert!(
+            transfers_between(&mut conn, 1, 301, 400, None, 10)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            transfers_between(&mut conn, 2, 0, u64::MAX, None, 10)
+                .unwrap()
+                .is_empty()
+        );
+
+        // Paging resumes after the last position returned
+        let page = transfers_between(&mut conn, 1, 0, 1000, None, 2).unwrap();
+        assert_eq!(positions(page), vec![(1, 0), (2, 0)]);
+        let page = transfers_between(&mut conn, 1, 0, 1000, Some((2, 0)), 2).unwrap();
+        assert_eq!(positions(page), vec![(2, 1), (3, 0)]);
+    }
+
+    #[test]
+    fn transfers_between_uses_the_timestamp_index() {
+        #[derive(QueryableByName)]
+        struct Plan {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            detail: String,
+        }
+        let mut conn = crate::testing::in_memory_db();
+        let plan: Vec<String> = diesel::sql_query(
+            "EXPLAIN QUERY PLAN SELECT * FROM transfers \
+             WHERE chain_id = 1 AND block_timestamp >= 0 AND block_timestamp < 10",
+        )
+        .load::<Plan>(&mut conn)
+        .unwrap()
+        .into_iter()
+        .map(|row| row.detail)
+        .collect();
+        assert!(
+            plan.iter()
+                .any(|detail| detail.contains("idx_transfers_timestamp")),
+            "{:?}",
+            plan
+        );
+    }
+
+    #[test]
+    fn token_tables_of_older_versions_get_the_new_columns() {
+        let mut conn = crate::testing::in_memory_db();
+        let token = Address::repeat_byte(0xaa);
+        diesel::sql_query(format!(
+            "CREATE TABLE {} (chain_id INTEGER NOT NULL, block_number INTEGER NOT NULL, \
+             tx_hash CHAR(66) NOT NULL, token_address CHAR(42) NOT NULL, \
+             from_addr CHAR(42) NOT NULL, to_addr CHAR(42) NOT NULL, value TEXT NOT NULL, \
+             log_index INTEGER NOT NULL, base_fee INTEGER, \
+             PRIMARY KEY (chain_id, tx_hash, log_index))",
+            token_table_name(token)
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+        let write = WriteOptions {
+            tables: TransferTables::PerToken,
+            ..WriteOptions::default()
+        };
+        let changes = [TransferChange::Added(transfer(1, 0))];
+        apply_transfer_changes(&mut conn, &changes, &write).unwrap();
+        let stored = table_transfers(&mut conn, &token_table_name(token));
+        assert_eq!(stored[0].block_timestamp, Some(1_700_000_000));
+        assert_eq!(stored[0].gas_used, Some(21_000));
+    }
+
+    #[test]
+    fn updated_at_advances_with_the_pointer() {
+        let mut conn = crate::testing::in_memory_db();
+        let now = || {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+        };
+        assert_eq!(get_sync_updated_at(&mut conn, 1).unwrap(), None);
+
+        let before = now();
+        seed_sync_pointer(&mut conn, 1, 10).unwrap();
+        let seeded = get_sync_updated_at(&mut conn, 1).unwrap().unwrap();
+        assert!((before..=now()).contains(&seeded));
+
+        // Backdate it, as if the pointer last moved long ago
+        let backdate = |conn: &mut SqliteConnection, value: Option<i64>| {
+            diesel::update(schema::sync::table)
+                .set(schema::sync::updated_at.eq(value))
+                .execute(conn)
+                .unwrap();
+        };
+        backdate(&mut conn, Some(1_000));
+        assert_eq!(get_sync_updated_at(&mut conn, 1).unwrap(), Some(1_000));
+        set_last_synced_block(&mut conn, 1, 20).unwrap();
+        let advanced = get_sync_updated_at(&mut conn, 1).unwrap().unwrap();
+        assert!(advanced >= before, "{} < {}", advanced, before);
+
+        // A row written before the column existed has no time until the pointer moves
+        backdate(&mut conn, None);
+        assert_eq!(get_sync_updated_at(&mut conn, 1).unwrap(), None);
+        set_last_synced_block(&mut conn, 1, 21).unwrap();
+        assert!(get_sync_updated_at(&mut conn, 1).unwrap().is_some());
+    }
+
+    #[test]
+    fn u64_columns_past_i64_max_are_rejected() {
+        let huge = i64::MAX as u64 + 1;
+        assert_eq!(
+            u64_to_storage(i64::MAX as u64, "Gas used").unwrap(),
+            i64::MAX
+        );
+        assert_overflow(block_to_storage(huge));
+        assert_overflow(chain_to_storage(i32::MAX as u64 + 1));
+
+        let mut absurd = transfer(1, 0);
+        absurd.log_index = huge;
+        assert_overflow(NewTransfer::try_from(&absurd));
+        let mut absurd = transfer(1, 0);
+        absurd.base_fee = Some(u64::MAX);
+        assert_overflow(NewTransfer::try_from(&absurd));
+        let mut absurd = transfer(1, 0);
+        absurd.block_timestamp = Some(u64::MAX);
+        assert_overflow(NewTransfer::try_from(&absurd));
+        let mut absurd = transfer(1, 0);
+        absurd.gas_used = Some(huge);
+        assert_overflow(NewTransfer::try_from(&absurd));
+        let absurd = transfer(u64::MAX, 0);
+        assert_overflow(NewTransfer::try_from(&absurd));
+
+        let mut conn = crate::testing::in_memory_db();
+        assert_overflow(insert_transfers(&mut conn, &[absurd]));
+    }
+
+    #[test]
+    fn negative_stored_values_fail_to_read() {
+        let mut conn = crate::testing::in_memory_db();
+        let stored = transfer(3, 1);
+        insert_transfers(&mut conn, std::slice::from_ref(&stored)).unwrap();
+        let read = transfers_in_range(&mut conn, 1, 0, 10).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].log_index, 1);
+        assert_eq!(read[0].gas_used, Some(21_000));
+
+        // A row written by something else with a wrapped value
+        diesel::sql_query("UPDATE transfers SET gas_used = -1")
+            .execute(&mut conn)
+            .unwrap();
+        assert_overflow(transfers_in_range(&mut conn, 1, 0, 10));
+    }
+
+    #[test]
+    fn compact_reclaims_deleted_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("compact.db").display().to_string();
+        let mut conn = open_db(&path);
+        let transfers: Vec<TransferEvent> = (0..2000).map(|block| transfer(block, 0)).collect();
+        insert_transfers(&mut conn, &transfers).unwrap();
+        let before = std::fs::metadata(&path).unwrap().len();
+
+        diesel::sql_query("DELETE FROM transfers")
+            .execute(&mut conn)
+            .unwrap();
+        compact(&mut conn).unwrap();
+        let after = std::fs::metadata(&path).unwrap().len();
+        assert!(after < before, "{} -> {}", before, after);
+        assert!(
+            transfers_in_range(&mut conn, 1, 0, u64::MAX)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn value_histogram_buckets_values_by_decades() {
+        let mut conn = crate::testing::in_memory_db();
+        let token = Address::repeat_byte(0xaa);
+        let values = [0, 5, 99, 12_345, 100_000_000, 100_000_000_000];
+        let mut transfers: Vec<TransferEvent> = values
+            .iter()
+            .enumerate()
+            .map(|(index, value)| moved(1, index as u64, 1, 2, *value))
+            .collect();
+        // Another token's transfer isn't counted
+        transfers.push(TransferEvent {
+            token_address: Address::repeat_byte(0xbb),
+            ..moved(2, 0, 1, 2, 7)
+        });
+        insert_transfers(&mut conn, &transfers).unwrap();
+
+        let power = |exponent: u64| U256::from(10).pow(U256::from(exponent));
+        let bucket = |low: U256, high: u64, count| {
+            (
+                BucketRange {
+                    low,
+                    high: Some(power(high)),
+                },
+                count,
+            )
+        };
+        // 12 digits in 4 buckets of 3 decades
+        assert_eq!(
+            value_histogram(&mut conn, 1, token, 4).unwrap(),
+            vec![
+                bucket(U256::ZERO, 3, 3),
+                bucket(power(3), 6, 1),
+                bucket(power(6), 9, 1),
+                bucket(power(9), 12, 1),
+            ]
+        );
+        // Fewer buckets than decades with values, and one per decade at most
+        assert_eq!(
+            value_histogram(&mut conn, 1, token, 1).unwrap(),
+            vec![bucket(U256::ZERO, 12, 6)]
+        );
+        assert_eq!(value_histogram(&mut conn, 1, token, 100).unwrap().len(), 12);
+        assert!(value_histogram(&mut conn, 1, token, 0).unwrap().is_empty());
+        assert!(
+            value_histogram(&mut conn, 1, Address::repeat_byte(0xcc), 4)
+                .unwrap()
+                .is_empty()
+        );
+
+        // The bucket of the largest uint256 has no upper bound (10^78 doesn't fit)
+        insert_transfers(
+            &mut conn,
+            &[TransferEvent {
+                value: U256::MAX,
+                ..moved(3, 0, 1, 2, 0)
+            }],
+        )
+        .unwrap();
+        let histogram = value_histogram(&mut conn, 1, token, 2).unwrap();
+        assert_eq!(histogram[0], bucket(U256::ZERO, 39, 6));
+        assert_eq!(
+            histogram[1],
+            (
+                BucketRange {
+                    low: power(39),
+                    high: None
+                },
+                1
+            )
+        );
+    }
+
+    #[test]
+    fn first_seen_block_is_the_earliest_side_of_any_transfer() {
+        let mut conn = crate::testing::in_memory_db();
+        insert_transfers(
+            &mut conn,
+            &[
+                moved(40, 0, 1, 3, 5),
+                moved(60, 0, 3, 4, 5),
+                moved(25, 1, 3, 2, 5),
+                moved(30, 0, 1, 2, 5),
+                // Another chain's earlier transfer doesn't count
+                TransferEvent {
+                    chain_id: 2,
+                    ..moved(5, 0, 3, 4, 5)
+                },
+            ],
+        )
+        .unwrap();
+
+        let first_seen = |conn: &mut SqliteConnection, account: u8| {
+            first_seen_block(conn, 1, Address::repeat_byte(account)).unwrap()
+        };
+        // Account 3 first sent (block 25), before it first received (block 40)
+        assert_eq!(first_seen(&mut conn, 3), Some(25));
+        assert_eq!(first_seen(&mut conn, 4), Some(60));
+        assert_eq!(first_seen(&mut conn, 1), Some(30));
+        assert_eq!(first_seen(&mut conn, 9), None);
+        assert_eq!(
+            first_seen_block(&mut conn, 2, Address::repeat_byte(3)).unwrap(),
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn partitions_hold_their_blocks_and_the_view_unions_them() {
+        let mut conn = crate::testing::in_memory_db();
+        let write = WriteOptions {
+            tables: TransferTables::Partitioned { blocks: 100 },
+            ..WriteOptions::default()
+        };
+        let changes: Vec<TransferChange> = [(5, 0), (150, 0), (199, 1), (250, 0)]
+            .into_iter()
+            .map(|(block, log_index)| TransferChange::Added(transfer(block, log_index)))
+            .collect();
+        apply_transfer_changes(&mut conn, &changes, &write).unwrap();
+
+        assert_eq!(
+            partition_tables(&mut conn).unwrap(),
+            vec![
+                "transfers_blocks_0",
+                "transfers_blocks_100",
+                "transfers_blocks_200",
+            ]
+        );
+        let blocks = |conn: &mut SqliteConnection, table: &str| -> Vec<u64> {
+            table_transfers(conn, table)
+                .iter()
+                .map(|transfer| transfer.block_number)
+                .collect()
+        };
+        assert_eq!(blocks(&mut conn, "transfers_blocks_0"), vec![5]);
+        assert_eq!(blocks(&mut conn, "transfers_blocks_100"), vec![150, 199]);
+        assert_eq!(blocks(&mut conn, "transfers_blocks_200"), vec![250]);
+        assert_eq!(blocks(&mut conn, PARTITIONS_VIEW), vec![5, 150, 199, 250]);
+        assert!(table_transfers(&mut conn, "transfers").is_empty());
+        // Every column goes through the view
+        let all = table_transfers(&mut conn, PARTITIONS_VIEW);
+        assert!(all.iter().all(|transfer| transfer.gas_used == Some(21_000)
+            && transfer.tx_status == Some(true)
+            && transfer.block_timestamp == Some(1_700_000_000)));
+
+        // Same indexes as `transfers`
+        let indexes: Vec<String> = diesel::sql_query(
+            "SELECT name FROM sqlite_master \
+             WHERE type = 'index' AND tbl_name = 'transfers_blocks_100' AND sql IS NOT NULL",
+        )
+        .load::<TableName>(&mut conn)
+        .unwrap()
+        .into_iter()
+        .map(|index| index.name)
+        .collect();
+        for (suffix, _) in TRANSFER_INDEXES {
+            let name = format!("idx_transfers_blocks_100_{}", suffix);
+            assert!(indexes.contains(&name), "{:?}", indexes);
+        }
+
+        // A reorged transfer leaves its partition
+        let removed = [TransferChange::Removed(transfer(150, 0))];
+        apply_transfer_changes(&mut conn, &removed, &write).unwrap();
+        assert_eq!(blocks(&mut conn, PARTITIONS_VIEW), vec![5, 199, 250]);
+    }
+
+    #[test]
+    fn partitions_of_older_versions_are_upgraded_with_the_view() {
+        let mut conn = crate::testing::in_memory_db();
+        // Partitions and view as created before the enrichment columns existed
+        for table in ["transfers_blocks_0", "transfers_blocks_100"] {
+            diesel::sql_query(format!(
+                "CREATE TABLE {} (chain_id INTEGER NOT NULL, block_number INTEGER NOT NULL, \
+                 tx_hash CHAR(66) NOT NULL, token_address CHAR(42) NOT NULL, \
+                 from_addr CHAR(42) NOT NULL, to_addr CHAR(42) NOT NULL, value TEXT NOT NULL, \
+                 log_index INTEGER NOT NULL, base_fee INTEGER, \
+                 PRIMARY KEY (chain_id, tx_hash, log_index))",
+                table
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        diesel::sql_query(format!(
+            "CREATE VIEW {} AS SELECT * FROM transfers_blocks_0 \
+             UNION ALL SELECT * FROM transfers_blocks_100",
+            PARTITIONS_VIEW
+        ))
+        .execute(&mut conn)
+        .unwrap();
+
+        let write = WriteOptions {
+            tables: TransferTables::Partitioned { blocks: 100 },
+            ..WriteOptions::default()
+        };
+        let changes = [TransferChange::Added(transfer(5, 0))];
+        apply_transfer_changes(&mut conn, &changes, &write).unwrap();
+
+        let all = table_transfers(&mut conn, PARTITIONS_VIEW);
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].gas_used, Some(21_000));
+    }
+
+    #[test]
+    fn ledger_replays_the_running_balance_of_an_address() {
+        let mut conn = crate::testing::in_memory_db();
+        let token = Address::repeat_byte(0xaa);
+        insert_transfers(
+            &mut conn,
+            &[
+                moved(12, 0, 3, 1, 40), // Inserted out of order
+                moved(10, 0, 0, 1, 100),
+                moved(11, 3, 1, 2, 30),
+                moved(11, 1, 5, 3, 99), // Not account 1's
+                moved(12, 2, 1, 1, 50),
+                TransferEvent {
+                    token_address: Address::repeat_byte(0xbb),
+                    ..moved(11, 0, 0, 1, 1_000)
+                },
+            ],
+        )
+        .unwrap();
+
+        let entries: Vec<(u64, u64, BalanceDelta, U256)> =
+            ledger(&mut conn, 1, Address::repeat_byte(1), token)
+                .unwrap()
+                .into_iter()
+                .map(|entry| {
+                    (
+                        entry.block_number,
+                        entry.log_index,
+                        entry.delta,
+                        entry.balance,
+                    )
+                })
+                .collect();
+        let credit = |value: u64| BalanceDelta::Credit(U256::from(value));
+        assert_eq!(
+            entries,
+            vec![
+                (10, 0, credit(100), U256::from(100)),
+                (11, 3, BalanceDelta::Debit(U256::from(30)), U256::from(70)),
+                (12, 0, credit(40), U256::from(110)),
+                // Sent to itself: no change
+                (12, 2, credit(0), U256::from(110)),
+            ]
+        );
+        assert!(
+            ledger(&mut conn, 1, Address::ZERO, token)
+                .unwrap()
+                .is_empty()
+        );
+
+        // Account 2 sends more than its history received
+        insert_transfers(&mut conn, &[moved(13, 0, 2, 4, 31)]).unwrap();
+        assert!(matches!(
+            ledger(&mut conn, 1, Address::repeat_byte(2), token),
+            Err(IndexerError::BalanceUnderflow { .. })
+        ));
+    }
+
+    #[test]
+    fn block_coverage_reports_min_and_max_per_chain() {
+        let mut conn = crate::testing::in_memory_db();
+        insert_transfers(
+            &mut conn,
+            &[
+                transfer(42, 0),
+                transfer(7, 1),
+                transfer(15, 0),
+                TransferEvent {
+                    chain_id: 5,
+                    ..transfer(100, 0)
+                },
+            ],
+        )
+        .unwrap();
+        set_last_synced_block(&mut conn, 1, 50).unwrap();
+        // A chain with a pointer but nothing stored yet
+        seed_sync_pointer(&mut conn, 9, 0).unwrap();
+
+        assert_eq!(
+            block_coverage(&mut conn).unwrap(),
+            vec![
+                BlockCoverage {
+                    chain_id: 1,
+                    min_block: Some(7),
+                    max_block: Some(42),
+                    synced_block: Some(50),
+                },
+                BlockCoverage {
+                    chain_id: 5,
+                    min_block: Some(100),
+                    max_block: Some(100),
+                    synced_block: None,
+                },
+                BlockCoverage {
+                    chain_id: 9,
+                    min_block: None,
+                    max_block: None,
+                    synced_block: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn slow_queries_are_logged() {
+        let buffer = crate::testing::LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let mut conn = crate::testing::in_memory_db();
+        conn.set_instrumentation(SlowQueryLog::new(Duration::from_millis(20)));
+
+        tracing::subscriber::with_default(subscriber, || {
+            diesel::sql_query("SELECT 1").execute(&mut conn).unwrap();
+            // Artificially slow: counts to a million through a recursive CTE
+            diesel::sql_query(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 1000000) \
+                 SELECT count(*) FROM n",
+            )
+            .execute(&mut conn)
+            .unwrap();
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let slow: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("Slow query"))
+            .collect();
+        assert_eq!(slow.len(), 1, "{}", output);
+        assert!(slow[0].contains("WITH RECURSIVE"), "{}", slow[0]);
+    }
+
+    #[test]
+    fn velocity_counts_transfers_per_bucket_with_gaps_filled() {
+        let mut conn = crate::testing::in_memory_db();
+        let token = Address::repeat_byte(0xaa);
+        insert_transfers(
+            &mut conn,
+            &[
+                transfer(23, 0),
+                transfer(21, 0),
+                transfer(29, 1),
+                transfer(30, 0),
+                transfer(65, 0),
+                // Another token and another chain are left out
+                TransferEvent {
+                    token_address: Address::repeat_byte(0xbb),
+                    ..transfer(45, 0)
+                },
+                TransferEvent {
+                    chain_id: 5,
+                    ..transfer(45, 0)
+                },
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            velocity(&mut conn, 1, token, 10).unwrap(),
+            vec![(20, 3), (30, 1), (40, 0), (50, 0), (60, 1)]
+        );
+        assert_eq!(velocity(&mut conn, 1, token, 100).unwrap(), vec![(0, 5)]);
+        assert_eq!(velocity(&mut conn, 1, token, 0).unwrap(), Vec::new());
+        assert_eq!(velocity(&mut conn, 2, token, 10).unwrap(), Vec::new());
+    }
+}
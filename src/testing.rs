@@ -0,0 +1,593 @@
+use crate::indexer::{
+    AlloyProvider, IndexerError, LogQuery, LogsProvider, ProviderCapabilities, Result, RpcMethods,
+    build_headers, decode_transfer, transfer_topic,
+};
+use crate::types::{BlockInfo, ReceiptInfo, TransferEvent};
+use alloy::primitives::{Address, B256, Bytes, LogData, U256, keccak256};
+use alloy::rpc::types::eth::Log;
+use alloy::transports::http::reqwest::Url;
+use diesel::{Connection, SqliteConnection};
+use diesel_migrations::MigrationHarness;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// Chain id of the generated transfers
+pub const CHAIN_ID: u64 = 1;
+// Token emitting every generated log
+pub const TOKEN: Address = Address::repeat_byte(0xaa);
+// Accounts the senders and receivers are drawn from, so balances and stats see repeat addresses
+const ACCOUNTS: usize = 32;
+// Most logs generated in a single block
+const MAX_LOGS_PER_BLOCK: u64 = 10;
+
+// SplitMix64: tiny, fast and fully determined by its seed, which is all test data needs
+// (no extra dependency, and no change of output when a rand crate is upgraded)
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_word(&mut self) -> B256 {
+        let mut word = [0u8; 32];
+        for chunk in word.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_be_bytes());
+        }
+        B256::from(word)
+    }
+
+    fn next_address(&mut self) -> Address {
+        Address::from_word(self.next_word())
+    }
+}
+
+// Generate `count` synthetic Transfer logs of TOKEN and the transfers they decode to, as pairs
+// The same seed always yields the same data: blocks start at 1 and hold 1 to 10 logs, one per
+// transaction, between ACCOUNTS addresses, with values up to about 10^24 (some of them zero).
+// Available with the `test-util` feature, for tests and benchmarks.
+pub fn gen_transfers(seed: u64, count: usize) -> Vec<(Log, TransferEvent)> {
+    let mut rng = SplitMix64(seed);
+    let topic = transfer_topic().expect("the Transfer signature is a valid topic");
+    let accounts: Vec<Address> = (0..ACCOUNTS).map(|_| rng.next_address()).collect();
+
+    let mut generated = Vec::with_capacity(count);
+    let mut block_number = 1;
+    let mut block_hash = rng.next_word();
+    let mut logs_in_block = 1 + rng.next_u64() % MAX_LOGS_PER_BLOCK;
+    let mut log_index = 0;
+    while generated.len() < count {
+        if log_index == logs_in_block {
+            block_number += 1;
+            block_hash = rng.next_word();
+            logs_in_block = 1 + rng.next_u64() % MAX_LOGS_PER_BLOCK;
+            log_index = 0;
+        }
+
+        let from = accounts[rng.next_u64() as usize % ACCOUNTS];
+        let to = accounts[rng.next_u64() as usize % ACCOUNTS];
+        // 1 in 16 is zero-value, like the spam of popular tokens
+        let value = match rng.next_u64() % 16 {
+            0 => U256::ZERO,
+            _ => U256::from(rng.next_u64()) * U256::from(rng.next_u64() % 100_000_000),
+        };
+        let log = Log {
+            inner: alloy::primitives::Log {
+                address: TOKEN,
+                data: LogData::new_unchecked(
+                    vec![topic, from.into_word(), to.into_word()],
+                    Bytes::from(value.to_be_bytes::<32>().to_vec()),
+                ),
+            },
+            block_hash: Some(block_hash),
+            block_number: Some(block_number),
+            block_timestamp: None,
+            transaction_hash: Some(rng.next_word()),
+            transaction_index: Some(log_index),
+            log_index: Some(log_index),
+            removed: false,
+        };
+        let transfer = decode_transfer(CHAIN_ID, &log).expect("generated logs are valid transfers");
+        generated.push((log, transfer));
+        log_index += 1;
+    }
+
+    generated
+}
+
+// Collects the formatted output of a test subscriber
+#[derive(Clone, Default)]
+pub struct LogBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+impl Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Held by tests that read (Config::load) or change the process environment, which is shared by
+// the tests running in parallel
+pub fn env_lock() -> MutexGuard<'static, ()> {
+    static ENV: Mutex<()> = Mutex::new(());
+    ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Database at `path` (`:memory:` for a private in-memory one) with the schema applied
+pub fn open_db(path: &str) -> SqliteConnection {
+    let mut conn = SqliteConnection::establish(path).expect("the test database opens");
+    conn.run_pending_migrations(crate::MIGRATIONS)
+        .expect("the migrations apply");
+    conn
+}
+
+// Fresh in-memory database with the schema applied
+pub fn in_memory_db() -> SqliteConnection {
+    open_db(":memory:")
+}
+
+// Transfer log of TOKEN at a position, in transaction `keccak256(block, log_index)`
+pub fn transfer_log(
+    block_number: u64,
+    log_index: u64,
+    from: Address,
+    to: Address,
+    value: U256,
+) -> Log {
+    let topic = transfer_topic().expect("the Transfer signature is a valid topic");
+    Log {
+        inner: alloy::primitives::Log {
+            address: TOKEN,
+            data: LogData::new_unchecked(
+                vec![topic, from.into_word(), to.into_word()],
+                Bytes::from(value.to_be_bytes::<32>().to_vec()),
+            ),
+        },
+        block_hash: Some(block_hash(block_number)),
+        block_number: Some(block_number),
+        block_timestamp: None,
+        transaction_hash: Some(tx_hash(block_number, log_index)),
+        transaction_index: Some(log_index),
+        log_index: Some(log_index),
+        removed: false,
+    }
+}
+
+// Hash of a block of FakeProvider, unless set in `blocks`
+pub fn block_hash(block_number: u64) -> B256 {
+    keccak256(block_number.to_be_bytes())
+}
+
+// Hash of the transaction of `transfer_log(block_number, log_index, ..)`
+pub fn tx_hash(block_number: u64, log_index: u64) -> B256 {
+    keccak256([block_number.to_be_bytes(), log_index.to_be_bytes()].concat())
+}
+
+// LogsProvider answering from memory, recording the calls it gets
+// Logs are served by block number (and by hash for `block_hash_logs`); a block missing from
+// `blocks` gets a derived header (block_hash, the parent's hash, 12s blocks, base fee = number).
+#[derive(Debug, Default)]
+pub struct FakeProvider {
+    pub head: u64,
+    pub head_step: u64, // Added to the head after every latest_block call, like a live chain
+    pub chain_id: u64,
+    pub logs: Vec<Log>,
+    pub blocks: HashMap<u64, BlockInfo>,
+    pub receipts: HashMap<B256, ReceiptInfo>,
+    pub calls: HashMap<Bytes, Bytes>, // eth_call output by calldata; other calls revert
+    pub code: Bytes,
+    pub capabilities: ProviderCapabilities,
+    pub oldest_block: u64,        // Older logs are refused, like a pruned node
+    pub failing: Vec<(u64, u64)>, // Log ranges that always fail
+    pub delay: Duration,          // Every log request first sleeps this long, like a hung RPC
+    pub requests: Mutex<Vec<(u64, u64)>>, // Every log range requested, in order
+    pub receipt_requests: Mutex<Vec<B256>>,
+    pub block_requests: Mutex<Vec<u64>>,
+}
+
+impl FakeProvider {
+    // Provider of CHAIN_ID at `head` serving `logs`
+    pub fn new(head: u64, logs: Vec<Log>) -> Self {
+        FakeProvider {
+            head,
+            chain_id: CHAIN_ID,
+            logs,
+            code: Bytes::from_static(&[0x60, 0x80]),
+            ..FakeProvider::default()
+        }
+    }
+
+    // Log ranges requested so far
+    pub fn requested(&self) -> Vec<(u64, u64)> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn logs_between(&self, start_block: u64, end_block: u64) -> Result<Vec<Log>> {
+        self.requests.lock().unwrap().push((start_block, end_block));
+        std::thread::sleep(self.delay);
+        if start_block < self.oldest_block {
+            return Err(IndexerError::Rpc(format!(
+                "Block {} is older than the oldest retained block {}",
+                start_block, self.oldest_block
+            )));
+        }
+        if self
+            .failing
+            .iter()
+            .any(|(from, to)| start_block <= *to && *from <= end_block)
+        {
+            return Err(IndexerError::Rpc(format!(
+                "Blocks {}..={} failed",
+                start_block, end_block
+            )));
+        }
+        Ok(self
+            .logs
+            .iter()
+            .filter(|log| {
+                log.block_number
+                    .is_none_or(|block| (start_block..=end_block).contains(&block))
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+impl LogsProvider for FakeProvider {
+    fn latest_block(&mut self) -> Result<u64> {
+        let head = self.head;
+        self.head += self.head_step;
+        Ok(head)
+    }
+
+    fn chain_id(&mut self) -> Result<u64> {
+        Ok(self.chain_id)
+    }
+
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<impl IntoIterator<Item = Log>> {
+        let transfer = transfer_topic()?;
+        Ok(self
+            .logs_between(start_block, end_block)?
+            .into_iter()
+            .filter(move |log| log.topics().first() == Some(&transfer)))
+    }
+
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>> {
+        queries
+            .iter()
+            .map(|query| {
+                Ok(self
+                    .logs(query.from_block, query.to_block)?
+                    .into_iter()
+                    .filter(|log| log.address() == query.address)
+                    .collect())
+            })
+            .collect()
+    }
+
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo> {
+        self.block_requests.lock().unwrap().push(block_number);
+        Ok(self
+            .blocks
+            .get(&block_number)
+            .copied()
+            .unwrap_or_else(|| BlockInfo {
+                number: block_number,
+                hash: block_hash(block_number),
+                parent_hash: block_hash(block_number.saturating_sub(1)),
+                timestamp: block_number * 12,
+                base_fee: Some(block_number),
+            }))
+    }
+
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo> {
+        self.receipt_requests.lock().unwrap().push(tx_hash);
+        self.receipts
+            .get(&tx_hash)
+            .copied()
+            .ok_or_else(|| IndexerError::Rpc(format!("No receipt for {:#x}", tx_hash)))
+    }
+
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        self.calls
+            .get(&data)
+            .cloned()
+            .ok_or_else(|| IndexerError::RpcResponse(format!("Call to {:#x} reverted", to)))
+    }
+
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>> {
+        Ok(self
+            .logs_between(start_block, end_block)?
+            .into_iter()
+            .filter(|log| {
+                log.topics()
+                    .first()
+                    .is_some_and(|topic| selectors.contains(topic))
+            })
+            .collect())
+    }
+
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>> {
+        Ok(self
+            .logs
+            .iter()
+            .filter(|log| log.block_number == Some(block_number))
+            .cloned()
+            .collect())
+    }
+
+    fn block_hash_logs(&self, hash: B256) -> Result<Vec<Log>> {
+        let transfer = transfer_topic()?;
+        Ok(self
+            .logs
+            .iter()
+            .filter(|log| log.block_hash == Some(hash) && log.topics().first() == Some(&transfer))
+            .cloned()
+            .collect())
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.capabilities
+    }
+
+    fn code(&self, _address: Address) -> Result<Bytes> {
+        Ok(self.code.clone())
+    }
+}
+
+// Answer of RpcServer to one JSON-RPC call: its result, or how the call fails
+pub type RpcReply = std::result::Result<serde_json::Value, RpcFailure>;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RpcFailure {
+    Status(u16),              // HTTP status failing the whole request
+    Error(i64, &'static str), // JSON-RPC error object (code and message) answering the call
+}
+
+// JSON-RPC endpoint over plain HTTP on localhost, answering each call with `handler`
+// Every HTTP request is recorded (headers and JSON body), so tests can count round trips and
+// inspect the calls. A batch is a single request; its calls are answered in order.
+pub struct RpcServer {
+    pub url: Url,
+    requests: Arc<Mutex<Vec<RpcRequest>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcRequest {
+    pub headers: Vec<(String, String)>, // Lowercased names
+    pub body: serde_json::Value,
+}
+
+type RpcHandler = dyn Fn(&str, &serde_json::Value) -> RpcReply + Send + Sync;
+
+impl RpcServer {
+    pub fn start(
+        handler: impl Fn(&str, &serde_json::Value) -> RpcReply + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("a local port is available");
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<RpcHandler> = Arc::new(handler);
+        let recorded = requests.clone();
+        // The listener lives as long as the test process; each connection gets its own thread
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (handler, recorded) = (handler.clone(), recorded.clone());
+                std::thread::spawn(move || serve_connection(stream, &*handler, &recorded));
+            }
+        });
+        RpcServer { url, requests }
+    }
+
+    // HTTP requests received so far
+    pub fn requests(&self) -> Vec<RpcRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // Methods called so far, batches flattened, in order
+    pub fn methods(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .flat_map(|request| match &request.body {
+                serde_json::Value::Array(calls) => calls.clone(),
+                call => vec![call.clone()],
+            })
+            .filter_map(|call| call["method"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    // AlloyProvider of TOKEN pointed at this server, with the default settings
+    pub fn provider(&self) -> AlloyProvider {
+        AlloyProvider {
+            url: self.url.clone(),
+            token_address: TOKEN,
+            emitters: Vec::new(),
+            headers: build_headers("rust-indexer-test", None, &[]).unwrap(),
+            topic_filter: true,
+            methods: RpcMethods::default(),
+            max_block_range: None,
+            compression: false,
+        }
+    }
+}
+
+// Serve the keep-alive HTTP requests of one connection until the client closes it
+fn serve_connection(stream: TcpStream, handler: &RpcHandler, recorded: &Mutex<Vec<RpcRequest>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut line = String::new();
+        // Request line; EOF or a broken connection ends the loop
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let Some((name, value)) = line.trim_end().split_once(':') else {
+                break;
+            };
+            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+        }
+        let length = headers
+            .iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        recorded.lock().unwrap().push(RpcRequest {
+            headers,
+            body: body.clone(),
+        });
+
+        let answer = |call: &serde_json::Value| {
+            let method = call["method"].as_str().unwrap_or_default();
+            match handler(method, &call["params"]) {
+                Ok(result) => {
+                    Ok(serde_json::json!({"jsonrpc": "2.0", "id": call["id"], "result": result}))
+                }
+                Err(RpcFailure::Error(code, message)) => Ok(serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": call["id"],
+                    "error": {"code": code, "message": message},
+                })),
+                Err(RpcFailure::Status(status)) => Err(status),
+            }
+        };
+        let reply = match &body {
+            serde_json::Value::Array(calls) => calls
+                .iter()
+                .map(answer)
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map(serde_json::Value::Array),
+            call => answer(call),
+        };
+        let (status, body) = match reply {
+            Ok(reply) => (200, reply.to_string()),
+            Err(status) => (status, String::new()),
+        };
+        let response = format!(
+            "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        if writer.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+// Lets a test run the loop with a borrowed FakeProvider and inspect its requests afterwards
+impl LogsProvider for &FakeProvider {
+    fn latest_block(&mut self) -> Result<u64> {
+        Ok(self.head)
+    }
+
+    fn chain_id(&mut self) -> Result<u64> {
+        Ok(self.chain_id)
+    }
+
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<impl IntoIterator<Item = Log>> {
+        (**self).logs(start_block, end_block)
+    }
+
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>> {
+        (**self).batch_logs(queries)
+    }
+
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo> {
+        (**self).block_info(block_number)
+    }
+
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo> {
+        (**self).transaction_receipt(tx_hash)
+    }
+
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        (**self).eth_call(to, data)
+    }
+
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>> {
+        (**self).event_logs(start_block, end_block, selectors)
+    }
+
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>> {
+        (**self).block_receipt_logs(block_number)
+    }
+
+    fn block_hash_logs(&self, hash: B256) -> Result<Vec<Log>> {
+        (**self).block_hash_logs(hash)
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.capabilities
+    }
+
+    fn code(&self, address: Address) -> Result<Bytes> {
+        (**self).code(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_yields_identical_data() {
+        let logs = |generated: Vec<(Log, TransferEvent)>| -> Vec<Log> {
+            generated.into_iter().map(|(log, _)| log).collect()
+        };
+        let rows = |generated: Vec<(Log, TransferEvent)>| -> Vec<Vec<u8>> {
+            generated
+                .iter()
+                .map(|(_, transfer)| transfer.canonical_bytes())
+                .collect()
+        };
+
+        assert_eq!(logs(gen_transfers(42, 200)), logs(gen_transfers(42, 200)));
+        assert_eq!(rows(gen_transfers(42, 200)), rows(gen_transfers(42, 200)));
+        assert_ne!(logs(gen_transfers(42, 200)), logs(gen_transfers(43, 200)));
+        // A shorter run is a prefix of a longer one
+        assert_eq!(
+            logs(gen_transfers(42, 50)),
+            logs(gen_transfers(42, 200))[..50].to_vec()
+        );
+    }
+
+    #[test]
+    fn generated_logs_are_in_chain_order() {
+        let generated = gen_transfers(7, 500);
+        assert_eq!(generated.len(), 500);
+        assert_eq!(generated[0].1.block_number, 1);
+        for pair in generated.windows(2) {
+            let (previous, next) = (&pair[0].1, &pair[1].1);
+            if next.block_number == previous.block_number {
+                assert_eq!(next.log_index, previous.log_index + 1);
+            } else {
+                assert_eq!(next.block_number, previous.block_number + 1);
+                assert_eq!(next.log_index, 0);
+            }
+        }
+    }
+}
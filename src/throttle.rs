@@ -0,0 +1,241 @@
+use crate::indexer::{IndexerError, LogQuery, LogsProvider, ProviderCapabilities, Result};
+use crate::types::{BlockInfo, ReceiptInfo};
+use alloy::primitives::{Address, B256, Bytes};
+use alloy::rpc::types::eth::Log;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// Requests/sec added back after every successful request
+const RATE_INCREASE: f64 = 0.5;
+// Factor applied to the rate after a rate-limit response
+const RATE_DECREASE: f64 = 0.5;
+
+#[derive(Debug)]
+struct ThrottleState {
+    rate: f64,          // Current requests/sec
+    next_slot: Instant, // Earliest time the next request may be sent
+}
+
+// AIMD request pacing: the rate grows additively while the provider accepts requests and is
+// halved whenever it answers with a rate-limit error, staying within [min_rps, max_rps].
+// Shared between clones so every caller backs off together.
+#[derive(Debug, Clone)]
+pub struct AdaptiveThrottle {
+    min_rps: f64,
+    max_rps: f64,
+    state: Arc<Mutex<ThrottleState>>,
+}
+
+impl AdaptiveThrottle {
+    // Start at max_rps (nothing is known about the quota yet)
+    pub fn new(min_rps: f64, max_rps: f64) -> Self {
+        let min_rps = min_rps.max(f64::MIN_POSITIVE);
+        let max_rps = max_rps.max(min_rps);
+        AdaptiveThrottle {
+            min_rps,
+            max_rps,
+            state: Arc::new(Mutex::new(ThrottleState {
+                rate: max_rps,
+                next_slot: Instant::now(),
+            })),
+        }
+    }
+
+    // Current allowed requests/sec
+    pub fn rate(&self) -> f64 {
+        self.lock().rate
+    }
+
+    // Block until the next request may be sent and reserve its slot
+    pub fn wait(&self) {
+        let slot = {
+            let mut state = self.lock();
+            let slot = state.next_slot.max(Instant::now());
+            state.next_slot = slot + Duration::from_secs_f64(1.0 / state.rate);
+            slot
+        };
+        std::thread::sleep(slot.saturating_duration_since(Instant::now()));
+    }
+
+    // Additive increase after an accepted request
+    pub fn on_success(&self) {
+        let mut state = self.lock();
+        state.rate = (state.rate + RATE_INCREASE).min(self.max_rps);
+    }
+
+    // Multiplicative decrease after a rate-limit response, also pushing back the next slot
+    pub fn on_rate_limited(&self) {
+        let mut state = self.lock();
+        state.rate = (state.rate * RATE_DECREASE).max(self.min_rps);
+        state.next_slot = Instant::now() + Duration::from_secs_f64(1.0 / state.rate);
+        warn!(
+            "RPC rate limited, throttling to {:.2} requests/sec",
+            state.rate
+        );
+    }
+
+    // Pace a request and adjust the rate from its outcome
+    pub fn call<T>(&self, request: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.wait();
+        let result = request();
+        match &result {
+            Err(IndexerError::RateLimited(_)) => self.on_rate_limited(),
+            // Other errors say nothing about the quota
+            Err(_) => {}
+            Ok(_) => self.on_success(),
+        }
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// LogsProvider decorator sending every RPC call through an AdaptiveThrottle
+// Without a throttle the calls are passed through unchanged
+pub struct Throttled<P> {
+    pub inner: P,
+    pub throttle: Option<AdaptiveThrottle>,
+}
+
+impl<P: LogsProvider> Throttled<P> {
+    fn call<T>(&self, request: impl FnOnce() -> Result<T>) -> Result<T> {
+        match &self.throttle {
+            Some(throttle) => throttle.call(request),
+            None => request(),
+        }
+    }
+}
+
+impl<P: LogsProvider> LogsProvider for Throttled<P> {
+    fn latest_block(&mut self) -> Result<u64> {
+        match &self.throttle {
+            Some(throttle) => throttle.call(|| self.inner.latest_block()),
+            None => self.inner.latest_block(),
+        }
+    }
+
+    fn chain_id(&mut self) -> Result<u64> {
+        match &self.throttle {
+            Some(throttle) => throttle.call(|| self.inner.chain_id()),
+            None => self.inner.chain_id(),
+        }
+    }
+
+    fn logs(&self, start_block: u64, end_block: u64) -> Result<impl IntoIterator<Item = Log>> {
+        self.call(|| self.inner.logs(start_block, end_block))
+    }
+
+    fn batch_logs(&self, queries: &[LogQuery]) -> Result<Vec<Vec<Log>>> {
+        // A batch is a single HTTP request, so it takes a single slot
+        self.call(|| self.inner.batch_logs(queries))
+    }
+
+    fn block_info(&self, block_number: u64) -> Result<BlockInfo> {
+        self.call(|| self.inner.block_info(block_number))
+    }
+
+    fn transaction_receipt(&self, tx_hash: B256) -> Result<ReceiptInfo> {
+        self.call(|| self.inner.transaction_receipt(tx_hash))
+    }
+
+    fn transaction_receipts(&self, tx_hashes: &[B256]) -> Result<Vec<ReceiptInfo>> {
+        // Batched like batch_logs: a single request, a single slot
+        self.call(|| self.inner.transaction_receipts(tx_hashes))
+    }
+
+    fn eth_call(&self, to: Address, data: Bytes) -> Result<Bytes> {
+        self.call(|| self.inner.eth_call(to, data))
+    }
+
+    fn event_logs(&self, start_block: u64, end_block: u64, selectors: &[B256]) -> Result<Vec<Log>> {
+        self.call(|| self.inner.event_logs(start_block, end_block, selectors))
+    }
+
+    fn block_receipt_logs(&self, block_number: u64) -> Result<Vec<Log>> {
+        self.call(|| self.inner.block_receipt_logs(block_number))
+    }
+
+    fn block_hash_logs(&self, block_hash: B256) -> Result<Vec<Log>> {
+        self.call(|| self.inner.block_hash_logs(block_hash))
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn code(&self, address: Address) -> Result<Bytes> {
+        self.call(|| self.inner.code(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn rate_halves_on_rate_limits_and_recovers_additively() {
+        let throttle = AdaptiveThrottle::new(2.0, 10.0);
+        assert_eq!(throttle.rate(), 10.0);
+
+        throttle.on_rate_limited();
+        assert_eq!(throttle.rate(), 5.0);
+        for _ in 0..3 {
+            throttle.on_rate_limited();
+        }
+        assert_eq!(throttle.rate(), 2.0);
+
+        throttle.on_success();
+        assert_eq!(throttle.rate(), 2.5);
+        for _ in 0..100 {
+            throttle.on_success();
+        }
+        assert_eq!(throttle.rate(), 10.0);
+    }
+
+    #[test]
+    fn other_errors_leave_the_rate_alone() {
+        let throttle = AdaptiveThrottle::new(1.0, 1000.0);
+        let _ = throttle.call(|| Err::<(), _>(IndexerError::Rpc("boom".to_string())));
+        assert_eq!(throttle.rate(), 1000.0);
+        let _ = throttle.call(|| Err::<(), _>(IndexerError::RateLimited("429".to_string())));
+        assert_eq!(throttle.rate(), 500.0);
+    }
+
+    #[test]
+    fn requests_slow_down_below_the_quota_of_the_provider() {
+        // Answers HTTP 429 past 5 requests in 100ms, i.e. 50 requests/sec
+        let sent = Mutex::new(VecDeque::new());
+        let server = crate::testing::RpcServer::start(move |_, _| {
+            let mut sent = sent.lock().unwrap();
+            let now = Instant::now();
+            sent.retain(|at| now.duration_since(*at) < Duration::from_millis(100));
+            if sent.len() >= 5 {
+                return Err(crate::testing::RpcFailure::Status(429));
+            }
+            sent.push_back(now);
+            Ok(serde_json::json!([]))
+        });
+        let throttle = AdaptiveThrottle::new(1.0, 400.0);
+        let provider = Throttled {
+            inner: server.provider(),
+            throttle: Some(throttle.clone()),
+        };
+
+        let outcomes: Vec<bool> = (0..40)
+            .map(|block| match provider.logs(block, block) {
+                Ok(_) => true,
+                Err(IndexerError::RateLimited(_)) => false,
+                Err(e) => panic!("unexpected error: {}", e),
+            })
+            .collect();
+
+        assert!(outcomes.contains(&false), "the quota was never hit");
+        assert!(throttle.rate() < 50.0, "rate {}", throttle.rate());
+        // Once throttled, the requests fit the quota
+        assert!(outcomes[30..].iter().all(|ok| *ok), "{:?}", outcomes);
+    }
+}
@@ -10,4 +10,179 @@ pub struct TransferEvent {
     pub to_addr: Address,
     pub value: U256,
     pub log_index: u64,
+    // Block fields, only set when the matching enrichment is enabled
+    pub base_fee: Option<u64>,        // Base fee (wei) of the block
+    pub block_timestamp: Option<u64>, // Unix timestamp of the block
+    // Receipt fields of the transaction, only set with ENRICH_RECEIPTS
+    pub gas_used: Option<u64>,   // Gas used by the whole transaction
+    pub tx_status: Option<bool>, // Whether the transaction succeeded
+}
+
+impl TransferEvent {
+    // Stable fixed-width binary encoding of a transfer, used for checksums
+    // Independent of how the row is formatted in the database. Optional enrichment (base_fee,
+    // block_timestamp, receipt fields) is left out so instances with and without it produce the
+    // same checksum
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + 8 + 32 + 8 + 20 * 3 + 32);
+        bytes.extend_from_slice(&self.chain_id.to_be_bytes());
+        bytes.extend_from_slice(&self.block_number.to_be_bytes());
+        bytes.extend_from_slice(self.tx_hash.as_slice());
+        bytes.extend_from_slice(&self.log_index.to_be_bytes());
+        bytes.extend_from_slice(self.token_address.as_slice());
+        bytes.extend_from_slice(self.from_addr.as_slice());
+        bytes.extend_from_slice(self.to_addr.as_slice());
+        bytes.extend_from_slice(&self.value.to_be_bytes::<32>());
+        bytes
+    }
+}
+
+// How addresses are written in outputs: EIP-55 checksummed (mixed case) for people, or
+// lowercase, the stored form, for machines. Parsing accepts both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFormat {
+    #[default]
+    Lowercase,
+    Checksummed,
+}
+
+impl AddressFormat {
+    pub fn render(self, address: Address) -> String {
+        match self {
+            AddressFormat::Lowercase => format!("{:#x}", address),
+            AddressFormat::Checksummed => address.to_checksum(None),
+        }
+    }
+}
+
+impl TransferEvent {
+    // One line per transfer: `block tx_hash#log_index token from -> to value`
+    pub fn line(&self, addresses: AddressFormat) -> String {
+        format!(
+            "{} {:#x}#{} {} {} -> {} {}",
+            self.block_number,
+            self.tx_hash,
+            self.log_index,
+            addresses.render(self.token_address),
+            addresses.render(self.from_addr),
+            addresses.render(self.to_addr),
+            self.value
+        )
+    }
+}
+
+// TransferEvent::line with lowercase addresses
+impl std::fmt::Display for TransferEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.line(AddressFormat::Lowercase))
+    }
+}
+
+// Header fields of a block used to enrich transfers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub number: u64,
+    pub hash: B256,
+    pub parent_hash: B256,
+    pub timestamp: u64,
+    pub base_fee: Option<u64>, // None before London (EIP-1559)
+}
+
+// Receipt fields of a transaction used to enrich transfers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiptInfo {
+    pub tx_hash: B256,
+    pub gas_used: u64,
+    pub status: bool, // false if the transaction reverted
+}
+
+// Placeholder for a metadata getter that reverted or returned nothing usable
+pub const UNKNOWN_TOKEN_TEXT: &str = "UNKNOWN";
+
+// ERC20 metadata of the indexed token, detected once at startup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMetadata {
+    pub symbol: String,
+    pub name: String,
+    pub decimals: Option<u8>, // None if decimals() reverted or returned something unusable
+}
+
+// What a fetched log means for the `transfers` table
+#[derive(Debug, Clone)]
+pub enum TransferChange {
+    // A new transfer to store
+    Added(TransferEvent),
+    // The log was reverted by a reorg (`removed: true`), its row has to be deleted
+    Removed(TransferEvent),
+}
+
+impl TransferChange {
+    pub fn event(&self) -> &TransferEvent {
+        match self {
+            TransferChange::Added(event) | TransferChange::Removed(event) => event,
+        }
+    }
+}
+
+// A custom event log decoded with its EVENTS_FILE signature (see events.rs)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventLog {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub tx_hash: B256,
+    pub log_index: u64,
+    pub event_name: String,
+    pub json_args: String, // Decoded parameters as a JSON object, in declaration order
+}
+
+// One line per event: `block tx_hash#log_index name json_args`
+impl std::fmt::Display for EventLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:#x}#{} {} {}",
+            self.block_number, self.tx_hash, self.log_index, self.event_name, self.json_args
+        )
+    }
+}
+
+// What a fetched custom event log means for the `events` table (same semantics as TransferChange)
+#[derive(Debug, Clone)]
+pub enum EventChange {
+    Added(EventLog),
+    Removed(EventLog),
+}
+
+impl EventChange {
+    pub fn event(&self) -> &EventLog {
+        match self {
+            EventChange::Added(event) | EventChange::Removed(event) => event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_render_checksummed_or_lowercase() {
+        // The EIP-55 test vector
+        let address: Address = "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            AddressFormat::Checksummed.render(address),
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+        assert_eq!(
+            AddressFormat::Lowercase.render(address),
+            "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"
+        );
+
+        // Both parse back to the same address
+        for format in [AddressFormat::Checksummed, AddressFormat::Lowercase] {
+            assert_eq!(format.render(address).parse::<Address>().unwrap(), address);
+        }
+    }
 }
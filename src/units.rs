@@ -0,0 +1,112 @@
+use alloy_primitives::U256;
+
+// How token amounts are shown to people (`tail`, `export --display-values`): the raw integer
+// scaled by the token's decimals, with VALUE_PRECISION fractional digits and, with
+// VALUE_TRIM_ZEROS, without trailing zeros. Stored values are always the raw integer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueFormat {
+    pub decimals: u8,
+    pub precision: usize,
+    pub trim_zeros: bool,
+}
+
+impl ValueFormat {
+    pub fn format(&self, value: U256) -> String {
+        let formatted = format_units(value, self.decimals, self.precision);
+        if self.trim_zeros {
+            trim_fraction(formatted)
+        } else {
+            formatted
+        }
+    }
+}
+
+// Render a raw token amount with `decimals` decimals and `precision` fractional digits, e.g.
+// 1234500 with 6 decimals and precision 2 is "1.23". Extra digits are truncated rather than
+// rounded, so an amount is never shown larger than it is. A non-zero amount below the smallest
+// shown unit is "<0.01" (or "<1" with precision 0) rather than a misleading "0.00".
+pub fn format_units(value: U256, decimals: u8, precision: usize) -> String {
+    let decimals = decimals as usize;
+    // At least one integer digit, so "5" with 3 decimals is "0.005"
+    let digits = format!("{:0>width$}", value.to_string(), width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction: String = fraction
+        .chars()
+        .chain(std::iter::repeat('0'))
+        .take(precision)
+        .collect();
+
+    let shown_zero = integer == "0" && fraction.bytes().all(|digit| digit == b'0');
+    if shown_zero && !value.is_zero() {
+        return match precision {
+            0 => "<1".to_string(),
+            _ => format!("<0.{}1", "0".repeat(precision - 1)),
+        };
+    }
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+// Drop the trailing zeros of the fractional part, and the point if nothing is left
+// ("1.500" -> "1.5", "2.000" -> "2"); "<0.0001" is left alone
+fn trim_fraction(formatted: String) -> String {
+    if formatted.starts_with('<') || !formatted.contains('.') {
+        return formatted;
+    }
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_units_across_magnitudes() {
+        let units =
+            |value: u128, decimals, precision| format_units(U256::from(value), decimals, precision);
+        assert_eq!(units(0, 18, 4), "0.0000");
+        assert_eq!(units(1_234_500, 6, 2), "1.23");
+        // Truncated, never rounded up
+        assert_eq!(units(1_999_999, 6, 2), "1.99");
+        assert_eq!(units(5, 3, 3), "0.005");
+        assert_eq!(units(5, 3, 5), "0.00500");
+        assert_eq!(units(42, 0, 2), "42.00");
+        assert_eq!(units(1_500_000, 6, 0), "1");
+        assert_eq!(units(123_456_789 * 10u128.pow(18), 18, 1), "123456789.0");
+        assert_eq!(
+            format_units(U256::MAX, 18, 0),
+            "115792089237316195423570985008687907853269984665640564039457"
+        );
+    }
+
+    #[test]
+    fn amounts_below_the_smallest_shown_unit_are_marked() {
+        assert_eq!(format_units(U256::from(1), 18, 4), "<0.0001");
+        assert_eq!(format_units(U256::from(99), 4, 1), "<0.1");
+        assert_eq!(format_units(U256::from(999), 6, 0), "<1");
+        assert_eq!(format_units(U256::from(100), 4, 2), "0.01");
+    }
+
+    #[test]
+    fn trimming_drops_trailing_zeros_only() {
+        let format = |value: u64, precision| {
+            ValueFormat {
+                decimals: 3,
+                precision,
+                trim_zeros: true,
+            }
+            .format(U256::from(value))
+        };
+        assert_eq!(format(1_500, 3), "1.5");
+        assert_eq!(format(2_000, 3), "2");
+        assert_eq!(format(10_000, 2), "10");
+        assert_eq!(format(1, 2), "<0.01");
+        assert_eq!(format(0, 2), "0");
+    }
+}